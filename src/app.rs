@@ -0,0 +1,520 @@
+// State of the TUI that outlives a frame: which view is shown and what is selected in it.
+//
+// Every frame rebuilds the rows from the tree, so the selection is kept as a row index
+// that is clamped to however many rows the new frame has. The tree view also records
+// which entry each row showed, so keys acting on the selection know what it is.
+//
+// Mouse clicks are matched to rows through where the list was drawn in the last frame
+// and how far it was scrolled, and to the cells of the treemap through where each was
+// drawn.
+//
+// Like in ncdu, the tree view lists the entries of one directory, at first the root,
+// and Enter moves into the selected directory. Directories are listed collapsed unless
+// they were expanded, so only the entries directly inside it are listed at first.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use ratatui::layout::Rect;
+use ratatui::widgets::ListState;
+
+use crate::treemap::{self, Direction};
+
+// Longest time between two clicks on a row for them to open it
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+// How long the status line tells what a key did
+const STATUS_SHOWN: Duration = Duration::from_secs(5);
+
+// What the main list of the TUI shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen {
+    Tree,
+    // Totals per file extension over the whole scan
+    Extensions,
+    // Groups of identical files (--find-duplicates)
+    Duplicates,
+    // Totals per file owner, from which a user's own files can be shown as a tree
+    Owners,
+    // The largest files anywhere in the tree, from which one can be shown in the tree
+    Largest,
+    // The entries of the directory listed as rectangles sized by how much they take
+    Treemap,
+}
+
+// The selected row of a list, with the scroll offset ratatui keeps alongside it.
+pub struct Selection {
+    pub state: ListState,
+    // Rows in the list as of the last frame
+    len: usize,
+}
+
+impl Selection {
+    pub fn new() -> Selection {
+        let mut state = ListState::default();
+        state.select(Some(0));
+        Selection { state, len: 0 }
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.state.selected()
+    }
+
+    // Takes in the number of rows of a new frame, keeping the selection on one of them.
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len;
+        match self.state.selected() {
+            _ if len == 0 => self.state.select(None),
+            Some(row) if row >= len => self.state.select(Some(len - 1)),
+            None => self.state.select(Some(0)),
+            Some(_) => {}
+        }
+    }
+
+    // Scrolls the list so that the selected row is among the `height` rows shown. The
+    // rows shown stay put while the selection moves within them, and the list is not
+    // scrolled past its last row, so that a shorter list or a taller terminal fills the
+    // rows shown again.
+    pub fn scroll(&mut self, height: usize) {
+        let mut offset = self.state.offset().min(self.len.saturating_sub(height));
+        if let Some(row) = self.state.selected() {
+            if row < offset {
+                offset = row;
+            } else if height > 0 && row >= offset + height {
+                offset = row + 1 - height;
+            }
+        }
+        *self.state.offset_mut() = offset;
+    }
+
+    // Moves the selection by `delta` rows, stopping at the first and last row.
+    pub fn move_by(&mut self, delta: isize) {
+        if self.len == 0 {
+            return;
+        }
+        let row = self.state.selected().unwrap_or(0).saturating_add_signed(delta);
+        self.state.select(Some(row.min(self.len - 1)));
+    }
+
+    // Moves the selection by `delta` rows and scrolls the list as far, so that the
+    // selected row keeps its place on the screen until the list ends; `scroll` then
+    // clamps the offset to the rows there are.
+    pub fn page_by(&mut self, delta: isize) {
+        self.move_by(delta);
+        let offset = self.state.offset().saturating_add_signed(delta);
+        *self.state.offset_mut() = offset;
+    }
+
+    pub fn select_first(&mut self) {
+        self.move_by(isize::MIN);
+    }
+
+    pub fn select_last(&mut self) {
+        self.move_by(isize::MAX);
+    }
+}
+
+// What a cell of the treemap showed in the last frame.
+pub struct Cell {
+    pub area: Rect,
+    // Path below the root of the entry
+    pub path: Vec<String>,
+    // Whether it is a directory with entries to show
+    pub expandable: bool,
+}
+
+// What a row of the tree view showed in the last frame.
+pub struct TreeRow {
+    // Path below the root of the entry (the directory itself for an "(N other items)" row)
+    pub path: Vec<String>,
+    // Row of the directory it is listed in
+    pub parent: Option<usize>,
+    // Whether it is a directory with entries to list below it
+    pub expandable: bool,
+    // Whether it stands for several entries, like an "(N other items)" row
+    pub summary: bool,
+}
+
+pub struct App {
+    pub screen: Screen,
+    pub tree: Selection,
+    rows: Vec<TreeRow>,
+    // Entry to select again once the rows are rebuilt in a new order or from a new tree,
+    // by path since its row may have moved
+    reselect: Option<Vec<String>>,
+    // Directory whose entries the tree view lists, by path below the root
+    pub view: Vec<String>,
+    // Directories whose entries are listed below them, by path below the root
+    pub expanded: HashSet<Vec<String>>,
+    // Directories whose children are all listed even while folding is on ('Z')
+    pub unfolded: HashSet<Vec<String>>,
+    pub extensions: Selection,
+    pub duplicates: Selection,
+    pub owners: Selection,
+    // Uid shown on each row of the per-user view
+    pub owner_rows: Vec<u32>,
+    // The user whose files the tree is limited to, picked in the per-user view
+    pub owner_filter: Option<u32>,
+    pub largest: Selection,
+    // Path below the root of the file on each row of the largest-files view
+    pub largest_rows: Vec<Vec<String>>,
+    pub treemap: Selection,
+    cells: Vec<Cell>,
+    // Entries marked to be deleted together ('M'), by path below the root; sorted, so that
+    // the entries inside a marked directory come right after it
+    pub marked: BTreeSet<Vec<String>>,
+    // Whether the list of broken symlinks is open over the main view
+    pub show_broken: bool,
+    // Whether the list of bookmarks is open over the main view ("'"), and the row
+    // selected in it
+    pub show_bookmarks: bool,
+    pub bookmark_list: Selection,
+    // Entries waiting for the deletion to be confirmed ('d'): the selected one, or the
+    // marked ones
+    pub confirm_delete: Option<Vec<Vec<String>>>,
+    // How far the list of marked entries in the deletion dialog is scrolled
+    pub confirm_scroll: usize,
+    // Whether quitting waits for a confirmation, asked for while entries are marked
+    pub confirm_quit: bool,
+    // Shown over everything until a key is pressed, such as why a deletion failed
+    pub message: Option<String>,
+    // Whether the list of keys is open ('?')
+    pub show_help: bool,
+    // Whether the details of the selected entry are shown beside the tree ('i')
+    pub show_details: bool,
+    // What a key did, like a deletion, with when it did it; at the end of the bottom line
+    // for a few seconds
+    status: Option<(String, Instant)>,
+    // The query being typed after '/'
+    pub search: Option<String>,
+    // The filter typed after Ctrl+F, which narrows the tree to the entries whose names
+    // have it in them, and whether it is still being typed
+    pub filter: Option<String>,
+    pub filtering: bool,
+    // Columns of the names in the tree scrolled out of view on the left, to read the ends
+    // of names too long for their rows ('[' and ']')
+    pub pan: usize,
+    // The name of the file being typed after 'E', and a file waiting for its overwriting
+    // to be confirmed
+    pub export: Option<String>,
+    pub confirm_export: Option<PathBuf>,
+    // Where the rows of the list were drawn in the last frame
+    pub list_area: Rect,
+    // Screen, row and time of the last click, to tell a double click
+    last_click: Option<(Screen, usize, Instant)>,
+}
+
+impl App {
+    pub fn new() -> App {
+        App {
+            screen: Screen::Tree,
+            tree: Selection::new(),
+            rows: Vec::new(),
+            reselect: None,
+            view: Vec::new(),
+            expanded: HashSet::new(),
+            unfolded: HashSet::new(),
+            extensions: Selection::new(),
+            duplicates: Selection::new(),
+            owners: Selection::new(),
+            owner_rows: Vec::new(),
+            owner_filter: None,
+            largest: Selection::new(),
+            largest_rows: Vec::new(),
+            treemap: Selection::new(),
+            cells: Vec::new(),
+            marked: BTreeSet::new(),
+            show_broken: false,
+            show_bookmarks: false,
+            bookmark_list: Selection::new(),
+            confirm_delete: None,
+            confirm_scroll: 0,
+            confirm_quit: false,
+            message: None,
+            show_help: false,
+            show_details: false,
+            status: None,
+            search: None,
+            filter: None,
+            filtering: false,
+            pan: 0,
+            export: None,
+            confirm_export: None,
+            list_area: Rect::default(),
+            last_click: None,
+        }
+    }
+
+    // The selection of the view currently shown.
+    pub fn selection(&mut self) -> &mut Selection {
+        match self.screen {
+            Screen::Tree => &mut self.tree,
+            Screen::Extensions => &mut self.extensions,
+            Screen::Duplicates => &mut self.duplicates,
+            Screen::Owners => &mut self.owners,
+            Screen::Largest => &mut self.largest,
+            Screen::Treemap => &mut self.treemap,
+        }
+    }
+
+    // Moves the selection by `halves` half screens of the list, going by how many rows
+    // the last frame had room for, so that the step follows the size of the terminal.
+    pub fn page(&mut self, halves: isize) {
+        let height = self.list_area.height as isize;
+        let delta = halves.signum() * (height * halves.abs() / 2).max(1);
+        self.selection().page_by(delta);
+    }
+
+    // Selects the row drawn at a column and row of the terminal, if there is one. Returns
+    // whether this is the second click on it in a short while, which opens it.
+    pub fn click(&mut self, column: u16, row: u16) -> bool {
+        let area = self.list_area;
+        if column < area.x || column >= area.x + area.width || row < area.y || row >= area.y + area.height {
+            return false;
+        }
+        let screen = self.screen;
+        let index = if screen == Screen::Treemap {
+            let Some(index) = self.cells.iter().position(|cell| {
+                let area = cell.area;
+                column >= area.x && column < area.right() && row >= area.y && row < area.bottom()
+            }) else {
+                return false;
+            };
+            index
+        } else {
+            let selection = self.selection();
+            let index = selection.state.offset() + (row - area.y) as usize;
+            if index >= selection.len {
+                return false;
+            }
+            index
+        };
+        self.selection().state.select(Some(index));
+        let now = Instant::now();
+        let double = matches!(
+            self.last_click,
+            Some((clicked_screen, clicked, at)) if clicked_screen == screen && clicked == index && now - at < DOUBLE_CLICK
+        );
+        // A third click starts over
+        self.last_click = if double { None } else { Some((screen, index, now)) };
+        double
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = Some((status, Instant::now()));
+    }
+
+    pub fn clear_status(&mut self) {
+        self.status = None;
+    }
+
+    // The status to show in this frame, until it fades.
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_ref().filter(|(_, at)| at.elapsed() < STATUS_SHOWN).map(|(status, _)| status.as_str())
+    }
+
+    // Takes in the rows of the tree view of a new frame.
+    pub fn set_rows(&mut self, rows: Vec<TreeRow>) {
+        if let Some(mut path) = self.reselect.take() {
+            let mut found = rows.iter().position(|row| row.path == path);
+            // An entry no longer listed gives way to the closest directory above it that is
+            while found.is_none() && path.pop().is_some() {
+                found = rows.iter().position(|row| row.path == path && !row.summary);
+            }
+            if let Some(row) = found {
+                self.tree.state.select(Some(row));
+            }
+        }
+        self.rows = rows;
+    }
+
+    // Takes in the cells of the treemap of a new frame.
+    pub fn set_cells(&mut self, cells: Vec<Cell>) {
+        if let Some(path) = self.reselect.take()
+            && let Some(index) = cells.iter().position(|cell| path.starts_with(&cell.path))
+        {
+            self.treemap.state.select(Some(index));
+        }
+        self.treemap.set_len(cells.len());
+        self.cells = cells;
+    }
+
+    // Moves the selection of the treemap to the next cell in `direction`, if there is one.
+    pub fn move_in_treemap(&mut self, direction: Direction) {
+        let areas: Vec<Rect> = self.cells.iter().map(|cell| cell.area).collect();
+        if let Some(index) = self.treemap.selected().and_then(|from| treemap::neighbour(&areas, from, direction)) {
+            self.treemap.state.select(Some(index));
+        }
+    }
+
+    fn selected_cell(&self) -> Option<&Cell> {
+        self.treemap.selected().and_then(|index| self.cells.get(index))
+    }
+
+    // Keeps the selected entry (or cell of the treemap) selected in the next frame even if
+    // it moves to another row, unless another one is to be selected already.
+    pub fn keep_selection(&mut self) {
+        if self.reselect.is_none() {
+            self.reselect = match self.screen {
+                Screen::Treemap => self.selected_cell().map(|cell| cell.path.clone()),
+                _ => self.selected_path().cloned(),
+            };
+        }
+    }
+
+    fn selected_row(&self) -> Option<&TreeRow> {
+        self.tree.selected().and_then(|row| self.rows.get(row))
+    }
+
+    // Path below the root of the entry selected in the tree view.
+    pub fn selected_path(&self) -> Option<&Vec<String>> {
+        self.selected_row().map(|row| &row.path)
+    }
+
+    // Path of the selected row if it shows a single entry, which can be deleted: in the
+    // tree view, the largest-files view or the treemap.
+    pub fn selected_entry(&self) -> Option<&Vec<String>> {
+        match self.screen {
+            Screen::Largest => self.largest.selected().and_then(|row| self.largest_rows.get(row)),
+            Screen::Treemap => self.selected_cell().map(|cell| &cell.path),
+            _ => self.selected_row().filter(|row| !row.summary).map(|row| &row.path),
+        }
+    }
+
+    // Marks the selected entry, or unmarks it if it is marked, and moves on to the next row
+    // so that a run of entries is marked by pressing the key again.
+    pub fn toggle_mark(&mut self) {
+        let Some(path) = self.selected_entry().cloned() else {
+            return;
+        };
+        if !self.marked.remove(&path) {
+            self.marked.insert(path);
+        }
+        if self.screen != Screen::Treemap {
+            self.selection().move_by(1);
+        }
+    }
+
+    // The marked entries that are not inside a marked directory, which stand for all of
+    // them: deleting those deletes everything marked, and their sizes add up to what that
+    // frees without counting anything twice.
+    pub fn marked_tops(&self) -> Vec<&Vec<String>> {
+        let mut tops: Vec<&Vec<String>> = Vec::new();
+        for path in &self.marked {
+            if !tops.last().is_some_and(|top| path.starts_with(top)) {
+                tops.push(path);
+            }
+        }
+        tops
+    }
+
+    // Expands the selected directory, or collapses it if it is expanded.
+    pub fn toggle_selected(&mut self) {
+        if let Some(path) = self.selected_directory()
+            && !self.expanded.remove(&path)
+        {
+            self.expanded.insert(path);
+        }
+    }
+
+    pub fn expand_selected(&mut self) {
+        if let Some(path) = self.selected_directory() {
+            self.expanded.insert(path);
+        }
+    }
+
+    // Collapses the selected directory, or selects the directory it is in if there is
+    // nothing to collapse, going up a level from the directory listed.
+    pub fn collapse_selected(&mut self) {
+        if let Some(path) = self.selected_directory()
+            && self.expanded.remove(&path)
+        {
+            return;
+        }
+        match self.selected_row().map(|row| row.parent) {
+            Some(Some(parent)) => self.tree.state.select(Some(parent)),
+            Some(None) => self.go_up(),
+            None => {}
+        }
+    }
+
+    // Makes the entry at `path` visible and selects it in the next frame: the directories
+    // above it are expanded and unfolded, and the root is listed if the entry is not
+    // below the directory listed.
+    pub fn reveal(&mut self, path: Vec<String>) {
+        if !path.starts_with(&self.view) {
+            self.view.clear();
+        }
+        for depth in self.view.len()..path.len() {
+            if depth > self.view.len() {
+                self.expanded.insert(path[..depth].to_vec());
+            }
+            self.unfolded.insert(path[..depth].to_vec());
+        }
+        self.screen = Screen::Tree;
+        self.reselect = Some(path);
+    }
+
+    // Lists the entries of the selected directory instead.
+    pub fn enter_selected(&mut self) {
+        if let Some(path) = self.selected_directory() {
+            self.enter(path);
+        }
+    }
+
+    // Lists the entries of the directory at `path`, which is below the one listed, with
+    // the first one selected.
+    pub fn enter(&mut self, path: Vec<String>) {
+        self.view = path;
+        self.tree.state.select(Some(0));
+        self.treemap.state.select(Some(0));
+    }
+
+    // What Enter does: lists the selected directory of the tree or the treemap, the tree
+    // of the selected user's files, or the tree around the selected file of the largest
+    // ones.
+    pub fn open_selected(&mut self) {
+        match self.screen {
+            Screen::Tree => self.enter_selected(),
+            Screen::Owners => {
+                if let Some(&uid) = self.owners.selected().and_then(|row| self.owner_rows.get(row)) {
+                    self.owner_filter = Some(uid);
+                    self.screen = Screen::Tree;
+                }
+            }
+            Screen::Largest => {
+                if let Some(path) = self.selected_entry().cloned() {
+                    self.reveal(path);
+                }
+            }
+            Screen::Treemap => {
+                if let Some(cell) = self.selected_cell().filter(|cell| cell.expandable) {
+                    let path = cell.path.clone();
+                    self.enter(path);
+                }
+            }
+            Screen::Extensions | Screen::Duplicates => {}
+        }
+    }
+
+    // Lists the directory above the one listed, selecting the directory left; at the
+    // root nothing happens.
+    pub fn go_up(&mut self) {
+        let left = self.view.clone();
+        if self.view.pop().is_none() {
+            return;
+        }
+        self.reselect = Some(left);
+    }
+
+    // Path of the selected row if it is a directory that can be expanded.
+    fn selected_directory(&self) -> Option<Vec<String>> {
+        self.selected_row().filter(|row| row.expandable).map(|row| row.path.clone())
+    }
+
+    // Switches to `screen`, or back to the tree if it is already shown.
+    pub fn toggle(&mut self, screen: Screen) {
+        self.screen = if self.screen == screen { Screen::Tree } else { screen };
+    }
+}
@@ -0,0 +1,55 @@
+// Bookmarks of the TUI ('B'), kept from one session to the next in
+// $XDG_CACHE_HOME/rdu/bookmarks (~/.cache/rdu/bookmarks).
+//
+// The file has the absolute path of one bookmarked entry per line, for every tree ever
+// looked at. A session only shows the bookmarks below the roots it scans, and writes
+// those back in place of the ones it read, leaving the others alone. Entries whose
+// paths have a line break in them cannot be written there, so they stay bookmarked
+// for the session only.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("rdu").join("bookmarks"))
+}
+
+// Every bookmark in the file, in the order they were made. A missing or unreadable file
+// has none.
+fn read_all(path: &Path) -> Vec<PathBuf> {
+    fs::read_to_string(path)
+        .map(|text| text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+// The bookmarks below any of `roots`, which are absolute.
+pub fn load(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let Some(path) = default_path() else {
+        return Vec::new();
+    };
+    read_all(&path).into_iter()
+        .filter(|bookmark| roots.iter().any(|root| bookmark.starts_with(root)))
+        .collect()
+}
+
+// Replaces the bookmarks below `roots` in the file by `bookmarks`.
+pub fn save(roots: &[PathBuf], bookmarks: &[PathBuf]) -> io::Result<()> {
+    let Some(path) = default_path() else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "neither XDG_CACHE_HOME nor HOME is set"));
+    };
+    let mut kept: Vec<PathBuf> = read_all(&path).into_iter()
+        .filter(|bookmark| !roots.iter().any(|root| bookmark.starts_with(root)))
+        .collect();
+    kept.extend(bookmarks.iter().filter(|bookmark| !bookmark.to_string_lossy().contains('\n')).cloned());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text: String = kept.iter().map(|bookmark| format!("{}\n", bookmark.to_string_lossy())).collect();
+    fs::write(&path, text)
+}
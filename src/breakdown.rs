@@ -0,0 +1,122 @@
+// Disk usage totals grouped by something other than the directory tree: by file owner
+// for --by-owner, by file extension for --by-extension and the TUI's extension view.
+//
+// Files are attributed while they are scanned, after hardlink deduplication, so a file
+// with several links counts once. Totals are kept here rather than derived from the
+// finished tree because --max-depth and --min-size drop entries from it.
+
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use crate::{format_amount, format_count, SizeMode, Units};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub apparent_size: u64,
+    pub disk_usage: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, apparent_size: u64, disk_usage: u64) {
+        self.apparent_size += apparent_size;
+        self.disk_usage += disk_usage;
+        self.files += 1;
+    }
+
+    pub fn size(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::DiskUsage => self.disk_usage,
+            SizeMode::Apparent => self.apparent_size,
+            SizeMode::Inodes => self.files,
+        }
+    }
+}
+
+pub struct Breakdown<K> {
+    totals: Mutex<HashMap<K, Usage>>,
+}
+
+impl<K: Hash + Eq + Clone + Ord> Breakdown<K> {
+    pub fn new() -> Breakdown<K> {
+        Breakdown { totals: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn add(&self, key: K, apparent_size: u64, disk_usage: u64) {
+        self.totals.lock().unwrap().entry(key).or_default().add(apparent_size, disk_usage);
+    }
+
+    pub fn clear(&self) {
+        self.totals.lock().unwrap().clear();
+    }
+
+    // Adds totals gathered locally (e.g. for one directory), taking the lock only once.
+    pub fn merge(&self, local: HashMap<K, Usage>) {
+        let mut totals = self.totals.lock().unwrap();
+        for (key, usage) in local {
+            let total = totals.entry(key).or_default();
+            total.apparent_size += usage.apparent_size;
+            total.disk_usage += usage.disk_usage;
+            total.files += usage.files;
+        }
+    }
+
+    // All groups, largest first.
+    pub fn rows(&self, mode: SizeMode) -> Vec<(K, Usage)> {
+        let mut rows: Vec<(K, Usage)> = self.totals.lock().unwrap()
+            .iter()
+            .map(|(key, usage)| (key.clone(), *usage))
+            .collect();
+        rows.sort_by(|a, b| b.1.size(mode).cmp(&a.1.size(mode)).then_with(|| a.0.cmp(&b.0)));
+        rows
+    }
+
+    // Prints one line per group, largest first, labelling each key with `label`. The size
+    // column widens to fit exact byte counts (`units`).
+    pub fn print_report(&self, heading: &str, mode: SizeMode, units: Units, label: impl Fn(&K) -> String) {
+        let column = if mode == SizeMode::Inodes { "INODES" } else { "SIZE" };
+        let rows: Vec<(String, String, String)> = self.rows(mode)
+            .into_iter()
+            .map(|(key, usage)| (label(&key), format_amount(usage.size(mode), mode, units), format_count(usage.files)))
+            .collect();
+        let width = rows.iter().map(|(_, size, _)| size.len()).max().unwrap_or(0).max(12);
+        println!("{:<20} {:>width$} {:>12}", heading, column, "ITEMS");
+        for (label, size, files) in rows {
+            println!("{:<20} {:>width$} {:>12}", label, size, files);
+        }
+    }
+}
+
+// Group names of the extension breakdown for files without an extension and for directories.
+pub const NO_EXTENSION: &str = "(no extension)";
+pub const DIRECTORIES: &str = "(directories)";
+
+// Lowercased final extension of a file name: "Movie.MP4" -> "mp4", "a.tar.gz" -> "gz".
+// Dot files (".bashrc") and names ending in a dot have none.
+pub fn extension_of(name: &str) -> Option<String> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    if stem.is_empty() || extension.is_empty() {
+        return None;
+    }
+    Some(extension.to_lowercase())
+}
+
+// Account names from /etc/passwd, for --by-owner. Directory services (LDAP, NIS) are not
+// consulted, so their users show up as plain uids.
+pub fn user_names() -> HashMap<u32, String> {
+    let Ok(passwd) = fs::read_to_string("/etc/passwd") else {
+        return HashMap::new();
+    };
+    // Reversed so the first entry for a uid wins, as with getpwuid
+    passwd.lines().rev()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,75 @@
+// Copying text to the clipboard ('y'), like the path of an entry to paste into a shell.
+//
+// The text is sent to the terminal in an OSC 52 escape sequence, which has the terminal
+// put it on the clipboard of the machine it runs on, so copying works over SSH without
+// X11 forwarding. Terminals without OSC 52 support ignore the sequence; for those, the
+// text is also handed to the desktop's clipboard tool (wl-copy, xclip, xsel, pbcopy or
+// clip) when there is one, without waiting for it.
+
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+pub fn copy(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    stdout.flush()?;
+    // OSC 52 cannot tell whether it worked, so a missing or failing tool is not an error
+    for tool in tools() {
+        if copy_with(tool, text).is_ok() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Commands that read the text to copy from their input, most likely to work first.
+fn tools() -> Vec<&'static [&'static str]> {
+    let mut tools: Vec<&'static [&'static str]> = Vec::new();
+    if cfg!(target_os = "macos") {
+        tools.push(&["pbcopy"]);
+    } else if cfg!(windows) {
+        tools.push(&["clip"]);
+    } else {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.push(&["wl-copy"]);
+        }
+        if env::var_os("DISPLAY").is_some() {
+            tools.push(&["xclip", "-selection", "clipboard"]);
+            tools.push(&["xsel", "--clipboard", "--input"]);
+        }
+    }
+    tools
+}
+
+fn copy_with(tool: &[&str], text: &str) -> io::Result<()> {
+    let mut child = Command::new(tool[0])
+        .args(&tool[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Closing its input lets the tool take over the clipboard; xclip and xsel then stay
+    // around to serve it, so the child is reaped by a thread of its own
+    let written = child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(text.as_bytes()));
+    thread::spawn(move || child.wait());
+    written
+}
+
+// Standard base64 with padding, as OSC 52 expects.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| group | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
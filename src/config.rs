@@ -0,0 +1,155 @@
+// The config file: $XDG_CONFIG_HOME/rdu/config.toml (~/.config/rdu/config.toml), or the
+// file given with --config. It is read once at startup, and a missing default file is
+// the same as an empty one.
+//
+// The file is a small subset of TOML, enough for settings that are names mapped to
+// strings: `[section]` headers, `name = "value"` or `name = ["value", ...]` on one line,
+// and `#` comments. Strings are in double quotes (with `\"` and `\\` escapes) or in
+// single quotes (taken as they are). So far the only section is [keys], which binds the
+// keys of the TUI's actions (see keys::Keymap):
+//
+//     [keys]
+//     quit = "x"
+//     down = ["j", "ctrl+n"]
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct Config {
+    // Where it was read from, for error messages
+    pub path: PathBuf,
+    // The lines of the [keys] section, in the order of the file
+    pub keys: Vec<Setting>,
+}
+
+// One `name = value` line.
+pub struct Setting {
+    pub name: String,
+    pub values: Vec<String>,
+    // Line number in the file, counting from 1
+    pub line: usize,
+}
+
+// Reads the config file at `path`, or else the default one if there is one.
+pub fn load(path: Option<&Path>) -> Result<Config, String> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config { path: PathBuf::new(), keys: Vec::new() }),
+        },
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => String::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let keys = parse(&text).map_err(|(line, e)| format!("{}:{}: {}", path.display(), line, e))?;
+    Ok(Config { path, keys })
+}
+
+fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("rdu").join("config.toml"))
+}
+
+// The settings of the [keys] section, or the number of the first line that is not
+// understood and why.
+fn parse(text: &str) -> Result<Vec<Setting>, (usize, String)> {
+    let mut keys = Vec::new();
+    let mut section = None;
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let content = strip_comment(raw).trim();
+        if content.is_empty() {
+            continue;
+        }
+        if let Some(name) = content.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            let name = name.trim();
+            if name != "keys" {
+                return Err((line, format!("unknown section [{}]; the only one is [keys]", name)));
+            }
+            section = Some(name.to_string());
+            continue;
+        }
+        let Some((name, value)) = content.split_once('=') else {
+            return Err((line, format!("expected `name = \"value\"`, got '{}'", content)));
+        };
+        if section.is_none() {
+            return Err((line, "settings go in a section, like [keys]".to_string()));
+        }
+        let values = parse_value(value.trim()).map_err(|e| (line, e))?;
+        keys.push(Setting { name: name.trim().to_string(), values, line });
+    }
+    Ok(keys)
+}
+
+// A string or an array of strings.
+fn parse_value(text: &str) -> Result<Vec<String>, String> {
+    let Some(inner) = text.strip_prefix('[') else {
+        let (value, rest) = parse_string(text)?;
+        return if rest.trim().is_empty() { Ok(vec![value]) } else { Err(format!("unexpected '{}' after the value", rest.trim())) };
+    };
+    let mut values = Vec::new();
+    let mut rest = inner.trim_start();
+    loop {
+        if let Some(after) = rest.strip_prefix(']') {
+            if !after.trim().is_empty() {
+                return Err(format!("unexpected '{}' after the array", after.trim()));
+            }
+            return Ok(values);
+        }
+        let (value, after) = parse_string(rest)?;
+        values.push(value);
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.starts_with(']') {
+            return Err("expected ',' or ']' in the array".to_string());
+        }
+    }
+}
+
+// A quoted string at the start of `text`, and what follows it.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, quote @ ('"' | '\''))) => quote,
+        _ => return Err(format!("expected a quoted string, got '{}'", text)),
+    };
+    let mut value = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((value, &text[index + 1..])),
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                _ => return Err("only \\\" and \\\\ can be escaped".to_string()),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("missing closing quote".to_string())
+}
+
+// The line without a `#` comment, leaving a `#` inside quotes alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..index],
+            None => {}
+        }
+    }
+    line
+}
@@ -0,0 +1,212 @@
+// What changed between two snapshots of the same paths (--diff old new): the directories
+// that grew or shrank the most, largest change first, and the entries that are new or
+// gone, largest first.
+//
+// Entries are matched by their path below the root, so a renamed entry shows up as gone
+// under its old name and new under the new one. An entry that changed type (a file that
+// became a directory, say) is gone and new as well, noted with what it was. Sizes are in
+// the metric of the options (--apparent-size, --count-inodes), which every snapshot has.
+// Only the outermost of the new and the gone entries is listed, as directories that grew
+// contain the entries that did, and with --threshold changes smaller than it are left
+// out. The summary rows of entries a scan did not keep (--max-children-per-dir) count in
+// their directory's size but are not entries of their own.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use chrono::{DateTime, Local};
+
+use crate::snapshot::Snapshot;
+use crate::{DirEntry, SizeMode, Units, format_amount, format_count};
+
+// Rows listed in each part of the report; the others are only counted
+const SHOWN: usize = 20;
+
+struct Grown {
+    path: String,
+    old: u64,
+    new: u64,
+}
+
+struct Listed {
+    path: String,
+    size: u64,
+    // For an entry that changed type, what it was (new) or became (gone)
+    other_kind: Option<&'static str>,
+}
+
+#[derive(Default)]
+struct Changes {
+    grown: Vec<Grown>,
+    added: Vec<Listed>,
+    removed: Vec<Listed>,
+}
+
+struct Diff {
+    mode: SizeMode,
+    threshold: u64,
+    changes: Changes,
+}
+
+impl Diff {
+    // Compares the directories `old` and `new`, both at `path`, and everything below them.
+    fn dir(&mut self, old: &DirEntry, new: &DirEntry, path: &mut String) {
+        let (old_size, new_size) = (old.size(self.mode), new.size(self.mode));
+        if old_size != new_size && old_size.abs_diff(new_size) >= self.threshold && !path.is_empty() {
+            self.changes.grown.push(Grown { path: path.clone(), old: old_size, new: new_size });
+        }
+        let mut old_children: HashMap<&str, &DirEntry> = old.children.iter()
+            .filter(|child| child.pruned == 0)
+            .map(|child| (child.name.as_str(), child))
+            .collect();
+        for child in new.children.iter().filter(|child| child.pruned == 0) {
+            let length = path.len();
+            if !path.is_empty() && !path.ends_with('/') {
+                path.push('/');
+            }
+            path.push_str(&child.name);
+            match old_children.remove(child.name.as_str()) {
+                Some(before) if kind(before) != kind(child) => {
+                    self.list(true, child, path, Some(kind(before)));
+                    self.list(false, before, path, Some(kind(child)));
+                }
+                Some(before) if child.is_dir => self.dir(before, child, path),
+                Some(_) => {}
+                None => self.list(true, child, path, None),
+            }
+            path.truncate(length);
+        }
+        // What is left of the old children is gone
+        for child in old_children.into_values() {
+            let length = path.len();
+            if !path.is_empty() && !path.ends_with('/') {
+                path.push('/');
+            }
+            path.push_str(&child.name);
+            self.list(false, child, path, None);
+            path.truncate(length);
+        }
+    }
+
+    fn list(&mut self, added: bool, entry: &DirEntry, path: &str, other_kind: Option<&'static str>) {
+        let size = entry.size(self.mode);
+        if size < self.threshold {
+            return;
+        }
+        let path = if entry.is_dir { dir_path(path) } else { path.to_string() };
+        let listed = Listed { path, size, other_kind };
+        if added { self.changes.added.push(listed) } else { self.changes.removed.push(listed) }
+    }
+}
+
+// A file that became a directory is not the same entry any more, nor is a file that
+// became a symlink.
+fn kind(entry: &DirEntry) -> &'static str {
+    if entry.is_symlink {
+        "symlink"
+    } else if entry.is_dir {
+        "directory"
+    } else if let Some(special) = entry.special {
+        special.label()
+    } else {
+        "file"
+    }
+}
+
+// A directory's path as listed, ending in a slash.
+fn dir_path(path: &str) -> String {
+    if path.ends_with('/') { path.to_string() } else { format!("{}/", path) }
+}
+
+// A change of size with its sign: +1.2 GiB, -300.0 MiB.
+fn signed(old: u64, new: u64, mode: SizeMode, units: Units) -> String {
+    let sign = if new >= old { '+' } else { '-' };
+    format!("{}{}", sign, format_amount(old.abs_diff(new), mode, units))
+}
+
+fn scanned(snapshot: &Snapshot) -> String {
+    let roots: Vec<String> = snapshot.roots.iter().map(|root| root.display().to_string()).collect();
+    let time: DateTime<Local> = snapshot.scanned_at.into();
+    format!("{} ({})", roots.join(", "), time.format("%Y-%m-%d %H:%M"))
+}
+
+// Prints what changed from `old` to `new`, leaving out changes smaller than `threshold`.
+pub fn print(old: &Snapshot, new: &Snapshot, mode: SizeMode, units: Units, threshold: u64) -> io::Result<()> {
+    if old.roots != new.roots {
+        eprintln!(
+            "Warning: the snapshots are not of the same paths; entries are compared by their path below the root"
+        );
+    }
+    let mut diff = Diff { mode, threshold, changes: Changes::default() };
+    // With a single root the paths start with it, with several with the root each is in
+    let mut path = if new.roots.len() == 1 { new.roots[0].display().to_string() } else { String::new() };
+    if kind(&old.tree) == kind(&new.tree) && old.tree.is_dir {
+        diff.dir(&old.tree, &new.tree, &mut path);
+    } else {
+        diff.list(true, &new.tree, &path, Some(kind(&old.tree)));
+        diff.list(false, &old.tree, &path, Some(kind(&new.tree)));
+    }
+    let Changes { mut grown, mut added, mut removed } = diff.changes;
+    grown.sort_by(|a, b| b.old.abs_diff(b.new).cmp(&a.old.abs_diff(a.new)).then_with(|| a.path.cmp(&b.path)));
+    for listed in [&mut added, &mut removed] {
+        listed.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    }
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let (old_total, new_total) = (old.tree.size(mode), new.tree.size(mode));
+    writeln!(out, "From {}", scanned(old))?;
+    writeln!(out, "  to {}", scanned(new))?;
+    writeln!(
+        out,
+        "Total: {} -> {} ({})",
+        format_amount(old_total, mode, units),
+        format_amount(new_total, mode, units),
+        signed(old_total, new_total, mode, units),
+    )?;
+
+    let rows: Vec<(String, String, String)> = grown.iter()
+        .map(|grown| (signed(grown.old, grown.new, mode, units), format_amount(grown.new, mode, units), dir_path(&grown.path)))
+        .collect();
+    write_part(&mut out, "CHANGE", "DIRECTORY", &rows, "directories")?;
+    for (heading, listed, other) in [("NEW", &added, "was"), ("GONE", &removed, "now")] {
+        let rows: Vec<(String, String, String)> = listed.iter()
+            .map(|listed| {
+                let sign = if heading == "NEW" { '+' } else { '-' };
+                let path = match listed.other_kind {
+                    Some(kind) => format!("{} ({} a {})", listed.path, other, kind),
+                    None => listed.path.clone(),
+                };
+                (format!("{}{}", sign, format_amount(listed.size, mode, units)), String::new(), path)
+            })
+            .collect();
+        write_part(&mut out, heading, "ENTRY", &rows, "entries")?;
+    }
+    out.flush()
+}
+
+// Writes a part of the report: a heading and up to SHOWN rows of a change, the size now
+// (for directories) and a path, then how many more there are.
+fn write_part(out: &mut impl Write, change: &str, what: &str, rows: &[(String, String, String)], plural: &str) -> io::Result<()> {
+    writeln!(out)?;
+    if rows.is_empty() {
+        return writeln!(out, "{}: no {}", change, plural);
+    }
+    let shown = &rows[..rows.len().min(SHOWN)];
+    let change_width = shown.iter().map(|row| row.0.len()).chain([change.len()]).max().unwrap_or(0);
+    let size_width = shown.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    if size_width > 0 {
+        writeln!(out, "{:>change_width$}  {:>size_width$}  {}", change, "NOW", what, size_width = size_width.max(3))?;
+        for (change, size, path) in shown {
+            writeln!(out, "{:>change_width$}  {:>size_width$}  {}", change, size, path, size_width = size_width.max(3))?;
+        }
+    } else {
+        writeln!(out, "{:>change_width$}  {}", change, what)?;
+        for (change, _, path) in shown {
+            writeln!(out, "{:>change_width$}  {}", change, path)?;
+        }
+    }
+    if rows.len() > SHOWN {
+        writeln!(out, "{:>change_width$}  ({} more {})", "...", format_count((rows.len() - SHOWN) as u64), plural)?;
+    }
+    Ok(())
+}
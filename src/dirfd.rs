@@ -0,0 +1,59 @@
+// Directories opened relative to their parent, so the scan never needs their full path.
+//
+// Opening `a/b/.../z` by its path makes the kernel resolve every component again, and fails
+// outright once the path is longer than PATH_MAX (4096 bytes on Linux) even though the
+// directory itself is perfectly readable. On Linux each directory is opened with openat on
+// the file descriptor of its parent instead, and its entries are read back through
+// /proc/self/fd, so the length of the path stops mattering. Elsewhere (or without /proc)
+// directories are opened and read by their path.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+pub struct Dir {
+    #[cfg(target_os = "linux")]
+    fd: OwnedFd,
+}
+
+impl Dir {
+    // Opens the directory at `path`; with a `parent` only its last component is looked up,
+    // in that directory.
+    #[cfg(target_os = "linux")]
+    pub fn open(parent: Option<&Dir>, path: &Path) -> io::Result<Dir> {
+        use std::os::unix::ffi::OsStrExt;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let (at, relative) = match (parent, path.file_name()) {
+            (Some(parent), Some(name)) => (parent.fd.as_raw_fd(), name),
+            _ => (libc::AT_FDCWD, path.as_os_str()),
+        };
+        let relative = std::ffi::CString::new(relative.as_bytes())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        // SAFETY: `relative` is NUL-terminated and `at` is open for as long as `parent` is
+        let fd = unsafe { libc::openat(at, relative.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openat just returned `fd`, which nothing else owns
+        Ok(Dir { fd: unsafe { OwnedFd::from_raw_fd(fd) } })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn open(_parent: Option<&Dir>, _path: &Path) -> io::Result<Dir> {
+        Ok(Dir {})
+    }
+
+    // The entries of the directory, which is at `path`.
+    #[cfg(target_os = "linux")]
+    pub fn read(&self, path: &Path) -> io::Result<fs::ReadDir> {
+        fs::read_dir(format!("/proc/self/fd/{}", self.fd.as_raw_fd())).or_else(|_| fs::read_dir(path))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn read(&self, path: &Path) -> io::Result<fs::ReadDir> {
+        fs::read_dir(path)
+    }
+}
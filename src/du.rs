@@ -0,0 +1,179 @@
+// The scan written to stdout the way du writes it (--du), for scripts that read du's
+// output: a line per directory, the size, a tab and the path, each directory after
+// everything in it. -a adds the other entries, -s keeps only the roots, and -h writes the
+// sizes like `du -h` does instead of in 1024-byte blocks, or --si like `du --si` does, in
+// powers of 1000. --total ends with the size of everything, like `du -c`.
+//
+// Paths start with the root as it was given, with trailing slashes cut down to one, as du
+// does, so that `rdu --du .` writes `./src` where `du .` does. Sizes are rounded up to
+// the next block or unit, as du rounds them. The entries of a directory come in the
+// order it listed them in, like du's. Hard links already counted elsewhere are left out
+// like du leaves them out, and so are the summary rows of --min-size.
+
+use std::io::{self, Write};
+
+use crate::{DirEntry, SizeMode};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    // -h: 4.0K, 12M, ... instead of a number of blocks
+    pub human: bool,
+    // --si: 4.1k, 13M, ..., in powers of 1000
+    pub si: bool,
+    // -a: a line for every entry, not just the directories
+    pub all: bool,
+    // -s: a line for each root only
+    pub summarize: bool,
+    // --total: a last line with the total of all the roots
+    pub total: bool,
+}
+
+const BLOCK: u64 = 1024;
+
+struct Du<W: Write> {
+    out: W,
+    mode: SizeMode,
+    options: Options,
+}
+
+impl<W: Write> Du<W> {
+    // Writes the lines of everything below `entry`, at `path`, and then its own.
+    fn entry(&mut self, entry: &DirEntry, path: &mut String, root: bool) -> io::Result<()> {
+        if !self.options.summarize {
+            for child in &entry.children {
+                let length = path.len();
+                if !path.ends_with('/') {
+                    path.push('/');
+                }
+                path.push_str(&child.name);
+                self.entry(child, path, false)?;
+                path.truncate(length);
+            }
+        }
+        let size = entry.size(self.mode);
+        let repeated_link = entry.shared.is_some() && size == 0;
+        if entry.pruned > 0 || repeated_link || !(root || entry.is_dir || self.options.all) {
+            return Ok(());
+        }
+        writeln!(self.out, "{}\t{}", self.size_text(size), path)
+    }
+
+    fn size_text(&self, size: u64) -> String {
+        match self.mode {
+            SizeMode::Inodes => size.to_string(),
+            SizeMode::DiskUsage | SizeMode::Apparent if self.options.si => human(size, 1000, "k"),
+            SizeMode::DiskUsage | SizeMode::Apparent if self.options.human => human(size, BLOCK, "K"),
+            SizeMode::DiskUsage | SizeMode::Apparent => size.div_ceil(BLOCK).to_string(),
+        }
+    }
+}
+
+// `bytes` the way `du -h` (`base` 1024, `kilo` "K") or `du --si` (1000, "k") writes it:
+// whole bytes below one kilo, else in the largest unit that leaves at least 1, rounded up,
+// with one decimal below 10.
+fn human(bytes: u64, base: u64, kilo: &'static str) -> String {
+    let units = [kilo, "M", "G", "T", "P", "E"];
+    if bytes < base {
+        return bytes.to_string();
+    }
+    let mut unit = 0;
+    let mut divisor = base as u128;
+    while unit + 1 < units.len() && bytes as u128 >= divisor * base as u128 {
+        divisor *= base as u128;
+        unit += 1;
+    }
+    let tenths = (bytes as u128 * 10).div_ceil(divisor);
+    if tenths < 100 {
+        return format!("{}.{}{}", tenths / 10, tenths % 10, units[unit]);
+    }
+    let whole = (bytes as u128).div_ceil(divisor);
+    // Rounding up can reach the next unit: 1023.5K is written 1.0M
+    if whole >= base as u128 && unit + 1 < units.len() {
+        return format!("1.0{}", units[unit + 1]);
+    }
+    format!("{}{}", whole, units[unit])
+}
+
+// The root's path as du writes it: as given, but with any trailing slashes cut to one.
+fn root_path(name: &str) -> String {
+    let trimmed = name.trim_end_matches('/');
+    if trimmed.len() < name.len() { format!("{}/", trimmed) } else { name.to_string() }
+}
+
+// Writes the lines of `tree`, or with several roots those of each root in turn, to `out`.
+pub fn write(out: impl Write, tree: &DirEntry, multiple_roots: bool, mode: SizeMode, options: Options) -> io::Result<()> {
+    let mut du = Du { out: io::BufWriter::new(out), mode, options };
+    let roots = if multiple_roots { tree.children.iter().collect() } else { vec![tree] };
+    for root in roots {
+        du.entry(root, &mut root_path(&root.name), true)?;
+    }
+    if options.total {
+        let total = du.size_text(tree.size(mode));
+        writeln!(du.out, "{}\ttotal", total)?;
+    }
+    du.out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(tree: &DirEntry, options: Options) -> String {
+        let mut out = Vec::new();
+        write(&mut out, tree, false, SizeMode::DiskUsage, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn file(name: &str, disk_usage: u64) -> DirEntry {
+        DirEntry { name: name.to_string(), disk_usage, ..Default::default() }
+    }
+
+    fn dir(name: &str, children: Vec<DirEntry>) -> DirEntry {
+        let disk_usage = 4096 + children.iter().map(|child| child.disk_usage).sum::<u64>();
+        DirEntry { name: name.to_string(), is_dir: true, disk_usage, children, ..Default::default() }
+    }
+
+    #[test]
+    fn human_sizes_round_up_like_du() {
+        assert_eq!(human(0, 1024, "K"), "0");
+        assert_eq!(human(1023, 1024, "K"), "1023");
+        assert_eq!(human(1024, 1024, "K"), "1.0K");
+        assert_eq!(human(1025, 1024, "K"), "1.1K");
+        // 9.95K is 10K once rounded up, and 1023.5K is 1.0M
+        assert_eq!(human(10189, 1024, "K"), "10K");
+        assert_eq!(human(1_048_064, 1024, "K"), "1.0M");
+        assert_eq!(human(1 << 20, 1024, "K"), "1.0M");
+        assert_eq!(human(u64::MAX, 1024, "K"), "16E");
+        assert_eq!(human(999, 1000, "k"), "999");
+        assert_eq!(human(1001, 1000, "k"), "1.1k");
+    }
+
+    #[test]
+    fn root_paths_keep_at_most_one_trailing_slash() {
+        assert_eq!(root_path("/"), "/");
+        assert_eq!(root_path("dir//"), "dir/");
+        assert_eq!(root_path("."), ".");
+        assert_eq!(root_path("./a"), "./a");
+    }
+
+    #[test]
+    fn directories_come_after_everything_in_them() {
+        let tree = dir("./", vec![
+            dir("a", vec![file("x", 1024), dir("b", vec![])]),
+            file("f", 2048),
+        ]);
+        assert_eq!(lines(&tree, Options::default()), "4\t./a/b\n9\t./a\n15\t./\n");
+        let all = Options { all: true, ..Default::default() };
+        assert_eq!(lines(&tree, all), "1\t./a/x\n4\t./a/b\n9\t./a\n2\t./f\n15\t./\n");
+        let summarize = Options { summarize: true, total: true, ..Default::default() };
+        assert_eq!(lines(&tree, summarize), "15\t./\n15\ttotal\n");
+    }
+
+    #[test]
+    fn links_counted_elsewhere_are_left_out() {
+        let shared = DirEntry { shared: Some((100, 4096)), ..file("link", 0) };
+        let tree = dir("/", vec![file("first", 4096), shared]);
+        let all = Options { all: true, human: true, ..Default::default() };
+        assert_eq!(lines(&tree, all), "4.0K\t/first\n8.0K\t/\n");
+    }
+}
@@ -0,0 +1,177 @@
+// Files with identical contents, for --find-duplicates.
+//
+// Counted files are collected during the scan; a file with several hard links is
+// collected once, since its links share the data instead of duplicating it. Once the scan
+// is done, files are grouped by size, candidates are narrowed down by a hash of their
+// first block, and the remaining ones are confirmed by hashing their whole contents.
+// Hashing runs on the scan's thread pool and stops when the scan is cancelled.
+
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use rayon::prelude::*;
+
+use crate::seen::SeenSet;
+use crate::{file_id, format_count, format_size, FileId, Units};
+
+const SHARDS: usize = 64;
+// Bytes hashed to tell apart files of the same size before reading them completely
+const PREFIX_LEN: u64 = 64 * 1024;
+
+pub struct DuplicateGroup {
+    pub size: u64,
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    // Space that would be freed by keeping a single copy
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+pub struct Duplicates {
+    // Candidate files, in shards picked by worker thread so that recording does not contend
+    files: Vec<Mutex<Vec<(u64, PathBuf)>>>,
+    // With --hardlinks=count-all every link is counted, so links are told apart here
+    links_seen: SeenSet<FileId>,
+    // Set once hashing is done, largest waste first
+    groups: Mutex<Option<Vec<DuplicateGroup>>>,
+}
+
+impl Duplicates {
+    pub fn new() -> Duplicates {
+        Duplicates {
+            files: (0..SHARDS).map(|_| Mutex::new(Vec::new())).collect(),
+            links_seen: SeenSet::new(),
+            groups: Mutex::new(None),
+        }
+    }
+
+    // Adds a counted file. `check_links` is set when other links of the same file may be
+    // counted too. Empty files are all "identical" and are left out.
+    pub fn record(&self, path: &Path, metadata: &fs::Metadata, size: u64, check_links: bool) {
+        if size == 0 {
+            return;
+        }
+        if check_links && file_id(path, metadata).is_ok_and(|id| !self.links_seen.insert(id)) {
+            return;
+        }
+        let shard = rayon::current_thread_index().unwrap_or(0) % SHARDS;
+        self.files[shard].lock().unwrap().push((size, path.to_path_buf()));
+    }
+
+    // Forgets the files recorded and the groups found, for another scan.
+    pub fn clear(&self) {
+        for shard in &self.files {
+            shard.lock().unwrap().clear();
+        }
+        self.links_seen.clear();
+        *self.groups.lock().unwrap() = None;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.groups.lock().unwrap().is_some()
+    }
+
+    // Runs `f` on the groups found, or on None while hashing is still going on.
+    pub fn with_groups<R>(&self, f: impl FnOnce(Option<&[DuplicateGroup]>) -> R) -> R {
+        f(self.groups.lock().unwrap().as_deref())
+    }
+
+    // Hashes the candidates and stores the groups of identical files. Files that cannot
+    // be read, or are not reached because of `cancelled`, are left out.
+    pub fn find(&self, cancelled: &AtomicBool) {
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        for shard in &self.files {
+            for (size, path) in std::mem::take(&mut *shard.lock().unwrap()) {
+                by_size.entry(size).or_default().push(path);
+            }
+        }
+        let candidates: Vec<(u64, Vec<PathBuf>)> = by_size.into_iter().filter(|(_, paths)| paths.len() > 1).collect();
+
+        // First pass: same size and same first block
+        let by_prefix = regroup(candidates, |size, path| hash_file(path, PREFIX_LEN, cancelled).map(|hash| (size, hash)));
+        // Second pass: same contents; files no longer than the first block are already known
+        let (small, large): (Vec<_>, Vec<_>) = by_prefix.into_iter().partition(|(size, _)| *size <= PREFIX_LEN);
+        let confirmed = regroup(large, |size, path| hash_file(path, u64::MAX, cancelled).map(|hash| (size, hash)));
+
+        let mut groups: Vec<DuplicateGroup> = small.into_iter()
+            .chain(confirmed)
+            .map(|(size, mut paths)| {
+                paths.sort();
+                DuplicateGroup { size, paths }
+            })
+            .collect();
+        groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.paths.cmp(&b.paths)));
+        *self.groups.lock().unwrap() = Some(groups);
+    }
+
+    // Prints every group, largest waste first, with its paths below it. The size columns
+    // widen to fit exact byte counts (`units`).
+    pub fn print_report(&self, units: Units) {
+        self.with_groups(|groups| {
+            let groups = groups.unwrap_or_default();
+            let wasted: u64 = groups.iter().map(DuplicateGroup::wasted).sum();
+            // The largest waste is the widest number, and a group's size is never above it
+            let width = groups.iter().map(|group| format_size(group.wasted(), units).len()).max().unwrap_or(0).max(12);
+            println!("{:>width$} {:>width$} {:>8}", "WASTED", "SIZE", "COPIES");
+            for group in groups {
+                println!(
+                    "{:>width$} {:>width$} {:>8}",
+                    format_size(group.wasted(), units),
+                    format_size(group.size, units),
+                    format_count(group.paths.len() as u64),
+                );
+                for path in &group.paths {
+                    println!("    {}", path.display());
+                }
+            }
+            println!("{} groups of duplicate files, {} reclaimable", format_count(groups.len() as u64), format_size(wasted, units));
+        });
+    }
+}
+
+// Splits each (size, paths) group by `key`, in parallel, keeping the groups that still
+// hold more than one file.
+fn regroup<K>(groups: Vec<(u64, Vec<PathBuf>)>, key: K) -> Vec<(u64, Vec<PathBuf>)>
+where
+    K: Fn(u64, &Path) -> Option<(u64, u64)> + Sync,
+{
+    let keyed: Vec<((u64, u64), PathBuf)> = groups.into_par_iter()
+        .flat_map_iter(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+        .filter_map(|(size, path)| Some((key(size, &path)?, path)))
+        .collect();
+    let mut regrouped: HashMap<(u64, u64), Vec<PathBuf>> = HashMap::new();
+    for (key, path) in keyed {
+        regrouped.entry(key).or_default().push(path);
+    }
+    regrouped.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((size, _), paths)| (size, paths))
+        .collect()
+}
+
+// Hash of the first `limit` bytes of a file; None if it cannot be read or the scan was
+// cancelled meanwhile.
+fn hash_file(path: &Path, limit: u64, cancelled: &AtomicBool) -> Option<u64> {
+    let mut file = fs::File::open(path).ok()?.take(limit);
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0; 128 * 1024];
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        match file.read(&mut buffer) {
+            Ok(0) => return Some(hasher.finish()),
+            Ok(read) => hasher.write(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return None,
+        }
+    }
+}
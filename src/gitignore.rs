@@ -0,0 +1,215 @@
+// .gitignore support for --respect-gitignore.
+//
+// Every directory inside a git repository gets a layer holding the rules of its own
+// .gitignore, chained to the layers of its parent directories. As in git, the deepest
+// matching rule wins and a nested repository (a directory with its own `.git`) starts
+// a fresh chain. The repository root layer also carries `.git/info/exclude`.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::glob::Pattern;
+
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    // Patterns containing a '/' match the path relative to the .gitignore's directory,
+    // all others match the entry name at any depth.
+    anchored: bool,
+}
+
+pub struct Gitignore {
+    parent: Option<Arc<Gitignore>>,
+    rules: Vec<Rule>,
+    // Path from this layer's directory down to the scan root ("" or ending in '/'),
+    // for layers loaded from directories above the root.
+    prefix: String,
+    // Number of leading root-relative components that belong to this layer's directory.
+    skip: usize,
+}
+
+impl Gitignore {
+    // Rules inherited by the scan root from enclosing directories of the same repository.
+    pub fn for_root(root: &Path) -> Option<Arc<Gitignore>> {
+        let root = fs::canonicalize(root).ok()?;
+        // The root itself being a repository is handled by `descend` like any other directory
+        if root.join(".git").exists() {
+            return None;
+        }
+        let repo = root.ancestors().skip(1).find(|dir| dir.join(".git").exists())?;
+
+        let mut chain: Vec<&Path> = root.ancestors().skip(1).take_while(|dir| *dir != repo).collect();
+        chain.push(repo);
+
+        let mut layer = None;
+        for dir in chain.into_iter().rev() {
+            let below: Vec<_> = root.strip_prefix(dir).ok()?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let prefix = format!("{}/", below.join("/"));
+            layer = Some(Self::load(layer, dir, prefix, 0));
+        }
+        layer
+    }
+
+    // Rules in effect inside `dir` (at root-relative path `relative`), layered on its parent's.
+    // Returns None outside of git repositories, where nothing is ever ignored.
+    pub fn descend(parent: Option<&Arc<Gitignore>>, dir: &Path, relative: &str) -> Option<Arc<Gitignore>> {
+        let skip = if relative.is_empty() { 0 } else { relative.split('/').count() };
+
+        if dir.join(".git").exists() {
+            // A (nested) repository does not inherit the rules of the one around it
+            return Some(Self::load(None, dir, String::new(), skip));
+        }
+        let parent = parent?;
+        if dir.join(".gitignore").is_file() {
+            Some(Self::load(Some(Arc::clone(parent)), dir, String::new(), skip))
+        } else {
+            Some(Arc::clone(parent))
+        }
+    }
+
+    fn load(parent: Option<Arc<Gitignore>>, dir: &Path, prefix: String, skip: usize) -> Arc<Gitignore> {
+        let mut rules = Vec::new();
+        // info/exclude comes first so the repository's .gitignore overrides it
+        if parent.is_none() {
+            rules.extend(read_rules(&dir.join(".git").join("info").join("exclude")));
+        }
+        rules.extend(read_rules(&dir.join(".gitignore")));
+        Arc::new(Gitignore { parent, rules, prefix, skip })
+    }
+
+    // Decides whether an entry (given by its root-relative path and name) is ignored.
+    pub fn is_ignored(&self, relative: &str, name: &str, is_dir: bool) -> bool {
+        let mut layer = Some(self);
+        while let Some(current) = layer {
+            let local = match relative.splitn(current.skip + 1, '/').nth(current.skip) {
+                Some(rest) => format!("{}{}", current.prefix, rest),
+                None => break,
+            };
+            // Within one file the last matching rule wins
+            let decision = current.rules.iter().rev().find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && if rule.anchored { rule.pattern.matches(&local) } else { rule.pattern.matches(name) }
+            });
+            if let Some(rule) = decision {
+                return !rule.negated;
+            }
+            layer = current.parent.as_deref();
+        }
+        false
+    }
+}
+
+fn read_rules(path: &Path) -> Vec<Rule> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents.lines().filter_map(parse_rule).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    // Trailing spaces are insignificant unless escaped with a backslash
+    let mut line = line.trim_end_matches('\r');
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    if line.is_empty() {
+        return None;
+    }
+
+    // Patterns git would reject are skipped rather than aborting the scan
+    let pattern = Pattern::new(line).ok()?;
+    Some(Rule { pattern, negated, dir_only, anchored })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A repository below the temporary directory with the given files, removed on drop.
+    struct Repo(PathBuf);
+
+    impl Repo {
+        fn new(name: &str, files: &[(&str, &str)]) -> Repo {
+            let root = std::env::temp_dir().join(format!("rdu-gitignore-{}-{}", std::process::id(), name));
+            fs::create_dir_all(root.join(".git/info")).unwrap();
+            for (path, contents) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            Repo(root)
+        }
+    }
+
+    impl Drop for Repo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn nested_files_layer_on_their_parents() {
+        let repo = Repo::new("nested", &[
+            (".gitignore", "*.log\nbuild/\n/only-here\n"),
+            ("sub/.gitignore", "*.tmp\n"),
+        ]);
+        let root = Gitignore::descend(None, &repo.0, "").unwrap();
+        let sub = Gitignore::descend(Some(&root), &repo.0.join("sub"), "sub").unwrap();
+        assert!(root.is_ignored("debug.log", "debug.log", false));
+        assert!(sub.is_ignored("sub/debug.log", "debug.log", false));
+        assert!(sub.is_ignored("sub/x.tmp", "x.tmp", false));
+        assert!(!root.is_ignored("x.tmp", "x.tmp", false));
+        // build/ only matches directories, and /only-here only at the top
+        assert!(root.is_ignored("build", "build", true));
+        assert!(!root.is_ignored("build", "build", false));
+        assert!(root.is_ignored("only-here", "only-here", false));
+        assert!(!sub.is_ignored("sub/only-here", "only-here", false));
+    }
+
+    #[test]
+    fn the_deepest_matching_rule_wins() {
+        let repo = Repo::new("negation", &[
+            (".git/info/exclude", "*.bak\n"),
+            (".gitignore", "*.log\n!keep.log\n!*.bak\n"),
+            ("sub/.gitignore", "keep.log\n!debug.log\n"),
+        ]);
+        let root = Gitignore::descend(None, &repo.0, "").unwrap();
+        let sub = Gitignore::descend(Some(&root), &repo.0.join("sub"), "sub").unwrap();
+        assert!(!root.is_ignored("keep.log", "keep.log", false));
+        assert!(sub.is_ignored("sub/keep.log", "keep.log", false));
+        assert!(!sub.is_ignored("sub/debug.log", "debug.log", false));
+        assert!(sub.is_ignored("sub/other.log", "other.log", false));
+        // .gitignore overrides info/exclude
+        assert!(!root.is_ignored("old.bak", "old.bak", false));
+    }
+
+    #[test]
+    fn a_nested_repository_starts_afresh() {
+        let repo = Repo::new("submodule", &[(".gitignore", "*.log\n"), ("inner/.git/HEAD", ""), ("plain/file", "")]);
+        let root = Gitignore::descend(None, &repo.0, "").unwrap();
+        let inner = Gitignore::descend(Some(&root), &repo.0.join("inner"), "inner").unwrap();
+        assert!(!inner.is_ignored("inner/debug.log", "debug.log", false));
+        // Without a repository around it a directory has no rules at all
+        assert!(Gitignore::descend(None, &repo.0.join("plain"), "plain").is_none());
+    }
+}
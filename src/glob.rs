@@ -0,0 +1,193 @@
+// Minimal shell-style glob patterns used to exclude entries from the scan.
+//
+// Supported syntax:
+//   *      any run of characters except '/'
+//   **     any run of characters including '/' ("**/" may also match nothing)
+//   ?      any single character except '/'
+//   [abc]  character class; ranges like [a-z] and negation with [!...] or [^...]
+//   \x     the literal character x
+
+#[derive(Debug, Clone)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    AnySequence,
+    AnyRecursive,
+    // "**/": zero or more whole leading directories
+    AnyDirectories,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    // Compiles a pattern, reporting syntax errors instead of failing later during traversal.
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    if chars.get(i + 2) == Some(&'/') {
+                        tokens.push(Token::AnyDirectories);
+                        i += 3;
+                    } else {
+                        tokens.push(Token::AnyRecursive);
+                        i += 2;
+                    }
+                    continue;
+                }
+                '*' => tokens.push(Token::AnySequence),
+                '?' => tokens.push(Token::AnyChar),
+                '\\' => {
+                    i += 1;
+                    match chars.get(i) {
+                        Some(&c) => tokens.push(Token::Literal(c)),
+                        None => return Err(format!("invalid pattern '{}': trailing backslash", pattern)),
+                    }
+                }
+                '[' => {
+                    let (token, next) = parse_class(&chars, i + 1)
+                        .ok_or_else(|| format!("invalid pattern '{}': unclosed '['", pattern))?;
+                    tokens.push(token);
+                    i = next;
+                    continue;
+                }
+                c => tokens.push(Token::Literal(c)),
+            }
+            i += 1;
+        }
+
+        Ok(Pattern { tokens })
+    }
+
+    // Matches the whole text against the pattern (dynamic programming, no backtracking blowup).
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let n = chars.len();
+
+        // next[j]: do tokens[i + 1..] match chars[j..]?
+        let mut next = vec![false; n + 1];
+        next[n] = true;
+
+        for token in self.tokens.iter().rev() {
+            let mut current = vec![false; n + 1];
+            match token {
+                Token::AnySequence | Token::AnyRecursive => {
+                    let crosses_slash = matches!(token, Token::AnyRecursive);
+                    current[n] = next[n];
+                    for j in (0..n).rev() {
+                        current[j] = next[j] || ((crosses_slash || chars[j] != '/') && current[j + 1]);
+                    }
+                }
+                Token::AnyDirectories => {
+                    // reach[j]: some prefix of chars[j..] ending in '/' is followed by a match
+                    let mut reach = false;
+                    current[n] = next[n];
+                    for j in (0..n).rev() {
+                        reach = (chars[j] == '/' && next[j + 1]) || reach;
+                        current[j] = next[j] || reach;
+                    }
+                }
+                _ => {
+                    for j in 0..n {
+                        current[j] = next[j + 1] && token_matches_char(token, chars[j]);
+                    }
+                }
+            }
+            next = current;
+        }
+
+        next[0]
+    }
+}
+
+fn token_matches_char(token: &Token, c: char) -> bool {
+    match token {
+        Token::Literal(expected) => *expected == c,
+        Token::AnyChar => c != '/',
+        Token::Class { negated, ranges } => {
+            c != '/' && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+        }
+        _ => false,
+    }
+}
+
+// Parses a character class body starting just after '['; returns the token and the index after ']'.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        // A ']' directly after the opening bracket is a literal member
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+
+        let lo = if c == '\\' {
+            i += 1;
+            *chars.get(i)?
+        } else {
+            c
+        };
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&hi| hi != ']') {
+            ranges.push((lo, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((lo, lo));
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).unwrap().matches(text)
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        assert!(matches("**/target", "target"));
+        assert!(matches("**/target", "a/b/target"));
+        assert!(!matches("**/target", "a/targets"));
+        assert!(matches("src/**", "src/a/b.rs"));
+        assert!(matches("a/**/z", "a/z"));
+        assert!(matches("a/**/z", "a/b/c/z"));
+        assert!(matches("a**z", "a/b/z"));
+        assert!(!matches("a*z", "a/b/z"));
+    }
+
+    #[test]
+    fn single_characters_and_classes_stay_within_a_name() {
+        assert!(matches("*.iso", "disk.iso"));
+        assert!(!matches("*.iso", "images/disk.iso"));
+        assert!(matches("file?.[ch]", "file1.c"));
+        assert!(!matches("file?", "file/"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(!matches("[^a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        assert_eq!(Pattern::new("[abc").unwrap_err(), "invalid pattern '[abc': unclosed '['");
+        assert_eq!(Pattern::new("end\\").unwrap_err(), "invalid pattern 'end\\': trailing backslash");
+    }
+}
@@ -0,0 +1,191 @@
+// Files with more than one hard link, and how their sizes are attributed (--hardlinks).
+//
+// Which link the parallel traversal reaches first is down to scheduling, so during the
+// scan the first one found simply gets the whole size. Every link is recorded here, and
+// once the scan is done `settle` moves the sizes to where the policy puts them:
+// - dedup: all of it to the lexicographically first path, the other links get nothing;
+// - shared: an equal part to every link found, so directory totals still add up to the
+//   space the file really uses.
+// With --hardlinks=count-all nothing is recorded and every link counts in full.
+//
+// Like SeenSet, the table is sharded by file identity so workers rarely wait on each other.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::breakdown::{self, Breakdown};
+use crate::{is_sparse, DirEntry, FileId, ScanRoot};
+
+const SHARDS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HardlinkPolicy {
+    // Count each file once, at its lexicographically first path
+    #[default]
+    Dedup,
+    // Count every link in full, like `du -l`
+    CountAll,
+    // Split each file's size evenly between its links
+    Shared,
+}
+
+impl HardlinkPolicy {
+    pub fn parse(text: &str) -> Result<HardlinkPolicy, String> {
+        match text {
+            "dedup" => Ok(HardlinkPolicy::Dedup),
+            "count-all" => Ok(HardlinkPolicy::CountAll),
+            "shared" => Ok(HardlinkPolicy::Shared),
+            _ => Err(format!("expected dedup, count-all or shared, got '{}'", text)),
+        }
+    }
+}
+
+struct LinkedFile {
+    apparent_size: u64,
+    disk_usage: u64,
+    owner: Option<u32>,
+    links: Vec<Link>,
+}
+
+struct Link {
+    root: usize,
+    path: PathBuf,
+    // The link that carries the size until the scan is settled
+    counted: bool,
+}
+
+pub struct LinkedFiles {
+    shards: Vec<Mutex<HashMap<FileId, LinkedFile>>>,
+    hasher: RandomState,
+}
+
+impl LinkedFiles {
+    pub fn new() -> LinkedFiles {
+        LinkedFiles {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    // Records a link found below command-line root `root`, returning true if it is the
+    // first link of that file (the one counted for now).
+    pub fn record(&self, id: FileId, root: usize, path: &Path, sizes: (u64, u64), owner: Option<u32>) -> bool {
+        let mut files = self.shards[self.shard(&id)].lock().unwrap();
+        let file = files.entry(id).or_insert_with(|| LinkedFile {
+            apparent_size: sizes.0,
+            disk_usage: sizes.1,
+            owner,
+            links: Vec::new(),
+        });
+        let counted = file.links.is_empty();
+        file.links.push(Link { root, path: path.to_path_buf(), counted });
+        counted
+    }
+
+    // Forgets every link recorded, for another scan.
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap().clear();
+        }
+    }
+
+    fn shard(&self, id: &FileId) -> usize {
+        self.hasher.hash_one(id) as usize % SHARDS
+    }
+
+    // Moves the sizes of every recorded file to the links `policy` picks, in `entries`
+    // (one finished tree per root, before any pruning), and attributes them to the
+    // owner and extension totals, which leave hard-linked files out during the scan.
+    pub fn settle(
+        &self,
+        policy: HardlinkPolicy,
+        roots: &[ScanRoot],
+        entries: &mut [DirEntry],
+        owners: Option<&Breakdown<u32>>,
+        extensions: &Breakdown<String>,
+    ) {
+        let mut by_extension: HashMap<String, breakdown::Usage> = HashMap::new();
+        for shard in &self.shards {
+            for (_, mut file) in std::mem::take(&mut *shard.lock().unwrap()) {
+                if let Some(owners) = owners
+                    && let Some(uid) = file.owner
+                {
+                    owners.add(uid, file.apparent_size, file.disk_usage);
+                }
+                file.links.sort_by(|a, b| a.path.cmp(&b.path));
+                let count = file.links.len() as u64;
+                for (index, link) in file.links.iter().enumerate() {
+                    let first = index == 0;
+                    let (apparent_size, disk_usage) = match policy {
+                        // The remainders go to the first link so the parts add up exactly
+                        HardlinkPolicy::Shared => (
+                            part(file.apparent_size, count, first),
+                            part(file.disk_usage, count, first),
+                        ),
+                        _ if first => (file.apparent_size, file.disk_usage),
+                        _ => (0, 0),
+                    };
+                    let inodes = first as u64;
+                    let (old_apparent, old_disk, old_inodes) = if link.counted {
+                        (file.apparent_size, file.disk_usage, 1)
+                    } else {
+                        (0, 0, 0)
+                    };
+                    let Some(entry) = entries.get_mut(link.root) else { continue };
+                    let leaf = apply(entry, &roots[link.root], &link.path, Delta {
+                        apparent_size: apparent_size as i64 - old_apparent as i64,
+                        disk_usage: disk_usage as i64 - old_disk as i64,
+                        inodes: inodes as i64 - old_inodes,
+                    });
+                    if let Some(leaf) = leaf {
+                        let carries_all = (policy == HardlinkPolicy::Dedup && first) || count == 1;
+                        leaf.shared = (!carries_all).then_some((file.apparent_size, file.disk_usage));
+                        leaf.sparse = apparent_size > 0 && is_sparse(file.apparent_size, file.disk_usage);
+                    }
+
+                    if apparent_size > 0 || disk_usage > 0 || first {
+                        let name = link.path.file_name().unwrap_or_default().to_string_lossy();
+                        let key = breakdown::extension_of(&name).unwrap_or_else(|| breakdown::NO_EXTENSION.to_string());
+                        let usage = by_extension.entry(key).or_default();
+                        usage.apparent_size += apparent_size;
+                        usage.disk_usage += disk_usage;
+                        usage.files += inodes;
+                    }
+                }
+            }
+        }
+        extensions.merge(by_extension);
+    }
+}
+
+// One link's part of `size` split `count` ways.
+fn part(size: u64, count: u64, first: bool) -> u64 {
+    size / count + if first { size % count } else { 0 }
+}
+
+struct Delta {
+    apparent_size: i64,
+    disk_usage: i64,
+    inodes: i64,
+}
+
+// Adds `delta` to every entry from the root down to the file at `path`, returning the
+// file's entry. The walk stops early where the tree was cut by --max-depth; the totals
+// above the cut still include the change.
+fn apply<'a>(entry: &'a mut DirEntry, root: &ScanRoot, path: &Path, delta: Delta) -> Option<&'a mut DirEntry> {
+    let relative = path.strip_prefix(&root.path).ok()?;
+    let mut node = entry;
+    for component in relative.components() {
+        node.apparent_size = node.apparent_size.saturating_add_signed(delta.apparent_size);
+        node.disk_usage = node.disk_usage.saturating_add_signed(delta.disk_usage);
+        node.inodes = node.inodes.saturating_add_signed(delta.inodes);
+        let name = component.as_os_str().to_string_lossy();
+        node = node.children.iter_mut().find(|child| child.name == name)?;
+    }
+    node.apparent_size = node.apparent_size.saturating_add_signed(delta.apparent_size);
+    node.disk_usage = node.disk_usage.saturating_add_signed(delta.disk_usage);
+    node.inodes = node.inodes.saturating_add_signed(delta.inodes);
+    Some(node)
+}
@@ -0,0 +1,56 @@
+// Icons before the names of the tree view (--icons): a folder for directories, a link for
+// symlinks, and for files one picked by the extension (images, video, audio, archives,
+// code, documents) or else a plain page.
+//
+// The icons are Nerd Font glyphs (https://www.nerdfonts.com) in Unicode's Private Use
+// Area, so only a terminal font patched with them draws them; any other font shows
+// empty boxes instead, which is why they are off unless asked for. Each takes one column.
+
+use crate::breakdown::extension_of;
+use crate::DirEntry;
+
+const FOLDER: &str = "\u{f07b}";
+const LINK: &str = "\u{f0c1}";
+const SPECIAL: &str = "\u{f013}";
+const FILE: &str = "\u{f15b}";
+const TEXT: &str = "\u{f15c}";
+const PDF: &str = "\u{f1c1}";
+const IMAGE: &str = "\u{f1c5}";
+const ARCHIVE: &str = "\u{f1c6}";
+const AUDIO: &str = "\u{f1c7}";
+const VIDEO: &str = "\u{f1c8}";
+const CODE: &str = "\u{f1c9}";
+
+// Extensions (lowercase, as breakdown::extension_of gives them) and their icons.
+const EXTENSIONS: &[(&[&str], &str)] = &[
+    (&["png", "jpg", "jpeg", "gif", "bmp", "webp", "svg", "ico", "tif", "tiff", "heic", "raw", "psd"], IMAGE),
+    (&["mp4", "mkv", "avi", "mov", "webm", "wmv", "flv", "m4v", "mpg", "mpeg", "ts"], VIDEO),
+    (&["mp3", "flac", "wav", "ogg", "opus", "m4a", "aac", "wma"], AUDIO),
+    (&["zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "iso", "dmg", "deb", "rpm", "jar"], ARCHIVE),
+    (&[
+        "rs", "c", "h", "cc", "cpp", "hpp", "go", "py", "js", "mjs", "jsx", "tsx", "java", "kt", "rb", "php",
+        "cs", "swift", "sh", "bash", "zsh", "lua", "pl", "hs", "ml", "scala", "html", "css", "json", "toml",
+        "yaml", "yml", "xml", "sql",
+    ], CODE),
+    (&["txt", "md", "rst", "log", "csv", "doc", "docx", "odt", "rtf"], TEXT),
+    (&["pdf"], PDF),
+];
+
+// The icon of `entry`'s row.
+pub fn icon(entry: &DirEntry) -> &'static str {
+    if entry.is_symlink {
+        return LINK;
+    }
+    if entry.is_dir {
+        return FOLDER;
+    }
+    if entry.special.is_some() {
+        return SPECIAL;
+    }
+    let Some(extension) = extension_of(&entry.name) else {
+        return FILE;
+    };
+    EXTENSIONS.iter()
+        .find(|(extensions, _)| extensions.contains(&extension.as_str()))
+        .map_or(FILE, |&(_, icon)| icon)
+}
@@ -0,0 +1,431 @@
+// The scan written to stdout as JSON (--json) instead of opening the TUI, for scripts.
+//
+// One object holds the roots, when the scan finished, its statistics and the tree:
+//
+//     {"roots": ["/data"], "scanned_at": "2024-05-01T12:00:00+02:00", "size_mode": "disk usage",
+//      "elapsed_seconds": 1.5, "stats": {"entries": 3, ...},
+//      "tree": {"name": "/data", "kind": "directory", "size": 8192, ..., "children": [...]}}
+//
+// Every entry has its name and kind, `size` in the metric of the scan, the other metrics,
+// and its modification time in seconds since 1970 (null when unknown). Directories add
+// their counts and, if they have children, `own_size`: what the directory takes itself,
+// so that adding up `own_size` or else `size` over all entries gives the size of the
+// tree. Names that were not valid UTF-8, which the tree keeps with U+FFFD in place of
+// the bytes that were not, have "name_lossy": true.
+//
+// Entries are written as the tree is walked, so nothing but the tree is held in memory;
+// children come largest first, like in the TUI. --pretty indents the output.
+//
+// With --ndjson each entry is written on a line of its own instead, with its path, as soon
+// as the scan is done with it, so that nothing waits for the whole tree; a directory comes
+// after everything in it, once its totals are known:
+//
+//     {"path": "/data/a.txt", "kind": "file", "size": 4096, ..., "mtime": 1714557600}
+//
+// Hard links are not settled yet at that point, so a file with several links counts in
+// full at the link the scan found first. The last line has "kind": "end" and the roots,
+// the settled total, the time taken and the statistics, as --json has them.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::Local;
+
+use crate::{DirEntry, ScanStats, SizeMode, SortOrder};
+
+// Writes JSON, keeping track of the objects and arrays open so that commas and
+// indentation go where they belong.
+struct Writer<W: Write> {
+    out: W,
+    pretty: bool,
+    // For each object or array open, whether anything is in it yet
+    open: Vec<bool>,
+}
+
+impl<W: Write> Writer<W> {
+    fn begin(&mut self, bracket: &[u8]) -> io::Result<()> {
+        self.out.write_all(bracket)?;
+        self.open.push(false);
+        Ok(())
+    }
+
+    fn end(&mut self, bracket: &[u8]) -> io::Result<()> {
+        if self.open.pop() == Some(true) {
+            self.newline()?;
+        }
+        self.out.write_all(bracket)
+    }
+
+    fn newline(&mut self) -> io::Result<()> {
+        if self.pretty {
+            write!(self.out, "\n{:1$}", "", 2 * self.open.len())?;
+        }
+        Ok(())
+    }
+
+    // Starts the next value of the innermost object or array.
+    fn item(&mut self) -> io::Result<()> {
+        if let Some(filled) = self.open.last_mut() {
+            if *filled {
+                self.out.write_all(b",")?;
+            }
+            *filled = true;
+        }
+        self.newline()
+    }
+
+    fn key(&mut self, key: &str) -> io::Result<()> {
+        self.item()?;
+        self.string(key)?;
+        self.out.write_all(if self.pretty { b": " } else { b":" })
+    }
+
+    fn string(&mut self, text: &str) -> io::Result<()> {
+        self.out.write_all(b"\"")?;
+        let mut start = 0;
+        for (index, c) in text.char_indices() {
+            let escaped = match c {
+                '"' => "\\\"".to_string(),
+                '\\' => "\\\\".to_string(),
+                '\n' => "\\n".to_string(),
+                '\t' => "\\t".to_string(),
+                c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+                _ => continue,
+            };
+            self.out.write_all(&text.as_bytes()[start..index])?;
+            self.out.write_all(escaped.as_bytes())?;
+            start = index + c.len_utf8();
+        }
+        self.out.write_all(&text.as_bytes()[start..])?;
+        self.out.write_all(b"\"")
+    }
+
+    fn field_str(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.key(key)?;
+        self.string(value)
+    }
+
+    // A number, or anything else written the way JSON writes it (true, null)
+    fn field(&mut self, key: &str, value: impl std::fmt::Display) -> io::Result<()> {
+        self.key(key)?;
+        write!(self.out, "{}", value)
+    }
+}
+
+// Writes the roots, the statistics and the tree of a finished scan to `out`.
+pub fn write(
+    out: impl Write,
+    tree: &DirEntry,
+    roots: &[&Path],
+    stats: &ScanStats,
+    elapsed: Duration,
+    mode: SizeMode,
+    pretty: bool,
+) -> io::Result<()> {
+    let mut json = Writer { out: io::BufWriter::new(out), pretty, open: Vec::new() };
+    json.begin(b"{")?;
+    json.key("roots")?;
+    json.begin(b"[")?;
+    for root in roots {
+        json.item()?;
+        json.string(&root.to_string_lossy())?;
+    }
+    json.end(b"]")?;
+    json.field_str("scanned_at", &Local::now().to_rfc3339())?;
+    json.field_str("size_mode", mode.label())?;
+    json.field("elapsed_seconds", elapsed.as_secs_f64())?;
+    write_stats(&mut json, stats)?;
+    json.key("tree")?;
+    write_entry(&mut json, tree, mode)?;
+    json.end(b"}")?;
+    json.out.write_all(b"\n")?;
+    json.out.flush()
+}
+
+fn write_stats<W: Write>(json: &mut Writer<W>, stats: &ScanStats) -> io::Result<()> {
+    json.key("stats")?;
+    json.begin(b"{")?;
+    json.field("entries", stats.entries)?;
+    json.field("files", stats.files)?;
+    json.field("directories", stats.directories)?;
+    json.field("symlinks", stats.symlinks)?;
+    json.field("repeated_hardlinks", stats.hardlinks)?;
+    json.field("excluded", stats.excluded)?;
+    json.field("hidden", stats.hidden)?;
+    json.field("errors", stats.errors)?;
+    json.field("broken_links", stats.broken_links)?;
+    json.field("cancelled", stats.cancelled)?;
+    json.end(b"}")
+}
+
+// What an entry is, as "kind" says.
+fn kind(entry: &DirEntry) -> &'static str {
+    if entry.pruned > 0 {
+        "summary"
+    } else if entry.is_symlink {
+        "symlink"
+    } else if entry.is_dir {
+        "directory"
+    } else if let Some(special) = entry.special {
+        special.label()
+    } else {
+        "file"
+    }
+}
+
+fn write_mtime<W: Write>(json: &mut Writer<W>, entry: &DirEntry) -> io::Result<()> {
+    match entry.mtime.and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok()) {
+        Some(since) => json.field("mtime", since.as_secs()),
+        None => json.field("mtime", "null"),
+    }
+}
+
+fn write_entry<W: Write>(json: &mut Writer<W>, entry: &DirEntry, mode: SizeMode) -> io::Result<()> {
+    json.begin(b"{")?;
+    json.field_str("name", &entry.name)?;
+    if entry.name.contains(char::REPLACEMENT_CHARACTER) {
+        json.field("name_lossy", true)?;
+    }
+    json.field_str("kind", kind(entry))?;
+    let size = entry.size(mode);
+    json.field("size", size)?;
+    json.field("apparent_size", entry.apparent_size)?;
+    json.field("disk_usage", entry.disk_usage)?;
+    json.field("inodes", entry.inodes)?;
+    write_mtime(json, entry)?;
+    if entry.is_dir || entry.pruned > 0 {
+        json.field("files", entry.file_count)?;
+        json.field("directories", entry.dir_count)?;
+    }
+    if entry.pruned > 0 {
+        json.field("entries", entry.pruned)?;
+    }
+    if let Some(target) = &entry.link_target {
+        json.field_str("target", target)?;
+        json.field("broken", entry.broken_link)?;
+    }
+    if entry.links > 1 {
+        json.field("links", entry.links)?;
+    }
+    if entry.shared.is_some() {
+        json.field("shared_hardlink", true)?;
+    }
+    let skipped = if entry.skipped_mount {
+        Some("other filesystem")
+    } else if entry.skipped_cache {
+        Some("cache")
+    } else if entry.skipped_virtual {
+        Some("virtual filesystem")
+    } else {
+        None
+    };
+    if let Some(skipped) = skipped {
+        json.field_str("skipped", skipped)?;
+    }
+    for (flag, set) in [("truncated", entry.truncated), ("incomplete", entry.incomplete), ("sparse", entry.sparse)] {
+        if set {
+            json.field(flag, true)?;
+        }
+    }
+    if !entry.children.is_empty() {
+        let children_size: u64 = entry.children.iter().map(|child| child.size(mode)).sum();
+        json.field("own_size", size.saturating_sub(children_size))?;
+        let mut children: Vec<&DirEntry> = entry.children.iter().collect();
+        children.sort_by(|a, b| SortOrder::Size.compare(a, b, mode, false));
+        json.key("children")?;
+        json.begin(b"[")?;
+        for child in children {
+            json.item()?;
+            write_entry(json, child, mode)?;
+        }
+        json.end(b"]")?;
+    }
+    json.end(b"}")
+}
+
+// The lines of --ndjson, written from every worker of the scan. Each line is put together
+// on its own and written whole under the lock, so lines never run into each other.
+pub struct Lines {
+    out: Mutex<Box<dyn Write + Send>>,
+    mode: SizeMode,
+    // Entries deeper than --max-depth or smaller than --min-size are left out
+    max_depth: Option<usize>,
+    min_size: Option<u64>,
+    // The first write that failed; the scan is cancelled then, since nobody reads the rest
+    failed: Mutex<Option<io::Error>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Lines {
+    pub fn new(out: impl Write + Send + 'static, mode: SizeMode, max_depth: Option<usize>, min_size: Option<u64>, cancelled: Arc<AtomicBool>) -> Lines {
+        Lines {
+            out: Mutex::new(Box::new(out)),
+            mode,
+            max_depth,
+            min_size,
+            failed: Mutex::new(None),
+            cancelled,
+        }
+    }
+
+    // Writes the line of `entry`, found at `path`, `depth` levels below its root.
+    // Directories are flushed right away, so the lines come out as the scan goes.
+    pub fn entry(&self, path: &Path, entry: &DirEntry, depth: usize) {
+        let size = entry.size(self.mode);
+        if self.max_depth.is_some_and(|max_depth| depth > max_depth) || self.min_size.is_some_and(|min_size| size < min_size) {
+            return;
+        }
+        // Writing to memory does not fail
+        if let Ok(line) = self.line(path, entry, size) {
+            self.write(&line, entry.is_dir);
+        }
+    }
+
+    fn line(&self, path: &Path, entry: &DirEntry, size: u64) -> io::Result<Vec<u8>> {
+        let mut json = Writer { out: Vec::new(), pretty: false, open: Vec::new() };
+        json.begin(b"{")?;
+        let path = path.to_string_lossy();
+        json.field_str("path", &path)?;
+        if path.contains(char::REPLACEMENT_CHARACTER) {
+            json.field("path_lossy", true)?;
+        }
+        json.field_str("kind", kind(entry))?;
+        json.field("size", size)?;
+        json.field("apparent_size", entry.apparent_size)?;
+        json.field("disk_usage", entry.disk_usage)?;
+        json.field("inodes", entry.inodes)?;
+        write_mtime(&mut json, entry)?;
+        if entry.is_dir {
+            json.field("files", entry.file_count)?;
+            json.field("directories", entry.dir_count)?;
+        }
+        if let Some(target) = &entry.link_target {
+            json.field_str("target", target)?;
+            json.field("broken", entry.broken_link)?;
+        }
+        if entry.incomplete {
+            json.field("incomplete", true)?;
+        }
+        json.end(b"}")?;
+        json.out.push(b'\n');
+        Ok(json.out)
+    }
+
+    fn write(&self, line: &[u8], flush: bool) {
+        let mut out = self.out.lock().unwrap();
+        let mut failed = self.failed.lock().unwrap();
+        if failed.is_some() {
+            return;
+        }
+        let written = out.write_all(line).and_then(|()| if flush { out.flush() } else { Ok(()) });
+        if let Err(e) = written {
+            *failed = Some(e);
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Writes the last line, with the roots, the settled total of the tree and the
+    // statistics, or returns why the lines could not all be written.
+    pub fn end(&self, roots: &[&Path], tree: &DirEntry, stats: &ScanStats, elapsed: Duration) -> io::Result<()> {
+        if let Some(e) = self.failed.lock().unwrap().take() {
+            return Err(e);
+        }
+        let mut json = Writer { out: Vec::new(), pretty: false, open: Vec::new() };
+        json.begin(b"{")?;
+        json.field_str("kind", "end")?;
+        json.key("roots")?;
+        json.begin(b"[")?;
+        for root in roots {
+            json.item()?;
+            json.string(&root.to_string_lossy())?;
+        }
+        json.end(b"]")?;
+        json.field_str("size_mode", self.mode.label())?;
+        json.field("size", tree.size(self.mode))?;
+        json.field("elapsed_seconds", elapsed.as_secs_f64())?;
+        write_stats(&mut json, stats)?;
+        json.end(b"}")?;
+        json.out.push(b'\n');
+        let mut out = self.out.lock().unwrap();
+        out.write_all(&json.out)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An output that can still be read once Lines has it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn string(text: &str) -> String {
+        let mut json = Writer { out: Vec::new(), pretty: false, open: Vec::new() };
+        json.string(text).unwrap();
+        String::from_utf8(json.out).unwrap()
+    }
+
+    #[test]
+    fn strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(string("plain"), r#""plain""#);
+        assert_eq!(string(r#"say "hi"\now"#), r#""say \"hi\"\\now""#);
+        assert_eq!(string("two\nlines\tand\u{1}\u{1f}"), r#""two\nlines\tand\u0001\u001f""#);
+        // Everything else, like the replacement character of a lossy name, is kept as it is
+        assert_eq!(string("café \u{fffd} \u{7f}"), "\"café \u{fffd} \u{7f}\"");
+    }
+
+    #[test]
+    fn the_tree_is_written_with_commas_only_between_items() {
+        let tree = DirEntry {
+            name: "root \"1\"".to_string(),
+            is_dir: true,
+            disk_usage: 3,
+            children: vec![DirEntry { name: "a\\b".to_string(), disk_usage: 3, ..Default::default() }],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write(&mut out, &tree, &[Path::new("/r")], &ScanStats::default(), Duration::ZERO, SizeMode::DiskUsage, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#""tree":{"name":"root \"1\"","kind":"directory","size":3,"#), "{}", out);
+        assert!(out.contains(r#""children":[{"name":"a\\b","kind":"file","size":3,"apparent_size":0,"disk_usage":3,"inodes":0,"mtime":null}]}}"#), "{}", out);
+        assert!(!out.contains(",,") && !out.contains(",}") && !out.contains(",]"), "{}", out);
+    }
+
+    #[test]
+    fn lines_come_out_in_the_order_entries_finish_and_end_last() {
+        let out = Shared::default();
+        let lines = Lines::new(out.clone(), SizeMode::DiskUsage, None, Some(1), Arc::default());
+        let file = DirEntry { name: "a\"b".to_string(), disk_usage: 4096, ..Default::default() };
+        let empty = DirEntry { name: "x".to_string(), ..Default::default() };
+        let dir = DirEntry { name: "d".to_string(), is_dir: true, disk_usage: 8192, ..Default::default() };
+        lines.entry(Path::new("/r/d/a\"b"), &file, 2);
+        lines.entry(Path::new("/r/d/x"), &empty, 2);
+        lines.entry(Path::new("/r/d"), &dir, 1);
+        lines.entry(Path::new("/r"), &dir, 0);
+        lines.end(&[Path::new("/r")], &dir, &ScanStats::default(), Duration::ZERO).unwrap();
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        // With --min-size 1 the empty file is left out
+        assert_eq!(lines.len(), 4, "{}", out);
+        assert!(lines[0].starts_with(r#"{"path":"/r/d/a\"b","kind":"file","size":4096,"#), "{}", lines[0]);
+        assert!(lines[1].starts_with(r#"{"path":"/r/d","kind":"directory","size":8192,"#), "{}", lines[1]);
+        assert!(lines[2].starts_with(r#"{"path":"/r","kind":"directory""#), "{}", lines[2]);
+        assert!(lines[3].starts_with(r#"{"kind":"end","roots":["/r"],"size_mode":"disk usage","size":8192,"#), "{}", lines[2]);
+        assert!(out.ends_with("}\n"));
+    }
+}
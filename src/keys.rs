@@ -0,0 +1,374 @@
+// The keys of the TUI, in one table that both the event loop and the help screen ('?')
+// read, so that every key handled is listed and every key listed is handled.
+//
+// A key press is looked up in a Keymap to find its action; what an action does can still
+// depend on what is shown, like Esc closing the list of broken symlinks before it quits.
+// The Keymap starts from the defaults in BINDINGS, and the [keys] section of the config
+// file can give any action other keys by its name:
+//
+//     [keys]
+//     quit = "x"
+//     down = ["j", "ctrl+n"]
+//
+// A key taken from another action that way is no longer that action's; an action given
+// an empty list has no key at all.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::config::Setting;
+
+// What a key press asks the TUI to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Back,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    HalfPageUp,
+    HalfPageDown,
+    First,
+    Last,
+    Open,
+    GoUp,
+    LargestChild,
+    LargestFile,
+    PanLeft,
+    PanRight,
+    Toggle,
+    Expand,
+    Collapse,
+    Sort,
+    ReverseSort,
+    Bars,
+    ShareOfRoot,
+    Dotfiles,
+    Mtime,
+    AgeColors,
+    ApparentSize,
+    ExactBytes,
+    DecimalUnits,
+    Inodes,
+    Fold,
+    Unfold,
+    Extensions,
+    Duplicates,
+    Owners,
+    Largest,
+    Treemap,
+    BrokenLinks,
+    NextTab,
+    PreviousTab,
+    GoToTab,
+    CloseTab,
+    Search,
+    Filter,
+    Bookmark,
+    Bookmarks,
+    Export,
+    Mark,
+    ClearMarks,
+    Details,
+    Launch,
+    Copy,
+    Delete,
+    Rescan,
+    RescanDirectory,
+    Cancel,
+    Help,
+}
+
+pub struct Binding {
+    // What the [keys] section of the config file calls the action
+    pub name: &'static str,
+    pub keys: &'static [KeyCode],
+    // Whether the keys are pressed with Ctrl
+    pub control: bool,
+    pub action: Action,
+    // What the help screen says the keys do
+    pub help: &'static str,
+}
+
+pub const BINDINGS: &[Binding] = &[
+    bind("quit", &[KeyCode::Char('q')], Action::Quit, "Quit"),
+    bind("back", &[KeyCode::Esc], Action::Back, "Close the broken symlinks or leave a user's files, else quit"),
+    bind("up", &[KeyCode::Up, KeyCode::Char('k')], Action::Up, "Move the selection up"),
+    bind("down", &[KeyCode::Down, KeyCode::Char('j')], Action::Down, "Move the selection down"),
+    bind("page-up", &[KeyCode::PageUp], Action::PageUp, "Move the selection up by a screenful"),
+    bind("page-down", &[KeyCode::PageDown], Action::PageDown, "Move the selection down by a screenful"),
+    bind_control("half-page-up", &[KeyCode::Char('u')], Action::HalfPageUp, "Move the selection up by half a screenful"),
+    bind_control("half-page-down", &[KeyCode::Char('d')], Action::HalfPageDown, "Move the selection down by half a screenful"),
+    bind("first", &[KeyCode::Home, KeyCode::Char('g')], Action::First, "Select the first row"),
+    bind("last", &[KeyCode::End, KeyCode::Char('G')], Action::Last, "Select the last row"),
+    bind("enter", &[KeyCode::Enter], Action::Open, "List the selected directory, or the files of the selected user"),
+    bind("parent", &[KeyCode::Backspace, KeyCode::Char('h')], Action::GoUp, "List the directory above"),
+    bind("largest-child", &[KeyCode::Char('>')], Action::LargestChild, "List the largest entry of the directory listed, or select it if it is a file"),
+    bind("largest-file", &[KeyCode::Char('}')], Action::LargestFile, "Select the largest file anywhere below the directory listed"),
+    bind("pan-left", &[KeyCode::Char('[')], Action::PanLeft, "Scroll names too long for their rows back to the left"),
+    bind("pan-right", &[KeyCode::Char(']')], Action::PanRight, "Scroll names too long for their rows to the right, to read their ends"),
+    bind("toggle", &[KeyCode::Char(' ')], Action::Toggle, "Expand or collapse the selected directory"),
+    bind("expand", &[KeyCode::Right], Action::Expand, "Expand the selected directory"),
+    bind("collapse", &[KeyCode::Left], Action::Collapse, "Collapse the selected directory, or select the one it is in"),
+    bind("sort", &[KeyCode::Char('s')], Action::Sort, "Sort by size, name, number of entries or age"),
+    bind("reverse-sort", &[KeyCode::Char('S')], Action::ReverseSort, "Reverse the sort order, like smallest first"),
+    bind("bars", &[KeyCode::Char('p')], Action::Bars, "Show size bars, percentages or neither"),
+    bind("share-of-root", &[KeyCode::Char('P')], Action::ShareOfRoot, "Show shares of the whole tree instead of each directory"),
+    bind("dotfiles", &[KeyCode::Char('.')], Action::Dotfiles, "Hide or show the entries whose names start with a dot"),
+    bind("mtime", &[KeyCode::Char('m')], Action::Mtime, "Show or hide modification times"),
+    bind("age-colors", &[KeyCode::Char('H')], Action::AgeColors, "Color by age instead of size"),
+    bind("apparent-size", &[KeyCode::Char('a')], Action::ApparentSize, "Switch between apparent and on-disk sizes"),
+    bind("exact-bytes", &[KeyCode::Char('b')], Action::ExactBytes, "Switch between rounded sizes and exact byte counts"),
+    bind("si-units", &[KeyCode::Char('U')], Action::DecimalUnits, "Switch between units of 1024 (KiB, MiB) and of 1000 (kB, MB)"),
+    bind("inodes", &[KeyCode::Char('c')], Action::Inodes, "Switch between bytes and inode counts"),
+    bind("fold", &[KeyCode::Char('z')], Action::Fold, "Fold small entries into one row"),
+    bind("unfold", &[KeyCode::Char('Z')], Action::Unfold, "List every entry of the selected directory while folding"),
+    bind("extensions", &[KeyCode::Char('e')], Action::Extensions, "Show the totals per file extension"),
+    bind("duplicates", &[KeyCode::Char('D')], Action::Duplicates, "Show the duplicate files (--find-duplicates)"),
+    bind("owners", &[KeyCode::Char('u')], Action::Owners, "Show the totals per user"),
+    bind("largest", &[KeyCode::Char('f')], Action::Largest, "Show the largest files anywhere in the tree; Enter shows one in the tree"),
+    bind("treemap", &[KeyCode::Char('t')], Action::Treemap, "Show the directory listed as a treemap, moving between cells with the arrows"),
+    bind("broken-links", &[KeyCode::Char('L')], Action::BrokenLinks, "List the broken symlinks"),
+    bind("next-tab", &[KeyCode::Tab], Action::NextTab, "With several roots, show the next tab"),
+    bind("previous-tab", &[KeyCode::BackTab], Action::PreviousTab, "With several roots, show the tab before"),
+    bind("go-to-tab", &[KeyCode::Char('1'), KeyCode::Char('2'), KeyCode::Char('3'), KeyCode::Char('4'), KeyCode::Char('5'), KeyCode::Char('6'), KeyCode::Char('7'), KeyCode::Char('8'), KeyCode::Char('9')], Action::GoToTab, "Show the tab with that number"),
+    bind("close-tab", &[KeyCode::Char('w')], Action::CloseTab, "Close the tab shown, unless it is the last one"),
+    bind("search", &[KeyCode::Char('/')], Action::Search, "Find entries by name, or by path with a '/'"),
+    bind_control("filter", &[KeyCode::Char('f')], Action::Filter, "List only the entries whose names have some text in them, as it is typed"),
+    bind("bookmark", &[KeyCode::Char('B')], Action::Bookmark, "Bookmark the selected entry, or remove its bookmark"),
+    bind("bookmarks", &[KeyCode::Char('\'')], Action::Bookmarks, "List the bookmarks with their sizes; Enter goes to one, d removes it"),
+    bind("export", &[KeyCode::Char('E')], Action::Export, "Write the rows of the tree, as shown, to a text file"),
+    bind("details", &[KeyCode::Char('i')], Action::Details, "Show or hide the details of the selected entry"),
+    bind("launch", &[KeyCode::Char('o')], Action::Launch, "Open the selected entry with its default application"),
+    bind("copy", &[KeyCode::Char('y')], Action::Copy, "Copy the full path of the selected entry to the clipboard"),
+    bind("mark", &[KeyCode::Char('M'), KeyCode::Insert], Action::Mark, "Mark or unmark the selected entry and move to the next one"),
+    bind("clear-marks", &[KeyCode::Char('X')], Action::ClearMarks, "Unmark every marked entry"),
+    bind("delete", &[KeyCode::Char('d')], Action::Delete, "Delete the marked entries, or else the selected one, after asking"),
+    bind("rescan", &[KeyCode::Char('r')], Action::Rescan, "Scan again, showing the new tree once it is complete"),
+    bind("rescan-directory", &[KeyCode::Char('R')], Action::RescanDirectory, "Scan the selected directory again"),
+    bind_control("cancel", &[KeyCode::Char('c')], Action::Cancel, "Cancel the scan, keeping what was found; a second time, quit"),
+    bind("help", &[KeyCode::Char('?'), KeyCode::F(1)], Action::Help, "Show this help"),
+];
+
+const fn bind(name: &'static str, keys: &'static [KeyCode], action: Action, help: &'static str) -> Binding {
+    Binding { name, keys, control: false, action, help }
+}
+
+const fn bind_control(name: &'static str, keys: &'static [KeyCode], action: Action, help: &'static str) -> Binding {
+    Binding { name, keys, control: true, action, help }
+}
+
+// A key as it is bound: its code, and whether Ctrl is held with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    code: KeyCode,
+    control: bool,
+}
+
+// Keys of the config file that are not a character, by the names it uses for them.
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("space", KeyCode::Char(' ')),
+    ("esc", KeyCode::Esc),
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("shift+tab", KeyCode::BackTab),
+    ("backspace", KeyCode::Backspace),
+    ("insert", KeyCode::Insert),
+    ("delete", KeyCode::Delete),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+];
+
+// The keys of every action: those of BINDINGS, in its order, unless the config file
+// says otherwise.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    keys: Vec<Vec<Key>>,
+}
+
+impl Keymap {
+    // The keymap with the keys of the [keys] section of the config file, or the line of
+    // the first setting that cannot be used and why.
+    pub fn new(settings: &[Setting]) -> Result<Keymap, (usize, String)> {
+        let mut keys: Vec<Vec<Key>> = BINDINGS.iter()
+            .map(|binding| binding.keys.iter().map(|&code| Key { code, control: binding.control }).collect())
+            .collect();
+        // Line of the setting that gave each action its keys
+        let mut configured: Vec<Option<usize>> = vec![None; BINDINGS.len()];
+        for setting in settings {
+            let Some(index) = BINDINGS.iter().position(|binding| binding.name == setting.name) else {
+                let names: Vec<&str> = BINDINGS.iter().map(|binding| binding.name).collect();
+                return Err((setting.line, format!("unknown action '{}'; the actions are {}", setting.name, names.join(", "))));
+            };
+            let parsed = setting.values.iter()
+                .map(|spec| parse_key(spec))
+                .collect::<Result<Vec<Key>, String>>()
+                .map_err(|e| (setting.line, e))?;
+            keys[index] = parsed;
+            configured[index] = Some(setting.line);
+        }
+        // A configured key leaves the action it had by default, and cannot be given to two
+        for index in 0..BINDINGS.len() {
+            let Some(line) = configured[index] else {
+                continue;
+            };
+            for key in keys[index].clone() {
+                for other in 0..BINDINGS.len() {
+                    if other == index || !keys[other].contains(&key) {
+                        continue;
+                    }
+                    if let Some(other_line) = configured[other] {
+                        // Reported at the later of the two lines
+                        let (first, second) = if other_line < line { (other, index) } else { (index, other) };
+                        return Err((line.max(other_line), format!(
+                            "{} is bound to both {} and {}",
+                            label(&key),
+                            BINDINGS[first].name,
+                            BINDINGS[second].name,
+                        )));
+                    }
+                    keys[other].retain(|&taken| taken != key);
+                }
+            }
+        }
+        Ok(Keymap { keys })
+    }
+
+    // The action of a key press, if it has one.
+    pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
+        let key = Key { code: event.code, control };
+        self.keys.iter()
+            .position(|keys| keys.contains(&key))
+            .map(|index| BINDINGS[index].action)
+    }
+
+    // The keys of every action that has some, as the help screen lists them ("Up, k" or
+    // "Ctrl+C"), with what they do.
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        BINDINGS.iter()
+            .zip(&self.keys)
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(binding, keys)| (keys.iter().map(label).collect::<Vec<_>>().join(", "), binding.help))
+            .collect()
+    }
+}
+
+// A key as the config file writes it: a character ("q", "G", "/"), one of NAMED_KEYS or
+// "f1" to "f12", any of them after "ctrl+". Names are not case-sensitive, characters are.
+fn parse_key(spec: &str) -> Result<Key, String> {
+    let (control, name) = match spec.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("ctrl+") && spec.len() > 5 => (true, &spec[5..]),
+        _ => (false, spec),
+    };
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        // With Ctrl, terminals report letters as lowercase
+        (Some(c), None) if control => KeyCode::Char(c.to_ascii_lowercase()),
+        (Some(c), None) => KeyCode::Char(c),
+        _ => {
+            let lower = name.to_ascii_lowercase();
+            let function = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()).filter(|n| (1..=12).contains(n));
+            match NAMED_KEYS.iter().find(|(key, _)| *key == lower) {
+                Some(&(_, code)) => code,
+                None => match function {
+                    Some(n) => KeyCode::F(n),
+                    None => return Err(format!(
+                        "unknown key '{}'; expected a character, f1 to f12 or one of {}, optionally after ctrl+",
+                        spec,
+                        NAMED_KEYS.iter().map(|(key, _)| *key).collect::<Vec<_>>().join(", "),
+                    )),
+                },
+            }
+        }
+    };
+    Ok(Key { code, control })
+}
+
+fn label(key: &Key) -> String {
+    if key.control { format!("Ctrl+{}", key_name(key.code).to_uppercase()) } else { key_name(key.code) }
+}
+
+fn key_name(key: KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::BackTab => "Shift+Tab".to_string(),
+        KeyCode::Insert => "Insert".to_string(),
+        KeyCode::Delete => "Delete".to_string(),
+        KeyCode::Enter => "Enter".to_string(),
+        KeyCode::Backspace => "Backspace".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(settings: &[(&str, &[&str])]) -> Result<Keymap, (usize, String)> {
+        let settings: Vec<Setting> = settings.iter().enumerate()
+            .map(|(i, (name, values))| Setting {
+                name: name.to_string(),
+                values: values.iter().map(|value| value.to_string()).collect(),
+                line: i + 2,
+            })
+            .collect();
+        Keymap::new(&settings)
+    }
+
+    fn press(keymap: &Keymap, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        keymap.action(&KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn configured_keys_replace_the_defaults() {
+        let keymap = keymap(&[("quit", &["x"]), ("down", &["J", "ctrl+N"]), ("help", &[])]).unwrap();
+        assert_eq!(press(&keymap, KeyCode::Char('x'), KeyModifiers::NONE), Some(Action::Quit));
+        assert_eq!(press(&keymap, KeyCode::Char('q'), KeyModifiers::NONE), None);
+        assert_eq!(press(&keymap, KeyCode::Char('J'), KeyModifiers::NONE), Some(Action::Down));
+        assert_eq!(press(&keymap, KeyCode::Char('n'), KeyModifiers::CONTROL), Some(Action::Down));
+        assert_eq!(press(&keymap, KeyCode::Char('?'), KeyModifiers::NONE), None);
+        // Actions that are not configured keep their keys
+        assert_eq!(press(&keymap, KeyCode::Up, KeyModifiers::NONE), Some(Action::Up));
+    }
+
+    #[test]
+    fn unknown_actions_are_refused_at_their_line() {
+        let (line, message) = keymap(&[("quit", &["x"]), ("jump", &["z"])]).unwrap_err();
+        assert_eq!(line, 3);
+        assert!(message.starts_with("unknown action 'jump'; the actions are quit, back, up, down"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_keys_are_refused_at_their_line() {
+        for spec in ["f13", "ctrl+", "pgup", "xy"] {
+            let (line, message) = keymap(&[("quit", &[spec])]).unwrap_err();
+            assert_eq!(line, 2);
+            assert!(message.starts_with(&format!("unknown key '{}'; expected a character, f1 to f12 or one of space, esc", spec)), "{}", message);
+        }
+    }
+
+    #[test]
+    fn a_key_given_to_two_actions_is_refused() {
+        let (line, message) = keymap(&[("quit", &["x"]), ("help", &["F1", "x"])]).unwrap_err();
+        assert_eq!((line, message.as_str()), (3, "x is bound to both quit and help"));
+    }
+}
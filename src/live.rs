@@ -0,0 +1,255 @@
+// The partial tree shown while a scan is still running.
+//
+// The traversal finishes directories bottom-up, and each finished directory is sent
+// over a channel with one level of children (the grandchildren were sent earlier, with
+// the subdirectories themselves). Directories that are still being scanned appear as
+// placeholders whose totals are the sums of what has arrived so far, so their sizes
+// grow as their subdirectories complete. Once the scan is done the complete tree
+// returned by the traversal replaces this one.
+//
+// When the roots are scanned again, the complete tree stays on screen until the new one
+// is done; no partial tree is built meanwhile. A directory scanned again on its own takes
+// the place of the old one, the difference in its totals going to every directory above.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::DirEntry;
+
+// Sent by the traversal to the TUI.
+pub enum ScanEvent {
+    // A finished directory, `path` being its components below command-line root `root`
+    Directory { root: usize, path: Vec<String>, entry: DirEntry },
+    // The whole scan is done; this is the final tree
+    Finished { tree: DirEntry, elapsed: Duration },
+    // A directory at `path` below the root was scanned again; None if it no longer exists
+    Rescanned { path: Vec<String>, entry: Option<DirEntry>, elapsed: Duration },
+}
+
+pub struct LiveTree {
+    tree: DirEntry,
+    // With several roots the tree is a virtual root holding one child per root
+    multiple_roots: bool,
+    // How long the scan took, once it is done
+    elapsed: Option<Duration>,
+    // Whether the roots are being scanned again
+    rescanning: bool,
+    // The directory scanned again on its own, while that runs and once it is done
+    rescanned: Option<Vec<String>>,
+    // Changes with every change to the tree, so what is worked out from it can be kept
+    version: u64,
+}
+
+impl LiveTree {
+    // Starts with an empty placeholder per root, named as the final entries will be.
+    pub fn new(mut roots: Vec<DirEntry>) -> LiveTree {
+        if roots.len() == 1 {
+            LiveTree { tree: roots.remove(0), multiple_roots: false, elapsed: None, rescanning: false, rescanned: None, version: 0 }
+        } else {
+            LiveTree {
+                tree: crate::virtual_root(roots),
+                multiple_roots: true,
+                elapsed: None,
+                rescanning: false,
+                rescanned: None,
+                version: 0,
+            }
+        }
+    }
+
+    pub fn tree(&self) -> &DirEntry {
+        &self.tree
+    }
+
+    pub fn has_multiple_roots(&self) -> bool {
+        self.multiple_roots
+    }
+
+    // Takes the entry at `path` below the root out of the tree, once it is deleted, and
+    // subtracts its totals from every directory above it.
+    pub fn remove(&mut self, path: &[String]) -> Option<DirEntry> {
+        self.version += 1;
+        remove(&mut self.tree, path)
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed.is_some() && !self.rescanning
+    }
+
+    // Keeps the tree until the scan started again sends the new one, or the directory
+    // at `directory` below the root if only that is scanned again.
+    pub fn rescan(&mut self, directory: Option<Vec<String>>) {
+        self.rescanning = true;
+        self.rescanned = directory;
+    }
+
+    pub fn is_rescanning(&self) -> bool {
+        self.rescanning
+    }
+
+    pub fn rescanned(&self) -> Option<&Vec<String>> {
+        self.rescanned.as_ref()
+    }
+
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.elapsed
+    }
+
+    pub fn handle(&mut self, event: ScanEvent) {
+        self.version += 1;
+        match event {
+            ScanEvent::Directory { root, path, entry } if self.elapsed.is_none() => self.apply(root, &path, entry),
+            ScanEvent::Directory { .. } => {}
+            ScanEvent::Finished { tree, elapsed } => {
+                self.tree = tree;
+                self.elapsed = Some(elapsed);
+                self.rescanning = false;
+                self.rescanned = None;
+            }
+            ScanEvent::Rescanned { path, entry, elapsed } => {
+                match entry {
+                    Some(entry) => {
+                        replace(&mut self.tree, &path, entry);
+                    }
+                    None => {
+                        remove(&mut self.tree, &path);
+                    }
+                }
+                self.elapsed = Some(elapsed);
+                self.rescanning = false;
+            }
+        }
+    }
+
+    fn apply(&mut self, root: usize, path: &[String], entry: DirEntry) {
+        if self.multiple_roots {
+            let Some(node) = self.tree.children.get_mut(root) else { return };
+            let delta = insert(node, path, entry);
+            delta.add_to(&mut self.tree);
+        } else {
+            insert(&mut self.tree, path, entry);
+        }
+    }
+}
+
+// A copy of a finished directory for the channel: its children are kept, theirs dropped.
+pub fn shallow_copy(entry: &DirEntry) -> DirEntry {
+    DirEntry {
+        children: entry.children.iter().map(without_children).collect(),
+        ..without_children(entry)
+    }
+}
+
+fn without_children(entry: &DirEntry) -> DirEntry {
+    DirEntry { name: entry.name.clone(), link_target: entry.link_target.clone(), children: Vec::new(), ..*entry }
+}
+
+// Places a finished directory at `path` below `node`, creating placeholders on the way,
+// and returns how much the totals of `node` changed.
+fn insert(node: &mut DirEntry, path: &[String], finished: DirEntry) -> Delta {
+    let Some((first, rest)) = path.split_first() else {
+        let delta = Delta::between(node, &finished);
+        merge(node, finished);
+        return delta;
+    };
+
+    let (index, created) = match node.children.iter().position(|child| child.name == *first) {
+        Some(index) => (index, false),
+        None => {
+            node.children.push(DirEntry { name: first.clone(), is_dir: true, dir_count: 1, inodes: 1, ..Default::default() });
+            (node.children.len() - 1, true)
+        }
+    };
+    let mut delta = insert(&mut node.children[index], rest, finished);
+    if created {
+        delta.dir_count += 1;
+        delta.inodes += 1;
+    }
+    delta.add_to(node);
+    delta
+}
+
+fn remove(node: &mut DirEntry, path: &[String]) -> Option<DirEntry> {
+    let (first, rest) = path.split_first()?;
+    let index = node.children.iter().position(|child| child.name == *first)?;
+    let removed = if rest.is_empty() {
+        node.children.remove(index)
+    } else {
+        remove(&mut node.children[index], rest)?
+    };
+    Delta::between(&removed, &DirEntry::default()).add_to(node);
+    Some(removed)
+}
+
+// Puts `entry` in the place of the entry at `path` below `node`, which keeps its name,
+// and returns how much the totals of `node` changed.
+fn replace(node: &mut DirEntry, path: &[String], entry: DirEntry) -> Option<Delta> {
+    let (first, rest) = path.split_first()?;
+    let child = node.children.iter_mut().find(|child| child.name == *first)?;
+    let delta = if rest.is_empty() {
+        let entry = DirEntry {
+            name: std::mem::take(&mut child.name),
+            is_symlink: child.is_symlink,
+            link_target: child.link_target.take(),
+            ..entry
+        };
+        let delta = Delta::between(child, &entry);
+        *child = entry;
+        delta
+    } else {
+        replace(child, rest, entry)?
+    };
+    delta.add_to(node);
+    Some(delta)
+}
+
+// Replaces a placeholder by the finished directory, keeping the subdirectories that
+// already arrived (with their own children) instead of the shallow copies.
+fn merge(node: &mut DirEntry, finished: DirEntry) {
+    let mut arrived: HashMap<String, DirEntry> = std::mem::take(&mut node.children)
+        .into_iter()
+        .map(|child| (child.name.clone(), child))
+        .collect();
+    let children = finished.children.into_iter()
+        .map(|child| arrived.remove(&child.name).unwrap_or(child))
+        .collect();
+    // The name stays: roots are named by their command-line path rather than file name
+    *node = DirEntry { name: std::mem::take(&mut node.name), children, ..finished };
+}
+
+// Change in the totals of a subtree, applied to each of its placeholder ancestors.
+struct Delta {
+    apparent_size: i64,
+    disk_usage: i64,
+    file_count: i64,
+    dir_count: i64,
+    inodes: i64,
+    mtime: Option<std::time::SystemTime>,
+}
+
+impl Delta {
+    fn between(old: &DirEntry, new: &DirEntry) -> Delta {
+        let diff = |old: u64, new: u64| new as i64 - old as i64;
+        Delta {
+            apparent_size: diff(old.apparent_size, new.apparent_size),
+            disk_usage: diff(old.disk_usage, new.disk_usage),
+            file_count: diff(old.file_count, new.file_count),
+            dir_count: diff(old.dir_count, new.dir_count),
+            inodes: diff(old.inodes, new.inodes),
+            mtime: new.mtime,
+        }
+    }
+
+    fn add_to(&self, entry: &mut DirEntry) {
+        entry.apparent_size = entry.apparent_size.saturating_add_signed(self.apparent_size);
+        entry.disk_usage = entry.disk_usage.saturating_add_signed(self.disk_usage);
+        entry.file_count = entry.file_count.saturating_add_signed(self.file_count);
+        entry.dir_count = entry.dir_count.saturating_add_signed(self.dir_count);
+        entry.inodes = entry.inodes.saturating_add_signed(self.inodes);
+        entry.mtime = entry.mtime.max(self.mtime);
+    }
+}
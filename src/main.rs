@@ -1,215 +1,5790 @@
 use std::env;
 use std::fs;
-use std::path::Path; 
+use std::path::{Path, PathBuf}; 
 use std::io;
-use std::sync::{Arc, Mutex};
-use std::collections::HashSet;
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use std::error::Error;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
 
 // TUI Imports (Phase 3: Visualization)
 use ratatui::prelude::*;
-use ratatui::widgets::{Block, Borders, List, ListItem, ListState};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap};
 use ratatui::layout::Margin; 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    cursor,
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers, MouseButton, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
+use chrono::{DateTime, Local, NaiveDate};
+
 // Phase 1: Concurrency (Rayon)
 use rayon::prelude::*; 
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 // Phase 2: Hardlink Analysis (Same-File API)
+#[cfg(not(unix))]
 use same_file::Handle;
 
-// Type alias for the unique file identifier (Inode/Device Handle).
-type FileId = Handle; 
+mod app;
+mod bookmarks;
+mod breakdown;
+mod clipboard;
+mod config;
+mod diff;
+mod dirfd;
+mod du;
+mod duplicates;
+mod gitignore;
+mod glob;
+mod hardlinks;
+mod icons;
+mod json;
+mod keys;
+mod live;
+mod print;
+mod seen;
+mod snapshot;
+mod table;
+mod theme;
+mod trash;
+mod treemap;
+
+use app::{App, Cell, Screen, TreeRow};
+use gitignore::Gitignore;
+use hardlinks::{HardlinkPolicy, LinkedFiles};
+use keys::{Action, Keymap};
+use breakdown::Breakdown;
+use dirfd::Dir;
+use duplicates::Duplicates;
+use live::{LiveTree, ScanEvent};
+use seen::SeenSet;
+use theme::Theme;
+use treemap::Direction;
+
+// Unique file identifier. On Unix the (device, inode) pair from the metadata we already
+// have; elsewhere a same-file Handle, which keeps the file open while it is tracked.
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = Handle;
 
 // Data structure for TUI visualization
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct DirEntry {
     name: String,
-    size: u64,
+    // Byte count as reported by metadata.len()
+    apparent_size: u64,
+    // Space actually allocated on disk (st_blocks * 512 on Unix)
+    disk_usage: u64,
     children: Vec<DirEntry>,
+    // Directory, mount point or cache, even one listed without children; empty
+    // directories and files both have none, so this is what tells them apart
+    is_dir: bool,
+    is_symlink: bool,
+    // Where a symlink points, as written in the link (not resolved), and whether nothing
+    // is there
+    link_target: Option<String>,
+    broken_link: bool,
+    // Mount point on another filesystem that was excluded by --one-file-system
+    skipped_mount: bool,
+    // Cache directory (tagged with CACHEDIR.TAG) left out by --exclude-caches
+    skipped_cache: bool,
+    // Mount point of a pseudo-filesystem (/proc, /sys, ...), skipped unless --include-virtual
+    skipped_virtual: bool,
+    // Children were folded into `size` because of --max-depth
+    truncated: bool,
+    // Reading this entry (or some of its children) failed, so its size is a lower bound
+    incomplete: bool,
+    // Non-directory entries in this subtree (hardlinks and symlinks included)
+    file_count: u64,
+    // Directories in this subtree, counting the entry itself
+    dir_count: u64,
+    // Filesystem objects (inodes) in this subtree: like the sizes, a file with several
+    // hard links counts once, and entries that are skipped or filtered out not at all
+    inodes: u64,
+    // Last modification; for directories the newest time anywhere in the subtree
+    // (the directory's own mtime included), so a directory looks as fresh as its
+    // most recently changed content. None when the metadata could not be read.
+    mtime: Option<SystemTime>,
+    // Set for device nodes, FIFOs and sockets, which count as 0 bytes unless --count-special
+    special: Option<SpecialKind>,
+    // Regular file whose allocated size is well below its length (holes never written)
+    sparse: bool,
+    // Synthetic row standing in for this many siblings hidden by --min-size (0 for real entries)
+    pruned: u64,
+    // Hard link count of a file (0 where the platform does not report it)
+    links: u64,
+    // Set on a hard link that does not carry the file's whole size (see --hardlinks): the
+    // file's real (apparent, disk) sizes, of which this link counts nothing or a part
+    shared: Option<(u64, u64)>,
+    // File recorded in the hard link table (see tracks_links), whose size is settled once
+    // the scan has found all of its links
+    linked: bool,
+    // Zero-length regular files in this subtree, by apparent length (so every link of an
+    // empty hard-linked file counts, and a sparse file with a length does not)
+    empty_files: u64,
+    // Directory with no files anywhere below it, nothing filtered out of it and nothing
+    // skipped (mount points, caches, unreadable entries), so rmdir could remove it
+    empty: bool,
+    // Uid of a regular file's owner, for the TUI's per-user view (None for everything
+    // else, and where the platform has no uids)
+    owner: Option<u32>,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // --- Argument Handling ---
-    let args: Vec<String> = env::args().collect();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpecialKind {
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <path>", args[0]);
-        std::process::exit(1);
+impl SpecialKind {
+    #[cfg(unix)]
+    fn from_file_type(file_type: &fs::FileType) -> Option<SpecialKind> {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_char_device() {
+            Some(SpecialKind::CharDevice)
+        } else if file_type.is_block_device() {
+            Some(SpecialKind::BlockDevice)
+        } else if file_type.is_fifo() {
+            Some(SpecialKind::Fifo)
+        } else if file_type.is_socket() {
+            Some(SpecialKind::Socket)
+        } else {
+            None
+        }
     }
-    let path = Path::new(&args[1]);
 
-    if !path.exists() {
-        eprintln!("Error: Path not found: {}", path.display());
-        std::process::exit(1);
+    #[cfg(not(unix))]
+    fn from_file_type(_file_type: &fs::FileType) -> Option<SpecialKind> {
+        None
     }
 
-    // Phase 2: Shared State Setup (Arc/Mutex for safe concurrent access to file tracker)
-    let files_seen: Arc<Mutex<HashSet<FileId>>> = Arc::new(Mutex::new(HashSet::new()));
+    fn label(self) -> &'static str {
+        match self {
+            SpecialKind::CharDevice => "char device",
+            SpecialKind::BlockDevice => "block device",
+            SpecialKind::Fifo => "fifo",
+            SpecialKind::Socket => "socket",
+        }
+    }
+}
 
-    // Phase 1 & 3: Build the tree structure concurrently
-    let root_entry = match calculate_tree(path, Arc::clone(&files_seen)) {
-        Ok(entry) => entry,
-        Err(e) => {
-            eprintln!("Error during traversal: {}", e);
-            std::process::exit(1);
+// Which of the metrics collected for every entry is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SizeMode {
+    // Allocated blocks, matching `du` and ncdu
+    #[default]
+    DiskUsage,
+    // File lengths, as `du --apparent-size` reports them
+    Apparent,
+    // Number of inodes rather than bytes, for filesystems that run out of those first
+    Inodes,
+}
+
+impl SizeMode {
+    fn label(self) -> &'static str {
+        match self {
+            SizeMode::DiskUsage => "disk usage",
+            SizeMode::Apparent => "apparent",
+            SizeMode::Inodes => "inodes",
         }
-    };
-    
-    // Phase 3: Run the visual TUI interface
-    run_tui(&root_entry)?;
+    }
+}
 
-    Ok(())
+impl DirEntry {
+    fn size(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::DiskUsage => self.disk_usage,
+            SizeMode::Apparent => self.apparent_size,
+            SizeMode::Inodes => self.inodes,
+        }
+    }
 }
 
-// --- CORE SYSTEM FUNCTION: Concurrent Tree Calculation ---
+// Presentation settings for the tree view that can be changed while the TUI runs.
+#[derive(Debug, Clone, Copy)]
+struct DisplayOptions {
+    size_mode: SizeMode,
+    show_mtime: bool,
+    // Color rows by how recently they were modified instead of by size
+    color_by_age: bool,
+    // None while every child is listed
+    fold: Option<Fold>,
+    sort: SortOrder,
+    // Whether the shares are of the whole tree rather than of each entry's directory ('P')
+    share_of_root: bool,
+    // Whether the sort order is turned around ('S'), like smallest first
+    reverse_sort: bool,
+    bars: Bars,
+    // --ascii, or a locale that is not UTF-8: draw everything with ASCII characters only
+    ascii: bool,
+    // --icons: a Nerd Font icon before each name, unless everything is ASCII
+    icons: bool,
+    // Leave out the rows of entries whose names start with a dot ('.'); they still count
+    hide_dotfiles: bool,
+    // Show sizes as exact byte counts instead of rounding them (--bytes, 'b')
+    exact_bytes: bool,
+    // Round sizes in powers of 1000 instead of 1024 (--si, 'U')
+    si: bool,
+    theme: &'static Theme,
+    size_colors: SizeColors,
+}
 
-// Recursively calculates the data structure, leveraging Rayon for parallelism.
-fn calculate_tree(path: &Path, files_seen: Arc<Mutex<HashSet<FileId>>>) -> io::Result<DirEntry> {
-    
-    // Base case: Handle single files using Phase 2 logic (Hardlink Analysis)
-    if path.is_file() {
-        let size = get_dir_size_unique_file(path, files_seen)?;
-        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
-        return Ok(DirEntry { name, size, children: Vec::new() });
+impl DisplayOptions {
+    fn units(&self) -> Units {
+        Units::new(self.exact_bytes, self.si)
     }
-    
-    if !path.is_dir() {
-        return Ok(DirEntry { name: path.to_string_lossy().into_owned(), size: 0, children: Vec::new() });
+
+    // Whether the row of `child`, listed in the directory at `path`, is left out. The
+    // roots are never hidden: they are named by their command-line path.
+    fn hides(&self, child: &DirEntry, path: &[String], multiple_roots: bool) -> bool {
+        self.hides_at(child, path.is_empty(), multiple_roots)
     }
 
-    // 1. Sequential I/O: Collect immediate children paths
-    let mut child_paths = Vec::new();
-    for entry in fs::read_dir(path)? {
-        if let Ok(entry) = entry {
-            child_paths.push(entry.path());
+    // The same, for a directory that `top` says is the root.
+    fn hides_at(&self, child: &DirEntry, top: bool, multiple_roots: bool) -> bool {
+        self.hide_dotfiles && child.name.starts_with('.') && !(multiple_roots && top)
+    }
+
+    // Order of the entries of a directory in the tree view.
+    fn compare(&self, a: &DirEntry, b: &DirEntry) -> std::cmp::Ordering {
+        self.sort.compare(a, b, self.size_mode, self.reverse_sort)
+    }
+
+    // The sort order as the bottom line names it, with an arrow for its direction.
+    fn sort_label(&self) -> String {
+        let descending = self.sort.descending() != self.reverse_sort;
+        let glyphs = self.glyphs();
+        format!("{} {}", self.sort.label(), if descending { glyphs.descending } else { glyphs.ascending })
+    }
+
+    fn glyphs(&self) -> &'static Glyphs {
+        if self.ascii { &ASCII_GLYPHS } else { &UNICODE_GLYPHS }
+    }
+
+    // A block with the given borders, drawn with the glyphs' lines in the theme's border
+    // style.
+    fn block(&self, borders: Borders) -> Block<'static> {
+        Block::default().borders(borders).border_set(self.glyphs().border).border_style(self.theme.border)
+    }
+}
+
+// Characters the TUI is drawn with.
+struct Glyphs {
+    branch: &'static str,
+    last_branch: &'static str,
+    // Below a directory that has more entries after the current one
+    continuation: &'static str,
+    expanded: &'static str,
+    collapsed: &'static str,
+    bar: &'static str,
+    // Between the directories of the breadcrumb above the tree
+    separator: &'static str,
+    // After the sort order on the bottom line: largest, last or newest first, or the other
+    // way around
+    descending: &'static str,
+    ascending: &'static str,
+    // Where text is cut short, like a long name or path
+    ellipsis: &'static str,
+    // At the end of what is being typed on the prompt line
+    cursor: &'static str,
+    // Before each age in the legend of the age colors ('H')
+    swatch: &'static str,
+    // Lines and corners of borders
+    border: ratatui::symbols::border::Set,
+}
+
+const UNICODE_GLYPHS: Glyphs = Glyphs {
+    branch: "├── ",
+    last_branch: "└── ",
+    continuation: "│   ",
+    expanded: "▾ ",
+    collapsed: "▸ ",
+    bar: "█",
+    separator: " ▸ ",
+    descending: "↓",
+    ascending: "↑",
+    ellipsis: "…",
+    cursor: "█",
+    swatch: "■",
+    border: ratatui::symbols::border::PLAIN,
+};
+
+const ASCII_GLYPHS: Glyphs = Glyphs {
+    branch: "|-- ",
+    last_branch: "`-- ",
+    continuation: "|   ",
+    expanded: "- ",
+    collapsed: "+ ",
+    bar: "#",
+    separator: " > ",
+    descending: "v",
+    ascending: "^",
+    ellipsis: "...",
+    cursor: "_",
+    swatch: "#",
+    border: ratatui::symbols::border::Set {
+        top_left: "+",
+        top_right: "+",
+        bottom_left: "+",
+        bottom_right: "+",
+        vertical_left: "|",
+        vertical_right: "|",
+        horizontal_top: "-",
+        horizontal_bottom: "-",
+    },
+};
+
+// Whether the locale says the terminal takes UTF-8, by the first of LC_ALL, LC_CTYPE
+// and LANG that is set. Without any of them nothing is known, so Unicode stays.
+fn utf8_locale() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .filter_map(std::env::var_os)
+        .find(|value| !value.is_empty())
+        .is_none_or(|locale| {
+            let locale = locale.to_string_lossy().to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+// What each row of the tree view shows of its share of the directory it is in, or of the
+// whole tree with 'P' ('p').
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Bars {
+    // A bar and the percentage
+    #[default]
+    Bar,
+    Percent,
+    Off,
+}
+
+impl Bars {
+    fn next(self) -> Bars {
+        match self {
+            Bars::Bar => Bars::Percent,
+            Bars::Percent => Bars::Off,
+            Bars::Off => Bars::Bar,
         }
     }
+}
 
-    // 2. Parallel Processing (Rayon): Recursively calculate children concurrently
-    let children_results: Vec<DirEntry> = child_paths.par_iter().filter_map(|child_path| {
-        calculate_tree(child_path, Arc::clone(&files_seen)).ok()
-    }).collect();
+// How the entries of each directory are ordered in the tree view ('s' cycles through them).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SortOrder {
+    // Largest first
+    #[default]
+    Size,
+    // Natural order ignoring case, so "file2" comes before "file10"
+    Name,
+    // Most files and directories (counting the entry itself) first
+    Count,
+    // Least recently modified first (the newest change below a directory), entries
+    // without a readable time last
+    Age,
+}
 
-    // 3. Aggregate size
-    let total_size = children_results.iter().map(|c| c.size).sum();
+impl SortOrder {
+    fn next(self) -> SortOrder {
+        match self {
+            SortOrder::Size => SortOrder::Name,
+            SortOrder::Name => SortOrder::Count,
+            SortOrder::Count => SortOrder::Age,
+            SortOrder::Age => SortOrder::Size,
+        }
+    }
 
-    let name = path.file_name()
-        .map(|os_str| os_str.to_string_lossy().into_owned())
-        .unwrap_or_else(|| path.to_string_lossy().into_owned());
+    fn label(self) -> &'static str {
+        match self {
+            SortOrder::Size => "size",
+            SortOrder::Name => "name",
+            SortOrder::Count => "item count",
+            SortOrder::Age => "modification time",
+        }
+    }
+
+    // Whether the order puts the larger values first, unless it is reversed ('S').
+    fn descending(self) -> bool {
+        matches!(self, SortOrder::Size | SortOrder::Count)
+    }
 
-    Ok(DirEntry { name, size: total_size, children: children_results })
+    // Ties are broken by name, so the order does not change between frames. `reverse`
+    // turns the order around ('S') but leaves the ties in the order of their names, and
+    // the entries without a time last.
+    fn compare(self, a: &DirEntry, b: &DirEntry, mode: SizeMode, reverse: bool) -> std::cmp::Ordering {
+        let by_name = || natural_cmp(&a.name, &b.name);
+        let flip = |ordering: std::cmp::Ordering| if reverse { ordering.reverse() } else { ordering };
+        match self {
+            SortOrder::Size => flip(b.size(mode).cmp(&a.size(mode))).then_with(by_name),
+            SortOrder::Name => flip(by_name()),
+            SortOrder::Count => flip((b.file_count + b.dir_count).cmp(&(a.file_count + a.dir_count))).then_with(by_name),
+            SortOrder::Age => match (a.mtime, b.mtime) {
+                (Some(a), Some(b)) => flip(a.cmp(&b)),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            }
+            .then_with(by_name),
+        }
+    }
 }
 
-// Phase 2: Hardlink Analysis (Apparent Size Logic)
-fn get_dir_size_unique_file(path: &Path, files_seen: Arc<Mutex<HashSet<FileId>>>) -> io::Result<u64> {
-    
-    // Get unique system file handle (Inode/Device)
-    let file_handle = match same_file::Handle::from_path(path) {
-        Ok(handle) => handle,
-        Err(_) => return Ok(0), 
-    };
-    
-    // Safely lock the shared set
-    let mut seen = files_seen.lock().unwrap();
-    
-    // Count size only if the handle is new (deduplication)
-    if seen.insert(file_handle) {
-        let metadata = fs::metadata(path)?;
-        return Ok(metadata.len());
-    } else {
-        return Ok(0); // Hardlink: Size is 0
+// Compares names case-insensitively, with runs of digits compared by their value.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut left, mut right) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let ordering = match (left.peek().copied(), right.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(digit);
+                    }
+                    digits.trim_start_matches('0').to_string()
+                };
+                let (x, y) = (take_number(&mut left), take_number(&mut right));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(&y))
+            }
+            (Some(x), Some(y)) => {
+                left.next();
+                right.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
     }
 }
 
-// --- TUI RENDERING LOGIC (Phase 3) ---
+// Sizes above which rows of the tree are colored as medium and large (--color-threshold-warn,
+// --color-threshold-crit); 0 leaves that color out.
+#[derive(Debug, Clone, Copy)]
+struct SizeColors {
+    warn: u64,
+    crit: u64,
+}
 
-fn format_size(bytes: u64) -> String {
-    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
-    let mut size = bytes as f64;
-    let mut unit_index = 0;
+impl Default for SizeColors {
+    fn default() -> SizeColors {
+        SizeColors { warn: 10_000_000, crit: 50_000_000 }
+    }
+}
 
-    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_index += 1;
+// When the children of a directory are folded into one "(N other items)" row.
+#[derive(Debug, Clone, Copy)]
+struct Fold {
+    // Only the largest this many children are listed
+    max_children: Option<usize>,
+    // Children below this percentage of their directory's size are folded
+    min_percent: Option<f64>,
+}
+
+impl Fold {
+    // What 'z' turns on when neither --fold nor --fold-below was given
+    const DEFAULT: Fold = Fold { max_children: Some(50), min_percent: None };
+}
+
+// What deleting an entry from the TUI does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Deletion {
+    // Move it to the trash
+    Trash,
+    // Delete it for good (--delete-permanently)
+    Permanent,
+    // Nothing; 'd' is disabled (--read-only)
+    Disabled,
+}
+
+// How the TUI takes input and reports progress, apart from what it displays.
+#[derive(Debug, Clone)]
+struct TuiOptions {
+    quiet: bool,
+    deletion: Deletion,
+    // Whether mouse clicks and the wheel are captured (not with --no-mouse)
+    mouse: bool,
+    keymap: Keymap,
+    // When the snapshot shown was made (--load-snapshot); its tree is not scanned again
+    snapshot: Option<SystemTime>,
+}
+
+// Command-line options controlling the scan.
+#[derive(Debug)]
+struct Options {
+    paths: Vec<PathBuf>,
+    follow_symlinks: bool,
+    // --follow-symlinks=within-root: only links resolving inside their root are followed
+    symlinks_within_root: bool,
+    one_file_system: bool,
+    excludes: Vec<glob::Pattern>,
+    exclude_regexes: Vec<Regex>,
+    include_regexes: Vec<Regex>,
+    respect_gitignore: bool,
+    max_depth: Option<usize>,
+    size_mode: SizeMode,
+    // --bytes: print sizes as exact byte counts, and start the TUI showing them
+    bytes: bool,
+    // --si: round sizes in powers of 1000 (kB, MB) instead of 1024 (KiB, MiB)
+    si: bool,
+    quiet: bool,
+    // Draw the TUI with ASCII characters instead of box-drawing ones
+    ascii: bool,
+    // --icons: show Nerd Font icons by the names in the TUI
+    icons: bool,
+    // --theme; None picks one from the environment
+    theme: Option<&'static Theme>,
+    // --config: the config file to read instead of the default one
+    config: Option<PathBuf>,
+    size_colors: SizeColors,
+    // What 'd' does in the TUI
+    deletion: Deletion,
+    // --no-mouse: leave the mouse to the terminal
+    mouse: bool,
+    count_special: bool,
+    exclude_caches: bool,
+    include_virtual: bool,
+    skip_hidden: bool,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    min_size: Option<u64>,
+    fold: Option<Fold>,
+    // Print per-owner / per-extension reports instead of opening the TUI
+    by_owner: bool,
+    by_extension: bool,
+    // Hash files after the scan to find identical ones
+    find_duplicates: bool,
+    // List the empty directories instead of opening the TUI, NUL-separated with `null`
+    empty_dirs: bool,
+    null: bool,
+    // Print the symlinks whose target does not exist instead of opening the TUI
+    broken_links: bool,
+    // Count the space taken by directories themselves, like du (off with --no-dir-sizes)
+    dir_sizes: bool,
+    // Keep at most this many children per directory, folding the rest into one entry
+    max_children: Option<usize>,
+    // Rank directories by their number of zero-length files instead of opening the TUI
+    empty_files: bool,
+    // Write the tree as JSON instead of opening the TUI, indented with --pretty
+    json: bool,
+    pretty: bool,
+    // Write each entry as a line of JSON as soon as it is scanned instead of opening the TUI
+    ndjson: bool,
+    // Write a row per entry as CSV or TSV instead of opening the TUI, leaving out the
+    // directories with --files-only
+    table: Option<table::Format>,
+    files_only: bool,
+    // Write the tree as indented text instead of opening the TUI, colored as --color says
+    print: bool,
+    color: print::ColorWhen,
+    // Write what du would instead of opening the TUI, with its -a, -h and -s
+    du: Option<du::Options>,
+    // -s without --du: print the size of each root instead of opening the TUI, and with
+    // --total that of all of them
+    summary: bool,
+    total: bool,
+    // Print the n largest files instead of opening the TUI
+    top: Option<usize>,
+    // Write the scan to this file instead of opening the TUI, or open the TUI on one
+    // written earlier without scanning
+    save_snapshot: Option<PathBuf>,
+    load_snapshot: Option<PathBuf>,
+    // Compare the two snapshots given as the paths instead of scanning, leaving out
+    // changes below the threshold
+    diff: bool,
+    threshold: u64,
+    hardlinks: HardlinkPolicy,
+    // Size of the scan's thread pool; None lets rayon decide (RAYON_NUM_THREADS or one per core)
+    threads: Option<usize>,
+}
+
+// Shared state handed to every (parallel) step of the traversal.
+struct ScanContext {
+    // Files with several links; shared by all roots, so a hardlink spanning two of them
+    // is still counted once
+    linked_files: LinkedFiles,
+    hardlinks: HardlinkPolicy,
+    // Directories already descended into; only populated when following symlinks,
+    // since that is the only way the traversal can revisit a directory.
+    dirs_seen: SeenSet<FileId>,
+    follow_symlinks: bool,
+    symlinks_within_root: bool,
+    excludes: Vec<glob::Pattern>,
+    exclude_regexes: Vec<Regex>,
+    include_regexes: Vec<Regex>,
+    excluded_count: AtomicU64,
+    // Only files modified after `newer_than` and before `older_than` are counted
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    // --no-hidden: dot entries are skipped (never the roots themselves)
+    skip_hidden: bool,
+    hidden_count: AtomicU64,
+    respect_gitignore: bool,
+    // Deepest level whose entries keep their children (the root is level 0)
+    max_depth: Option<usize>,
+    // --max-children-per-dir: the largest children kept in each directory
+    max_children: Option<usize>,
+    errors: Mutex<Vec<ScanError>>,
+    // Symlinks pointing at nothing, for --broken-links and the TUI's list of them
+    broken_links: Mutex<Vec<BrokenLink>>,
+    size_mode: SizeMode,
+    // Shared with the contexts of single-directory rescans, whose progress the TUI shows
+    progress: Arc<Progress>,
+    // Set by the Ctrl+C handler; workers stop reading the filesystem once it is raised
+    cancelled: Arc<AtomicBool>,
+    count_special: bool,
+    dir_sizes: bool,
+    exclude_caches: bool,
+    // Devices of the pseudo-filesystems (proc, sysfs, ...) that are not descended into;
+    // empty with --include-virtual
+    virtual_devices: HashSet<u64>,
+    // Number of sparse files and the sum of their (apparent - allocated) bytes
+    sparse_files: AtomicU64,
+    sparse_excess: AtomicU64,
+    // Finished directories go to the TUI, which shows the tree while it is being built
+    // (None when there is no TUI)
+    updates: Option<mpsc::Sender<ScanEvent>>,
+    // Per-owner totals (by uid), for --by-owner and the TUI's per-user view
+    owners: Option<Breakdown<u32>>,
+    // Files to compare for --find-duplicates
+    duplicates: Option<Duplicates>,
+    // Directories found empty, for --empty-dirs
+    empty_dirs: Option<Mutex<Vec<PathBuf>>>,
+    // Directories holding zero-length files and how many, for --empty-files
+    empty_files: Option<Mutex<Vec<(PathBuf, u64)>>>,
+    // Totals per lowercased file extension, for --by-extension and the TUI's extension view
+    extensions: Breakdown<String>,
+    // Every entry is written to stdout as soon as it is done, for --ndjson
+    ndjson: Option<json::Lines>,
+}
+
+impl ScanContext {
+    // Hands an entry the scan is done with, found at `path` `depth` levels below its root,
+    // to --ndjson.
+    fn finished(&self, path: &Path, entry: &DirEntry, depth: usize) {
+        if let Some(lines) = &self.ndjson {
+            lines.entry(path, entry, depth);
+        }
+    }
+
+    fn stats(&self) -> ScanStats {
+        ScanStats {
+            entries: self.progress.entries.load(Ordering::Relaxed),
+            bytes: self.progress.bytes.load(Ordering::Relaxed),
+            files: self.progress.files.load(Ordering::Relaxed),
+            directories: self.progress.directories.load(Ordering::Relaxed),
+            symlinks: self.progress.symlinks.load(Ordering::Relaxed),
+            hardlinks: self.progress.hardlinks.load(Ordering::Relaxed),
+            excluded: self.excluded_count.load(Ordering::Relaxed),
+            hidden: self.hidden_count.load(Ordering::Relaxed),
+            errors: self.errors.lock().unwrap().len(),
+            broken_links: self.broken_links.lock().unwrap().len(),
+            cancelled: self.cancelled.load(Ordering::SeqCst),
+        }
+    }
+
+    // Forgets everything the last scan found, so that scanning the roots again ('r' in the
+    // TUI) counts hard links, totals and errors afresh.
+    fn reset(&self) {
+        self.linked_files.clear();
+        self.dirs_seen.clear();
+        self.excluded_count.store(0, Ordering::Relaxed);
+        self.hidden_count.store(0, Ordering::Relaxed);
+        self.errors.lock().unwrap().clear();
+        self.broken_links.lock().unwrap().clear();
+        self.progress.reset();
+        self.cancelled.store(false, Ordering::SeqCst);
+        self.sparse_files.store(0, Ordering::Relaxed);
+        self.sparse_excess.store(0, Ordering::Relaxed);
+        if let Some(owners) = &self.owners {
+            owners.clear();
+        }
+        if let Some(duplicates) = &self.duplicates {
+            duplicates.clear();
+        }
+        if let Some(empty_dirs) = &self.empty_dirs {
+            empty_dirs.lock().unwrap().clear();
+        }
+        if let Some(empty_files) = &self.empty_files {
+            empty_files.lock().unwrap().clear();
+        }
+        self.extensions.clear();
+    }
+
+    // A context for scanning one directory again ('R' in the TUI): the same settings and
+    // progress counters, but totals of its own, so that the per-user, per-extension and
+    // duplicate totals of the whole tree are not counted again.
+    fn for_rescan(&self) -> ScanContext {
+        ScanContext {
+            linked_files: LinkedFiles::new(),
+            hardlinks: self.hardlinks,
+            dirs_seen: SeenSet::new(),
+            follow_symlinks: self.follow_symlinks,
+            symlinks_within_root: self.symlinks_within_root,
+            excludes: self.excludes.clone(),
+            exclude_regexes: self.exclude_regexes.clone(),
+            include_regexes: self.include_regexes.clone(),
+            excluded_count: AtomicU64::new(0),
+            newer_than: self.newer_than,
+            older_than: self.older_than,
+            skip_hidden: self.skip_hidden,
+            hidden_count: AtomicU64::new(0),
+            respect_gitignore: self.respect_gitignore,
+            max_depth: self.max_depth,
+            max_children: self.max_children,
+            errors: Mutex::new(Vec::new()),
+            broken_links: Mutex::new(Vec::new()),
+            size_mode: self.size_mode,
+            progress: Arc::clone(&self.progress),
+            cancelled: Arc::clone(&self.cancelled),
+            count_special: self.count_special,
+            dir_sizes: self.dir_sizes,
+            exclude_caches: self.exclude_caches,
+            virtual_devices: self.virtual_devices.clone(),
+            sparse_files: AtomicU64::new(0),
+            sparse_excess: AtomicU64::new(0),
+            updates: None,
+            owners: None,
+            duplicates: None,
+            empty_dirs: None,
+            empty_files: None,
+            ndjson: None,
+            extensions: Breakdown::new(),
+        }
     }
-    format!("{:.1} {}", size, UNITS[unit_index])
 }
 
-fn build_list_items(entry: &DirEntry, items: &mut Vec<ListItem>, level: usize) {
-    let size_unit = format_size(entry.size);
-    let prefix = "  ".repeat(level);
-    
-    let color = if level == 0 {
-        Color::Yellow
-    } else if entry.size > 50_000_000 { 
-        Color::Red
-    } else if entry.size > 10_000_000 { 
-        Color::LightYellow
+// What the TUI asks the scan thread to scan again.
+enum Rescan {
+    // All the roots ('r')
+    Everything,
+    // One directory, by its path below the root of the tree ('R')
+    Directory(Vec<String>),
+}
+
+// One of the paths given on the command line.
+struct ScanRoot {
+    // Position on the command line
+    index: usize,
+    // Used to build the relative paths that exclude patterns match against
+    path: PathBuf,
+    // Device id of the root when restricted to one filesystem
+    device: Option<u64>,
+    // The root with symlinks resolved, for --follow-symlinks=within-root
+    canonical: Option<PathBuf>,
+}
+
+// Live counters shown by the TUI while the scan runs. Plain atomics so
+// the rayon workers never wait on each other just to report progress.
+#[derive(Default)]
+struct Progress {
+    entries: AtomicU64,
+    // Regular files (every link of a hard-linked file), directories read, and symlinks
+    files: AtomicU64,
+    directories: AtomicU64,
+    symlinks: AtomicU64,
+    // Links of files already counted through another link
+    hardlinks: AtomicU64,
+    // Total of the files counted so far in the size mode (their number with --count-inodes)
+    bytes: AtomicU64,
+    // Directory most recently entered; only updated when the lock is free
+    current: Mutex<PathBuf>,
+}
+
+impl Progress {
+    fn reset(&self) {
+        for counter in [&self.entries, &self.files, &self.directories, &self.symlinks, &self.hardlinks, &self.bytes] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.current.lock().unwrap().clear();
     }
-    else {
-        Color::Green
-    };
+}
 
-    let text = format!("{}{} | {}", prefix, entry.name, size_unit);
-    items.push(ListItem::new(text).style(Style::default().fg(color)));
+// A path that could not be read during traversal.
+#[derive(Debug)]
+struct ScanError {
+    path: PathBuf,
+    kind: io::ErrorKind,
+}
 
-    for child in &entry.children {
-        build_list_items(child, items, level + 1);
+// A symlink whose target (possibly through further links) does not exist.
+#[derive(Debug)]
+struct BrokenLink {
+    path: PathBuf,
+    // Where the link points, as stored in it
+    target: PathBuf,
+}
+
+// Totals about the scan itself (not the sizes) shown alongside the tree.
+#[derive(Debug, Default)]
+struct ScanStats {
+    entries: u64,
+    bytes: u64,
+    files: u64,
+    directories: u64,
+    symlinks: u64,
+    hardlinks: u64,
+    excluded: u64,
+    hidden: u64,
+    errors: usize,
+    broken_links: usize,
+    cancelled: bool,
+}
+
+impl ScanStats {
+    // "12,345 files (2 repeated hard links), 678 directories, 9 symlinks": the headline
+    // numbers of a scan
+    fn coverage(&self) -> String {
+        let plural = |count: u64, one: &str, many: &str| {
+            format!("{} {}", format_count(count), if count == 1 { one } else { many })
+        };
+        let mut text = plural(self.files, "file", "files");
+        if self.hardlinks > 0 {
+            text.push_str(&format!(" ({})", plural(self.hardlinks, "repeated hard link", "repeated hard links")));
+        }
+        text.push_str(&format!(
+            ", {}, {}",
+            plural(self.directories, "directory", "directories"),
+            plural(self.symlinks, "symlink", "symlinks"),
+        ));
+        text
     }
 }
 
-fn run_tui(root_entry: &DirEntry) -> Result<(), Box<dyn Error>> { 
-    // Setup terminal for TUI (raw mode, alternate screen)
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+fn main() -> Result<(), Box<dyn Error>> {
+    // --- Argument Handling ---
+    let args: Vec<String> = env::args().collect();
 
-    let mut app_state = ListState::default();
-    app_state.select(Some(0));
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            // Piped to head, the help is cut short; that is not an error
+            let _ = write_usage(&mut io::stdout().lock(), &args[0]);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            let _ = write_usage(&mut io::stderr().lock(), &args[0]);
+            std::process::exit(1);
+        }
+    };
 
-    // Main TUI Loop
-    loop {
-        terminal.draw(|f| {
-            let size = f.area(); 
-            let block = Block::default()
-                .title(format!("rdu: Disk Usage of {}", root_entry.name))
-                .borders(Borders::ALL);
-            f.render_widget(block, size);
+    let units = Units::new(options.bytes, options.si);
 
-            let mut list_items = Vec::new();
-            build_list_items(root_entry, &mut list_items, 0);
+    if options.diff {
+        let [old, new] = [&options.paths[0], &options.paths[1]].map(|file| {
+            snapshot::load(file).unwrap_or_else(|e| {
+                eprintln!("Error: cannot load {}: {}", file.display(), e);
+                std::process::exit(1);
+            })
+        });
+        unless_stdout_closed(diff::print(&old, &new, options.size_mode, units, options.threshold))?;
+        return Ok(());
+    }
 
-            let list = List::new(list_items)
-                .block(Block::default().title("Directory Tree").borders(Borders::NONE))
-                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    // A snapshot has its roots and the whole tree, so nothing is read from the disk
+    let mut snapshot = options.load_snapshot.as_ref().map(|file| {
+        snapshot::load(file).unwrap_or_else(|e| {
+            eprintln!("Error: cannot load {}: {}", file.display(), e);
+            std::process::exit(1);
+        })
+    });
+
+    // A missing path among several is skipped with a warning; with a single path it is fatal
+    let mut paths = snapshot.as_ref().map(|snapshot| snapshot.roots.clone()).unwrap_or_default();
+    for path in &options.paths {
+        if path.exists() {
+            paths.push(path.clone());
+        } else if options.paths.len() > 1 {
+            eprintln!("Warning: Path not found, skipping: {}", path.display());
+        } else {
+            eprintln!("Error: Path not found: {}", path.display());
+            std::process::exit(1);
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("Error: None of the given paths exist");
+        std::process::exit(1);
+    }
 
-            f.render_stateful_widget(list, size.inner(Margin::new(1, 1)), &mut app_state);
+    // Record each root's device before traversal so mount points can be recognised
+    let roots: Vec<ScanRoot> = paths.into_iter().enumerate().map(|(index, path)| {
+        let device = if options.one_file_system {
+            fs::metadata(&path).ok().and_then(|metadata| device_id(&metadata))
+        } else {
+            None
+        };
+        let canonical = if options.symlinks_within_root { fs::canonicalize(&path).ok() } else { None };
+        ScanRoot { index, path, device, canonical }
+    }).collect();
+    if options.one_file_system && roots.iter().any(|root| root.device.is_none()) {
+        eprintln!("Warning: --one-file-system is not supported on this platform; ignoring it");
+    }
 
-        })?;
+    // A root that is itself on a pseudo-filesystem was asked for explicitly, so only the
+    // ones mounted below the roots are skipped
+    let virtual_devices = if options.include_virtual || snapshot.is_some() {
+        HashSet::new()
+    } else {
+        let mut devices = virtual_filesystem_devices();
+        for root in &roots {
+            if let Some(device) = fs::metadata(&root.path).ok().and_then(|metadata| device_id(&metadata)) {
+                devices.remove(&device);
+            }
+        }
+        devices
+    };
+
+    // Ctrl+C during the scan cancels it and shows what was gathered so far; a second
+    // Ctrl+C exits immediately. (Inside the TUI the terminal is in raw mode, so keys
+    // arrive as events instead of signals.)
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let handler_flag = Arc::clone(&cancelled);
+    if let Err(e) = ctrlc::set_handler(move || {
+        if handler_flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    }) {
+        eprintln!("Warning: cannot install Ctrl+C handler: {}", e);
+    }
+
+    // Reports are printed instead of opening the TUI (the duplicates are shown in it too),
+    // and so is the tree with --json, --ndjson, --csv, --tsv, --print or --du, or only its
+    // size with -s or its largest files with --top; --save-snapshot writes it to a file
+    let report = options.by_owner || options.by_extension || options.empty_dirs || options.broken_links || options.empty_files;
+    let tui = !report
+        && !options.json
+        && !options.ndjson
+        && options.table.is_none()
+        && !options.print
+        && options.du.is_none()
+        && !options.summary
+        && options.top.is_none()
+        && options.save_snapshot.is_none();
+
+    // Phase 2: Shared State Setup (sharded sets for safe concurrent access to file tracker)
+    let (updates, events) = mpsc::channel();
+    let ndjson = options.ndjson.then(|| json::Lines::new(io::BufWriter::new(io::stdout()), options.size_mode, options.max_depth, options.min_size, Arc::clone(&cancelled)));
+    let ctx = ScanContext {
+        linked_files: LinkedFiles::new(),
+        hardlinks: options.hardlinks,
+        dirs_seen: SeenSet::new(),
+        follow_symlinks: options.follow_symlinks,
+        symlinks_within_root: options.symlinks_within_root,
+        excludes: options.excludes,
+        exclude_regexes: options.exclude_regexes,
+        include_regexes: options.include_regexes,
+        excluded_count: AtomicU64::new(0),
+        skip_hidden: options.skip_hidden,
+        newer_than: options.newer_than,
+        older_than: options.older_than,
+        hidden_count: AtomicU64::new(0),
+        respect_gitignore: options.respect_gitignore,
+        // --ndjson writes each entry as it goes, so the tree only needs to keep the root's
+        max_depth: if options.ndjson { Some(0) } else { options.max_depth },
+        max_children: options.max_children,
+        errors: Mutex::new(Vec::new()),
+        broken_links: Mutex::new(Vec::new()),
+        size_mode: options.size_mode,
+        progress: Arc::default(),
+        cancelled,
+        count_special: options.count_special,
+        dir_sizes: options.dir_sizes,
+        exclude_caches: options.exclude_caches,
+        virtual_devices,
+        sparse_files: AtomicU64::new(0),
+        sparse_excess: AtomicU64::new(0),
+        updates: tui.then_some(updates),
+        owners: (options.by_owner || tui || options.save_snapshot.is_some()).then(Breakdown::new),
+        duplicates: options.find_duplicates.then(Duplicates::new),
+        empty_dirs: options.empty_dirs.then(|| Mutex::new(Vec::new())),
+        empty_files: options.empty_files.then(|| Mutex::new(Vec::new())),
+        extensions: Breakdown::new(),
+        ndjson,
+    };
 
-        // Event handling (Exit on 'q' or Esc)
-        if let Event::Key(key) = event::read()? {
-            if KeyCode::Char('q') == key.code || KeyCode::Esc == key.code {
-                break;
+    // An explicit pool instead of rayon's global one, so --threads 1 really is sequential
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(0))
+        .build()?;
+    // The reports only need the totals collected during the scan, not the tree
+    let started = Instant::now();
+    if report {
+        pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+        let scan_elapsed = started.elapsed();
+        // Reports after the first are separated by a blank line
+        let mut printed = false;
+        let mut section = || {
+            if printed {
+                println!();
             }
+            printed = true;
+        };
+        if let Some(owners) = &ctx.owners {
+            section();
+            // Uids without an account are shown numerically
+            let names = breakdown::user_names();
+            owners.print_report("OWNER", options.size_mode, units, |uid| owner_label(*uid, &names));
         }
+        if options.by_extension {
+            section();
+            ctx.extensions.print_report("EXTENSION", options.size_mode, units, |extension| extension_label(extension));
+        }
+        if let Some(duplicates) = &ctx.duplicates {
+            pool.install(|| duplicates.find(&ctx.cancelled));
+            section();
+            duplicates.print_report(units);
+        }
+        if options.broken_links {
+            section();
+            for link in sorted_broken_links(&ctx).iter() {
+                println!("{} -> {}", link.path.display(), link.target.display());
+            }
+        }
+        if let Some(empty_files) = &ctx.empty_files {
+            section();
+            print_empty_files(&mut empty_files.lock().unwrap());
+        }
+        if let Some(empty_dirs) = &ctx.empty_dirs {
+            print_empty_dirs(&mut empty_dirs.lock().unwrap(), options.null)?;
+        }
+        return finish(ctx, &pool, scan_elapsed, units);
     }
 
-    // Restore terminal state upon exit
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    // How the tree is shown, in the TUI or by --print
+    let display = DisplayOptions {
+        size_mode: options.size_mode,
+        show_mtime: false,
+        color_by_age: false,
+        fold: options.fold,
+        sort: SortOrder::default(),
+        share_of_root: false,
+        reverse_sort: false,
+        bars: Bars::default(),
+        ascii: options.ascii || !utf8_locale(),
+        icons: options.icons,
+        hide_dotfiles: false,
+        exact_bytes: options.bytes,
+        si: options.si,
+        theme: options.theme.unwrap_or_else(theme::detect),
+        size_colors: options.size_colors,
+    };
+
+    // The tree is written once it is complete; only the summary goes to stderr as usual
+    if options.json {
+        let tree = pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+        let scan_elapsed = started.elapsed();
+        let paths: Vec<&Path> = roots.iter().map(|root| root.path.as_path()).collect();
+        unless_stdout_closed(json::write(io::stdout().lock(), &tree, &paths, &ctx.stats(), scan_elapsed, options.size_mode, options.pretty))?;
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+    if options.ndjson {
+        let tree = pool.install(|| scan_roots(&roots, &ctx, None));
+        let scan_elapsed = started.elapsed();
+        let paths: Vec<&Path> = roots.iter().map(|root| root.path.as_path()).collect();
+        if let Some(lines) = &ctx.ndjson {
+            unless_stdout_closed(lines.end(&paths, &tree, &ctx.stats(), scan_elapsed))?;
+        }
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+    if let Some(format) = options.table {
+        let tree = pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+        let scan_elapsed = started.elapsed();
+        unless_stdout_closed(table::write(io::stdout().lock(), &tree, roots.len() > 1, format, options.size_mode, options.files_only))?;
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+    if options.print {
+        let tree = pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+        let scan_elapsed = started.elapsed();
+        // Percentages instead of bars, which say the same in less room
+        let rows = printed_rows(&tree, roots.len() > 1, &DisplayOptions { bars: Bars::Percent, ..display });
+        unless_stdout_closed(print::write(io::stdout().lock(), &rows, options.color.colors()))?;
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+    if let Some(du_options) = options.du {
+        let tree = pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+        let scan_elapsed = started.elapsed();
+        unless_stdout_closed(du::write(io::stdout().lock(), &tree, roots.len() > 1, options.size_mode, du_options))?;
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+    if let Some(file) = &options.save_snapshot {
+        // Before --min-size, which the TUI applies when it opens the snapshot
+        let tree = pool.install(|| scan_roots(&roots, &ctx, None));
+        let scan_elapsed = started.elapsed();
+        let paths: Vec<&Path> = roots.iter().map(|root| root.path.as_path()).collect();
+        if ctx.cancelled.load(Ordering::SeqCst) {
+            eprintln!("rdu: scan cancelled; no snapshot written");
+        } else if let Err(e) = snapshot::save(file, &paths, &args[1..], &tree, &ctx, scan_elapsed) {
+            eprintln!("Error: cannot write {}: {}", file.display(), e);
+            std::process::exit(1);
+        }
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+    if let Some(count) = options.top {
+        let tree = pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+        let scan_elapsed = started.elapsed();
+        unless_stdout_closed(print_largest(&tree, roots.len() > 1, count, &display))?;
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+    if options.summary {
+        let tree = pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+        let scan_elapsed = started.elapsed();
+        unless_stdout_closed(print_summary(&tree, roots.len() > 1, options.size_mode, units, options.total))?;
+        return finish(ctx, &pool, scan_elapsed, units);
+    }
+
+    // Phase 1 & 3: Build the tree structure concurrently on a background thread while
+    // the TUI shows it filling in
+    let live = LiveTree::new(roots.iter().map(|root| DirEntry {
+        name: root.path.display().to_string(),
+        is_dir: true,
+        dir_count: 1,
+        inodes: 1,
+        ..Default::default()
+    }).collect());
+    // Only a single root is known to be on one filesystem
+    let space = match roots.as_slice() {
+        [root] => filesystem_space(&root.path),
+        _ => None,
+    };
+    // Only the TUI reads the config file, so a mistake in it does not break the reports
+    let keymap = config::load(options.config.as_deref()).and_then(|config| {
+        Keymap::new(&config.keys).map_err(|(line, e)| format!("{}:{}: {}", config.path.display(), line, e))
+    });
+    let tui_options = TuiOptions {
+        quiet: options.quiet,
+        // The entries of a snapshot may have changed or gone since it was made
+        deletion: if snapshot.is_some() { Deletion::Disabled } else { options.deletion },
+        mouse: options.mouse,
+        keymap: keymap.unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }),
+        snapshot: snapshot.as_ref().map(|snapshot| snapshot.scanned_at),
+    };
+    let loaded = snapshot.as_mut().map(|snapshot| {
+        snapshot.restore(&ctx);
+        let mut tree = std::mem::take(&mut snapshot.tree);
+        if let Some(min_size) = options.min_size {
+            prune_small(&mut tree, min_size, ctx.size_mode);
+        }
+        (tree, snapshot.elapsed)
+    });
+    // 'r' and 'R' in the TUI ask for the roots or a directory to be scanned again; the
+    // TUI hangs up when it quits
+    let (rescans, rescan_requests) = mpsc::channel();
+    let (scan_elapsed, tui) = thread::scope(|scope| {
+        let scan = scope.spawn(|| {
+            let rescan_requests = rescan_requests;
+            // A snapshot is shown as it was saved, until the TUI quits
+            if let Some((tree, elapsed)) = loaded {
+                if let Some(updates) = &ctx.updates {
+                    let _ = updates.send(ScanEvent::Finished { tree, elapsed });
+                }
+                while rescan_requests.recv().is_ok() {}
+                return elapsed;
+            }
+            let mut started = started;
+            'scans: loop {
+                let root_entry = pool.install(|| scan_roots(&roots, &ctx, options.min_size));
+                let mut elapsed = started.elapsed();
+                // The TUI may already be gone, in which case nobody needs the tree
+                if let Some(updates) = &ctx.updates {
+                    let _ = updates.send(ScanEvent::Finished { tree: root_entry, elapsed });
+                }
+                // The TUI is usable meanwhile; its duplicates screen fills in when this is done
+                if let Some(duplicates) = &ctx.duplicates {
+                    pool.install(|| duplicates.find(&ctx.cancelled));
+                }
+                loop {
+                    match rescan_requests.recv() {
+                        Ok(Rescan::Everything) => break,
+                        Ok(Rescan::Directory(path)) => {
+                            elapsed = pool.install(|| rescan_directory(&roots, &ctx, path, options.min_size));
+                        }
+                        Err(_) => break 'scans elapsed,
+                    }
+                }
+                ctx.reset();
+                started = Instant::now();
+            }
+        });
+
+        // Phase 3: Run the visual TUI interface
+        let tui = run_tui(live, &events, &ctx, display, tui_options, rescans, space);
+        if tui.is_err() {
+            // Do not keep scanning for a TUI that is not there
+            ctx.cancelled.store(true, Ordering::SeqCst);
+        }
+        (scan.join().unwrap(), tui)
+    });
+    tui?;
+    if let Some(snapshot) = &snapshot {
+        let scanned_at: DateTime<Local> = snapshot.scanned_at.into();
+        eprintln!("rdu: snapshot made {} by: rdu {}", scanned_at.format("%Y-%m-%d %H:%M"), snapshot.args.join(" "));
+    }
+    if let Some(duplicates) = &ctx.duplicates {
+        if ctx.cancelled.load(Ordering::SeqCst) {
+            eprintln!("rdu: duplicate search cancelled");
+        } else {
+            duplicates.print_report(units);
+        }
+    }
+    finish(ctx, &pool, scan_elapsed, units)
+}
+
+// Scans every command-line root (concurrently, like the children of a directory) and
+// returns the tree to show: the root itself, or a virtual root above several.
+fn scan_roots(roots: &[ScanRoot], ctx: &ScanContext, min_size: Option<u64>) -> DirEntry {
+    let mut entries: Vec<DirEntry> = roots.par_iter().map(|root| {
+        let gitignore = if ctx.respect_gitignore { Gitignore::for_root(&root.path) } else { None };
+        let mut entry = calculate_tree(&root.path, None, None, ctx, root, gitignore.as_ref(), 0);
+        ctx.finished(&root.path, &entry, 0);
+        entry.name = root.path.display().to_string();
+        entry
+    }).collect();
+    ctx.linked_files.settle(ctx.hardlinks, roots, &mut entries, ctx.owners.as_ref(), &ctx.extensions);
+    let mut root_entry = if entries.len() == 1 { entries.remove(0) } else { virtual_root(entries) };
+    if let Some(min_size) = min_size {
+        prune_small(&mut root_entry, min_size, ctx.size_mode);
+    }
+    root_entry
+}
+
+// Scans the directory at `path` below the root of the tree again and sends it to the TUI
+// to take the place of the old one, returning how long that took. Errors and broken
+// symlinks found below it replace the ones the last scan found there; the other totals
+// of the whole tree stay those of the last full scan. Hard links are settled within the
+// directory only, so a file also linked from outside it is counted again inside it until
+// the next full scan.
+fn rescan_directory(roots: &[ScanRoot], ctx: &ScanContext, path: Vec<String>, min_size: Option<u64>) -> Duration {
+    let started = Instant::now();
+    // With several roots the first name is that of a root
+    let (root, below) = match path.split_first() {
+        Some((first, rest)) if roots.len() > 1 => (roots.iter().find(|root| root.path.display().to_string() == *first), rest),
+        _ => (roots.first(), path.as_slice()),
+    };
+    let full = root.map(|root| below.iter().fold(root.path.clone(), |full, name| full.join(name)));
+    let entry = match (root, &full) {
+        (Some(root), Some(full)) if fs::symlink_metadata(full).is_ok() => {
+            ctx.progress.reset();
+            ctx.cancelled.store(false, Ordering::SeqCst);
+            let rescan = ctx.for_rescan();
+            let gitignore = if ctx.respect_gitignore { inherited_gitignore(root, below) } else { None };
+            let mut entry = calculate_tree(full, None, None, &rescan, root, gitignore.as_ref(), below.len());
+            if let Some(name) = below.last() {
+                entry.name = name.clone();
+            }
+
+            // The links are settled in a tree holding nothing but the directory, at its
+            // place below the root
+            let mut tree = entry;
+            for depth in (0..below.len()).rev() {
+                let name = if depth > 0 { below[depth - 1].clone() } else { String::new() };
+                tree = DirEntry { name, children: vec![tree], is_dir: true, ..Default::default() };
+            }
+            let mut entries: Vec<DirEntry> = roots.iter().map(|_| DirEntry::default()).collect();
+            entries[root.index] = tree;
+            rescan.linked_files.settle(rescan.hardlinks, roots, &mut entries, None, &rescan.extensions);
+            let mut entry = std::mem::take(&mut entries[root.index]);
+            for _ in below {
+                entry = entry.children.pop().unwrap_or_default();
+            }
+            if let Some(min_size) = min_size {
+                prune_small(&mut entry, min_size, ctx.size_mode);
+            }
+
+            let mut errors = ctx.errors.lock().unwrap();
+            errors.retain(|error| !error.path.starts_with(full));
+            errors.extend(rescan.errors.into_inner().unwrap());
+            let mut broken_links = ctx.broken_links.lock().unwrap();
+            broken_links.retain(|link| !link.path.starts_with(full));
+            broken_links.extend(rescan.broken_links.into_inner().unwrap());
+            Some(entry)
+        }
+        // Gone since the last scan
+        _ => None,
+    };
+    let elapsed = started.elapsed();
+    if let Some(updates) = &ctx.updates {
+        let _ = updates.send(ScanEvent::Rescanned { path, entry, elapsed });
+    }
+    elapsed
+}
+
+// The .gitignore rules that the directory at `path` below `root` inherits, layered the
+// way the traversal builds them on its way down.
+fn inherited_gitignore(root: &ScanRoot, path: &[String]) -> Option<Arc<Gitignore>> {
+    let mut rules = Gitignore::for_root(&root.path);
+    let mut dir = root.path.clone();
+    for depth in 0..path.len() {
+        rules = Gitignore::descend(rules.as_ref(), &dir, &path[..depth].join("/"));
+        dir.push(&path[depth]);
+    }
+    rules
+}
+
+// The result of writing to stdout, where a reader that went away before the end (as `head`
+// does) is not an error: the rest is just not wanted.
+fn unless_stdout_closed(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+// Prints the scan summary and everything that went wrong, once the terminal is back to normal.
+fn finish(ctx: ScanContext, pool: &rayon::ThreadPool, scan_elapsed: Duration, units: Units) -> Result<(), Box<dyn Error>> {
+    let stats = ctx.stats();
+    let errors = ctx.errors.into_inner().unwrap();
 
+    let threads = pool.current_num_threads();
+    eprintln!(
+        "rdu: scanned {} entries in {:.2}s using {} {}",
+        format_count(stats.entries),
+        scan_elapsed.as_secs_f64(),
+        threads,
+        if threads == 1 { "thread" } else { "threads" },
+    );
+    let seconds = scan_elapsed.as_secs_f64().max(0.001);
+    eprintln!(
+        "rdu: {}; {} entries/s, {}/s",
+        stats.coverage(),
+        format_count((stats.entries as f64 / seconds) as u64),
+        format_amount((stats.bytes as f64 / seconds) as u64, ctx.size_mode, units),
+    );
+    for error in &errors {
+        eprintln!("rdu: cannot read '{}': {}", error.path.display(), error.kind);
+    }
+    if !errors.is_empty() {
+        eprintln!("rdu: {} errors during scan; reported sizes are lower bounds", errors.len());
+    }
+    if stats.cancelled {
+        eprintln!("rdu: scan cancelled; the results shown were partial");
+    }
+    let sparse_files = ctx.sparse_files.load(Ordering::Relaxed);
+    if sparse_files > 0 {
+        eprintln!(
+            "rdu: {} sparse files; their apparent size exceeds what is allocated on disk by {}",
+            format_count(sparse_files),
+            format_size(ctx.sparse_excess.load(Ordering::Relaxed), units),
+        );
+    }
+
+    Ok(())
+}
+
+// Writes the usage to stdout for --help, to stderr after a mistake in the arguments.
+fn write_usage(out: &mut dyn io::Write, program: &str) -> io::Result<()> {
+    writeln!(out, "Usage: {} [options] <path>...", program)?;
+    writeln!(out)?;
+    writeln!(out, "Options:")?;
+    writeln!(out, "  -L, --follow-symlinks   Descend into symlinked directories (cycles are detected)")?;
+    writeln!(out, "      --follow-symlinks=within-root  Only follow symlinks whose target is inside the scanned path")?;
+    writeln!(out, "  -x, --one-file-system   Do not cross into other filesystems (mount points)")?;
+    writeln!(out, "      --exclude <glob>    Skip files and directories matching the pattern (repeatable)")?;
+    writeln!(out, "      --exclude-from <file> Read --exclude patterns from a file, one per line")?;
+    writeln!(out, "      --exclude-regex <re> Skip entries whose path relative to the root matches (repeatable)")?;
+    writeln!(out, "      --include-regex <re> Only count files whose relative path matches (repeatable)")?;
+    writeln!(out, "      --respect-gitignore Skip entries ignored by .gitignore files inside git repositories")?;
+    writeln!(out, "  -d, --max-depth <n>     Only keep entries up to depth n (deeper sizes are still counted)")?;
+    writeln!(out, "      --max-children-per-dir <n> Keep only the n largest entries of each directory in memory")?;
+    writeln!(out, "      --apparent-size     Report file lengths instead of allocated disk usage")?;
+    writeln!(out, "      --disk-usage        Report allocated disk usage (default)")?;
+    writeln!(out, "      --count-inodes      Report the number of inodes instead of bytes")?;
+    writeln!(out, "      --bytes             Show sizes as exact byte counts (1,234,567) instead of rounding them")?;
+    writeln!(out, "      --si                Round sizes in powers of 1000 (kB, MB, GB) instead of 1024 (KiB, MiB, GiB)")?;
+    writeln!(out, "  -q, --quiet             Do not show live scan counters")?;
+    writeln!(out, "      --theme <name>      Colors of the TUI: dark, light or mono (default: mono if NO_COLOR is set, else dark)")?;
+    writeln!(out, "      --config <file>     Read the TUI's settings from file instead of ~/.config/rdu/config.toml")?;
+    writeln!(out, "      --color-threshold-warn <size> Color entries above size as medium (default 10M, 0: never)")?;
+    writeln!(out, "      --color-threshold-crit <size> Color entries above size as large (default 50M, 0: never)")?;
+    writeln!(out, "      --ascii             Draw with ASCII characters only (the default outside UTF-8 locales)")?;
+    writeln!(out, "      --icons             Show file-type icons by the names (needs a Nerd Font in the terminal)")?;
+    writeln!(out, "      --read-only         Do not allow deleting entries from the TUI")?;
+    writeln!(out, "      --no-mouse          Do not capture the mouse in the TUI")?;
+    writeln!(out, "      --delete-permanently Delete entries from the TUI for good instead of moving them to the trash")?;
+    writeln!(out, "      --count-special     Count the reported sizes of devices, FIFOs and sockets")?;
+    writeln!(out, "      --no-dir-sizes      Only count file contents, not the space taken by directories themselves")?;
+    writeln!(out, "      --exclude-caches    Skip directories tagged with a CACHEDIR.TAG file")?;
+    writeln!(out, "      --include-virtual   Also scan /proc, /sys and other pseudo-filesystems")?;
+    writeln!(out, "      --no-hidden         Skip files and directories whose name starts with '.'")?;
+    writeln!(out, "      --newer-than <age>  Only count files modified within age (7d, 6months) or since a date (2024-01-31)")?;
+    writeln!(out, "      --older-than <age>  Only count files modified longer ago than age or before a date")?;
+    writeln!(out, "      --min-size <size>   Hide entries smaller than size (bytes or 10K, 1.5G, ...)")?;
+    writeln!(out, "      --fold <n>          List only the n largest children of each directory, folding the rest")?;
+    writeln!(out, "      --fold-below <pct>  Fold children smaller than pct percent of their directory")?;
+    writeln!(out, "      --by-owner          Print disk usage per file owner instead of opening the TUI")?;
+    writeln!(out, "      --by-extension      Print disk usage per file extension instead of opening the TUI")?;
+    writeln!(out, "      --find-duplicates   Look for files with identical contents after the scan")?;
+    writeln!(out, "      --empty-dirs        Print the directories without any files below them instead of opening the TUI")?;
+    writeln!(out, "  -0, --null              With --empty-dirs, end each path with a NUL byte instead of a newline")?;
+    writeln!(out, "      --broken-links      Print the symlinks whose target does not exist instead of opening the TUI")?;
+    writeln!(out, "      --empty-files       Print the directories with the most zero-length files instead of opening the TUI")?;
+    writeln!(out, "      --json              Write the tree and the scan statistics to stdout as JSON instead of opening the TUI")?;
+    writeln!(out, "      --pretty            With --json, indent the output")?;
+    writeln!(out, "      --ndjson            Write each entry to stdout as a line of JSON as soon as it is scanned")?;
+    writeln!(out, "      --csv, --tsv        Write a row per entry to stdout as CSV or TSV instead of opening the TUI")?;
+    writeln!(out, "      --files-only        With --csv or --tsv, leave out the directories")?;
+    writeln!(out, "      --print, --no-tui   Write the tree to stdout as indented text instead of opening the TUI")?;
+    writeln!(out, "      --color <when>      With --print, color the rows: auto (default, on a terminal), always or never")?;
+    writeln!(out, "      --du                Write what du would (sizes in 1K blocks, then the paths) instead of opening the TUI")?;
+    writeln!(out, "  -a, --all               With --du, write a line for files too")?;
+    writeln!(out, "  -h, --human-readable    With --du, write sizes like 4.0K and 12M (-s and --print always do)")?;
+    writeln!(out, "  -s, --summarize         Print only the size of each path instead of opening the TUI (with --du: du's -s)")?;
+    writeln!(out, "  -c, --total             With -s or --du, end with the total of all the paths")?;
+    writeln!(out, "      --top <n>           Print the n largest files instead of opening the TUI")?;
+    writeln!(out, "      --save-snapshot <file> Write the scan to file instead of opening the TUI")?;
+    writeln!(out, "      --load-snapshot <file> Open the TUI on a snapshot written earlier instead of scanning (no paths)")?;
+    writeln!(out, "      --diff <old> <new>  Print what changed between two snapshots instead of scanning")?;
+    writeln!(out, "      --threshold <size>  With --diff, leave out changes smaller than size")?;
+    writeln!(out, "      --hardlinks <policy> Attribute hard-linked files: dedup (default), count-all or shared")?;
+    writeln!(out, "      --threads <n>       Scan with n threads (default: RAYON_NUM_THREADS or one per core)")?;
+    writeln!(out, "      --help              Print this help and exit")?;
     Ok(())
-}
\ No newline at end of file
+}
+
+// Short options that take no value given together, as in `du -sh`: "-sh" stands for "-s"
+// "-h". Anything else starting with a single '-' is left as it is.
+fn split_short_flags(args: &[String]) -> Vec<String> {
+    const FLAGS: &str = "Lxq0ahsc";
+    args.iter().flat_map(|arg| match arg.strip_prefix('-') {
+        Some(letters) if letters.len() > 1 && letters.chars().all(|c| FLAGS.contains(c)) => {
+            letters.chars().map(|c| format!("-{}", c)).collect()
+        }
+        _ => vec![arg.clone()],
+    }).collect()
+}
+
+// The options of the command line, or None if it asks for --help.
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let args = split_short_flags(args);
+    let mut paths = Vec::new();
+    let mut follow_symlinks = false;
+    let mut symlinks_within_root = false;
+    let mut one_file_system = false;
+    let mut excludes = Vec::new();
+    let mut exclude_regexes = Vec::new();
+    let mut include_regexes = Vec::new();
+    let mut respect_gitignore = false;
+    let mut max_depth = None;
+    let mut size_mode = SizeMode::default();
+    let mut bytes = false;
+    let mut si = false;
+    let mut quiet = false;
+    let mut read_only = false;
+    let mut ascii = false;
+    let mut icons = false;
+    let mut theme = None;
+    let mut config = None;
+    let mut size_colors = SizeColors::default();
+    let mut delete_permanently = false;
+    let mut mouse = true;
+    let mut count_special = false;
+    let mut exclude_caches = false;
+    let mut include_virtual = false;
+    let mut skip_hidden = false;
+    let mut newer_than = None;
+    let mut older_than = None;
+    let now = SystemTime::now();
+    let mut min_size = None;
+    let mut fold_children = None;
+    let mut fold_below = None;
+    let mut threads = None;
+    let mut by_owner = false;
+    let mut by_extension = false;
+    let mut find_duplicates = false;
+    let mut empty_dirs = false;
+    let mut null = false;
+    let mut broken_links = false;
+    let mut dir_sizes = true;
+    let mut max_children = None;
+    let mut empty_files = false;
+    let mut json = false;
+    let mut pretty = false;
+    let mut ndjson = false;
+    let mut table = None;
+    let mut files_only = false;
+    let mut print = false;
+    let mut color = None;
+    let mut du_mode = false;
+    let mut du_options = du::Options::default();
+    let mut summarize = false;
+    let mut top = None;
+    let mut save_snapshot = None;
+    let mut load_snapshot = None;
+    let mut diff = false;
+    let mut threshold = None;
+    let mut hardlinks = HardlinkPolicy::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        // Long options may carry their value inline as `--name=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value.clone()
+                .or_else(|| iter.next().cloned())
+                .ok_or_else(|| format!("Missing value for {}", flag))
+        };
+
+        match flag {
+            "-L" | "--follow-symlinks" => {
+                follow_symlinks = true;
+                symlinks_within_root = match inline_value.as_deref() {
+                    None | Some("always") => false,
+                    Some("within-root") => true,
+                    Some(other) => return Err(format!("{} expects always or within-root, got '{}'", flag, other)),
+                };
+            }
+            "-x" | "--one-file-system" => one_file_system = true,
+            "--respect-gitignore" => respect_gitignore = true,
+            "--apparent-size" => size_mode = SizeMode::Apparent,
+            "--disk-usage" => size_mode = SizeMode::DiskUsage,
+            "--count-inodes" => size_mode = SizeMode::Inodes,
+            "--bytes" => bytes = true,
+            "--si" => si = true,
+            "-q" | "--quiet" => quiet = true,
+            "--read-only" => read_only = true,
+            "--ascii" => ascii = true,
+            "--icons" => icons = true,
+            "--no-mouse" => mouse = false,
+            "--delete-permanently" => delete_permanently = true,
+            "--count-special" => count_special = true,
+            "--exclude-caches" => exclude_caches = true,
+            "--include-virtual" => include_virtual = true,
+            "--no-hidden" => skip_hidden = true,
+            "--by-owner" if cfg!(unix) => by_owner = true,
+            "--by-owner" => return Err("--by-owner is only supported on Unix".to_string()),
+            "--by-extension" => by_extension = true,
+            "--find-duplicates" => find_duplicates = true,
+            "--empty-dirs" => empty_dirs = true,
+            "-0" | "--null" => null = true,
+            "--broken-links" => broken_links = true,
+            "--no-dir-sizes" => dir_sizes = false,
+            "--empty-files" => empty_files = true,
+            "--json" => json = true,
+            "--pretty" => pretty = true,
+            "--ndjson" => ndjson = true,
+            "--save-snapshot" => save_snapshot = Some(PathBuf::from(value()?)),
+            "--load-snapshot" => load_snapshot = Some(PathBuf::from(value()?)),
+            "--diff" => diff = true,
+            "--threshold" => threshold = Some(parse_size(&value()?).map_err(|e| format!("{}: {}", flag, e))?),
+            "--csv" => table = Some(table::Format::Csv),
+            "--tsv" => table = Some(table::Format::Tsv),
+            "--files-only" => files_only = true,
+            "--print" | "--no-tui" => print = true,
+            "--du" => du_mode = true,
+            "-a" | "--all" => du_options.all = true,
+            "-h" | "--human-readable" => du_options.human = true,
+            "-s" | "--summarize" => summarize = true,
+            "-c" | "--total" => du_options.total = true,
+            "--color" => color = Some(print::ColorWhen::parse(&value()?).map_err(|e| format!("{}: {}", flag, e))?),
+            "-d" | "--max-depth" => {
+                let depth = value()?;
+                max_depth = Some(depth.parse::<usize>().map_err(|_| {
+                    format!("{} expects a non-negative integer, got '{}'", flag, depth)
+                })?);
+            }
+            "--max-children-per-dir" => {
+                let count = value()?;
+                max_children = match count.parse::<usize>() {
+                    Ok(count) if count > 0 => Some(count),
+                    _ => return Err(format!("{} expects a positive integer, got '{}'", flag, count)),
+                };
+            }
+            "--help" => return Ok(None),
+            "--threads" => {
+                let count = value()?;
+                threads = match count.parse::<usize>() {
+                    Ok(count) if count > 0 => Some(count),
+                    _ => return Err(format!("{} expects a positive integer, got '{}'", flag, count)),
+                };
+            }
+            "--theme" => theme = Some(theme::parse(&value()?).map_err(|e| format!("{}: {}", flag, e))?),
+            "--hardlinks" => hardlinks = HardlinkPolicy::parse(&value()?).map_err(|e| format!("{}: {}", flag, e))?,
+            "--newer-than" => newer_than = Some(parse_age(&value()?, now).map_err(|e| format!("{}: {}", flag, e))?),
+            "--older-than" => older_than = Some(parse_age(&value()?, now).map_err(|e| format!("{}: {}", flag, e))?),
+            "--config" => config = Some(PathBuf::from(value()?)),
+            "--color-threshold-warn" => size_colors.warn = parse_size(&value()?).map_err(|e| format!("{}: {}", flag, e))?,
+            "--color-threshold-crit" => size_colors.crit = parse_size(&value()?).map_err(|e| format!("{}: {}", flag, e))?,
+            "--min-size" => {
+                let size = value()?;
+                min_size = Some(parse_size(&size).map_err(|e| format!("{}: {}", flag, e))?);
+            }
+            "--top" => {
+                let count = value()?;
+                top = match count.parse::<usize>() {
+                    Ok(count) if count > 0 => Some(count),
+                    _ => return Err(format!("{} expects a positive integer, got '{}'", flag, count)),
+                };
+            }
+            "--fold" => {
+                let count = value()?;
+                fold_children = match count.parse::<usize>() {
+                    Ok(count) if count > 0 => Some(count),
+                    _ => return Err(format!("{} expects a positive integer, got '{}'", flag, count)),
+                };
+            }
+            "--fold-below" => {
+                let percent = value()?;
+                fold_below = match percent.trim_end_matches('%').parse::<f64>() {
+                    Ok(percent) if (0.0..=100.0).contains(&percent) => Some(percent),
+                    _ => return Err(format!("{} expects a percentage between 0 and 100, got '{}'", flag, percent)),
+                };
+            }
+            "--exclude" => {
+                let pattern = value()?;
+                excludes.push(glob::Pattern::new(&pattern).map_err(|e| format!("--exclude: {}", e))?);
+            }
+            "--exclude-from" => excludes.extend(read_exclude_file(&value()?)?),
+            "--exclude-regex" => exclude_regexes.push(parse_regex(flag, &value()?)?),
+            "--include-regex" => include_regexes.push(parse_regex(flag, &value()?)?),
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("Unknown option: {}", flag));
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    // The paths are the snapshots to compare
+    if diff && paths.len() != 2 {
+        return Err("--diff expects two snapshots: --diff <old> <new>".to_string());
+    }
+    // A snapshot names its own paths
+    if load_snapshot.is_some() && !paths.is_empty() {
+        return Err("--load-snapshot takes no <path>; the snapshot has the paths it was made of".to_string());
+    }
+    if paths.is_empty() && load_snapshot.is_none() {
+        return Err("Missing <path> argument".to_string());
+    }
+    if let (Some(newer), Some(older)) = (newer_than, older_than)
+        && newer >= older
+    {
+        return Err("--newer-than and --older-than leave no time window; nothing would match".to_string());
+    }
+    // The list of empty directories is meant for piping, so nothing else is printed with it
+    if empty_dirs && (by_owner || by_extension || find_duplicates || broken_links || empty_files) {
+        return Err("--empty-dirs cannot be combined with the other reports".to_string());
+    }
+    if json && (by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files) {
+        return Err("--json cannot be combined with the reports".to_string());
+    }
+    if ndjson && (json || by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files) {
+        return Err("--ndjson cannot be combined with --json or the reports".to_string());
+    }
+    if let Some(format) = table {
+        let flag = if format == table::Format::Csv { "--csv" } else { "--tsv" };
+        if json || ndjson || by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files {
+            return Err(format!("{} cannot be combined with --json, --ndjson or the reports", flag));
+        }
+    }
+    if files_only && table.is_none() {
+        return Err("--files-only only applies to --csv and --tsv".to_string());
+    }
+    if print && (json || ndjson || table.is_some() || by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files) {
+        return Err("--print cannot be combined with --json, --ndjson, --csv, --tsv or the reports".to_string());
+    }
+    if du_mode && (print || json || ndjson || table.is_some() || by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files) {
+        return Err("--du cannot be combined with --print, --json, --ndjson, --csv, --tsv or the reports".to_string());
+    }
+    if !du_mode && du_options.all {
+        return Err("-a only applies to --du".to_string());
+    }
+    // -s and --print round their sizes already, so -h asks for nothing more of them
+    if du_options.human && !(du_mode || summarize || print) {
+        return Err("-h only applies to --du, -s and --print".to_string());
+    }
+    du_options.summarize = du_mode && summarize;
+    du_options.si = du_mode && si;
+    let summary = summarize && !du_mode;
+    if summary && (print || json || ndjson || table.is_some() || by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files) {
+        return Err("-s cannot be combined with --print, --json, --ndjson, --csv, --tsv or the reports".to_string());
+    }
+    if du_options.total && !(du_mode || summary) {
+        return Err("--total only applies to -s and --du".to_string());
+    }
+    if top.is_some() && (json || ndjson || table.is_some() || print || du_mode || summary) {
+        return Err("--top cannot be combined with -s, --du, --print, --json, --ndjson, --csv or --tsv".to_string());
+    }
+    if top.is_some() && (by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files) {
+        return Err("--top cannot be combined with the reports".to_string());
+    }
+    let output = json || ndjson || table.is_some() || print || du_mode || summary || top.is_some();
+    let reports = by_owner || by_extension || find_duplicates || empty_dirs || broken_links || empty_files;
+    if save_snapshot.is_some() && (load_snapshot.is_some() || output || reports) {
+        return Err("--save-snapshot cannot be combined with --load-snapshot, the other outputs or the reports".to_string());
+    }
+    if load_snapshot.is_some() && (output || reports) {
+        return Err("--load-snapshot only opens the TUI; it cannot be combined with the other outputs or the reports".to_string());
+    }
+    if diff && (save_snapshot.is_some() || load_snapshot.is_some() || output || reports) {
+        return Err("--diff cannot be combined with the snapshot options, the other outputs or the reports".to_string());
+    }
+    if threshold.is_some() && !diff {
+        return Err("--threshold only applies to --diff".to_string());
+    }
+    if color.is_some() && !print {
+        return Err("--color only applies to --print".to_string());
+    }
+    if pretty && !json {
+        return Err("--pretty only applies to --json".to_string());
+    }
+    if null && !empty_dirs {
+        return Err("--null only applies to --empty-dirs".to_string());
+    }
+    if size_colors.warn > 0 && size_colors.crit > 0 && size_colors.warn >= size_colors.crit {
+        return Err(format!(
+            "--color-threshold-warn ({}) must be below --color-threshold-crit ({})",
+            format_size(size_colors.warn, Units::Binary),
+            format_size(size_colors.crit, Units::Binary),
+        ));
+    }
+    Ok(Some(Options {
+        paths,
+        save_snapshot,
+        load_snapshot,
+        diff,
+        threshold: threshold.unwrap_or(0),
+        follow_symlinks,
+        symlinks_within_root,
+        one_file_system,
+        excludes,
+        exclude_regexes,
+        include_regexes,
+        respect_gitignore,
+        max_depth,
+        size_mode,
+        bytes,
+        si,
+        quiet,
+        ascii,
+        icons,
+        theme,
+        config,
+        size_colors,
+        deletion: if read_only {
+            Deletion::Disabled
+        } else if delete_permanently {
+            Deletion::Permanent
+        } else {
+            Deletion::Trash
+        },
+        mouse,
+        count_special,
+        exclude_caches,
+        include_virtual,
+        skip_hidden,
+        newer_than,
+        older_than,
+        min_size,
+        fold: (fold_children.is_some() || fold_below.is_some())
+            .then_some(Fold { max_children: fold_children, min_percent: fold_below }),
+        by_owner,
+        by_extension,
+        find_duplicates,
+        empty_dirs,
+        null,
+        broken_links,
+        dir_sizes,
+        max_children,
+        empty_files,
+        json,
+        pretty,
+        ndjson,
+        table,
+        files_only,
+        print,
+        color: color.unwrap_or_default(),
+        du: du_mode.then_some(du_options),
+        summary,
+        top,
+        total: du_options.total,
+        hardlinks,
+        threads,
+    }))
+}
+
+// Reads the glob patterns of an --exclude-from file, one per line. Blank lines and lines
+// starting with '#' are skipped; trailing whitespace (including the '\r' of CRLF files) is
+// not part of a pattern.
+fn read_exclude_file(path: &str) -> Result<Vec<glob::Pattern>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("--exclude-from '{}': {}", path, e))?;
+    contents.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim_end()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            glob::Pattern::new(line).map_err(|e| format!("--exclude-from '{}', line {}: {}", path, number, e))
+        })
+        .collect()
+}
+
+// Prints the --empty-dirs list, each directory before its parent so the output can be
+// fed to `xargs rmdir` (or `xargs -0 rmdir` with --null).
+fn print_empty_dirs(paths: &mut [PathBuf], null: bool) -> io::Result<()> {
+    use std::io::Write;
+    paths.sort_by(|a, b| children_first(a, b));
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for path in paths.iter() {
+        out.write_all(path.as_os_str().as_encoded_bytes())?;
+        out.write_all(if null { b"\0" } else { b"\n" })?;
+    }
+    out.flush()
+}
+
+// Directories listed by --empty-files, the ones holding the most zero-length files first
+const EMPTY_FILES_SHOWN: usize = 20;
+
+// Prints the directories with the most zero-length files (counting only those directly
+// inside each one) and the totals over the whole scan.
+fn print_empty_files(dirs: &mut [(PathBuf, u64)]) {
+    dirs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    println!("{:>12}  DIRECTORY", "EMPTY FILES");
+    for (path, count) in dirs.iter().take(EMPTY_FILES_SHOWN) {
+        println!("{:>12}  {}", format_count(*count), path.display());
+    }
+    if dirs.len() > EMPTY_FILES_SHOWN {
+        println!("{:>12}  ({} more directories)", "...", format_count((dirs.len() - EMPTY_FILES_SHOWN) as u64));
+    }
+    let total: u64 = dirs.iter().map(|(_, count)| count).sum();
+    println!("{} empty files in {} directories", format_count(total), format_count(dirs.len() as u64));
+}
+
+// Prints the size of each root of `tree` (-s), and with `total` that of all of them, the
+// sizes lined up on the right.
+fn print_summary(tree: &DirEntry, multiple_roots: bool, mode: SizeMode, units: Units, total: bool) -> io::Result<()> {
+    use std::io::Write;
+    let roots = if multiple_roots { tree.children.iter().collect() } else { vec![tree] };
+    let mut lines: Vec<(String, &str)> = roots.iter()
+        .map(|root| (format_amount(root.size(mode), mode, units), root.name.as_str()))
+        .collect();
+    if total {
+        lines.push((format_amount(tree.size(mode), mode, units), "total"));
+    }
+    let width = lines.iter().map(|(size, _)| size.len()).max().unwrap_or(0);
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for (size, name) in &lines {
+        writeln!(out, "{:>width$}  {}", size, name)?;
+    }
+    out.flush()
+}
+
+// Prints the `count` largest files of `tree`, largest first, with their sizes lined up
+// on the right (--top).
+fn print_largest(tree: &DirEntry, multiple_roots: bool, count: usize, display: &DisplayOptions) -> io::Result<()> {
+    use std::io::Write;
+    let mut largest = BinaryHeap::new();
+    collect_largest(tree, &mut Vec::new(), &mut largest, count, display, multiple_roots);
+    let lines: Vec<(String, PathBuf)> = largest.into_sorted_vec().into_iter()
+        .map(|Reverse((size, path))| (format_amount(size, display.size_mode, display.units()), entry_path(tree, &path, multiple_roots)))
+        .collect();
+    let width = lines.iter().map(|(size, _)| size.len()).max().unwrap_or(0);
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for (size, path) in &lines {
+        writeln!(out, "{:>width$}  {}", size, path.display())?;
+    }
+    out.flush()
+}
+
+// Orders paths by name, except that a path comes before the directories containing it.
+fn children_first(a: &Path, b: &Path) -> std::cmp::Ordering {
+    let mut a = a.components();
+    let mut b = b.components();
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) if x == y => continue,
+            (Some(x), Some(y)) => return x.cmp(&y),
+            (Some(_), None) => return std::cmp::Ordering::Less,
+            (None, Some(_)) => return std::cmp::Ordering::Greater,
+            (None, None) => return std::cmp::Ordering::Equal,
+        }
+    }
+}
+
+// Compiles a --exclude-regex/--include-regex pattern once, before the scan starts.
+fn parse_regex(flag: &str, pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("{} '{}': {}", flag, pattern, e))
+}
+
+// Parses an age for the time filters into the point in time it refers to: either a
+// duration back from `now` ("90s", "12h", "7d", "2w", "6months", "1y"; months are
+// 30 days and years 365) or a local date ("2024-01-31", midnight at its start).
+fn parse_age(text: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let text = text.trim();
+    let invalid = || format!("invalid age '{}': expected a duration like 7d or 6months, or a date like 2024-01-31", text);
+
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        let local = date.and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+            .ok_or_else(invalid)?;
+        return Ok(local.into());
+    }
+
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let count: u64 = number.parse().map_err(|_| invalid())?;
+    const DAY: u64 = 24 * 60 * 60;
+    let unit_seconds = match unit.trim_start() {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        // A bare m could as well be months as minutes
+        "m" => return Err(format!("ambiguous age '{}': write min for minutes or mo for months", text)),
+        "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => DAY,
+        "w" | "week" | "weeks" => 7 * DAY,
+        "mo" | "month" | "months" => 30 * DAY,
+        "y" | "year" | "years" => 365 * DAY,
+        _ => return Err(invalid()),
+    };
+    count.checked_mul(unit_seconds)
+        .and_then(|seconds| now.checked_sub(Duration::from_secs(seconds)))
+        .ok_or_else(|| format!("age '{}' is too large", text))
+}
+
+// Parses a byte count with an optional 1024-based unit suffix: "4096", "10K", "1.5G", "2MiB".
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let multiplier: u64 = match unit.trim_start().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit in '{}'", text)),
+    };
+    let value: f64 = number.parse().map_err(|_| format!("invalid size '{}'", text))?;
+    let bytes = value * multiplier as f64;
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("size '{}' is too large", text));
+    }
+    Ok(bytes.round() as u64)
+}
+
+// --- CORE SYSTEM FUNCTION: Concurrent Tree Calculation ---
+
+// Directories up to this depth have their children scanned in parallel by recursing on
+// the rayon workers. Deeper subtrees are walked with an explicit stack instead, so a
+// pathologically deep tree costs heap rather than overflowing a worker's stack.
+const PARALLEL_DEPTH: usize = 64;
+
+// Recursively calculates the data structure, leveraging Rayon for parallelism.
+// `dir_entry` is the read_dir entry the path came from (None for a root) and supplies
+// its metadata, `parent` is the directory it is in (None for a root), `root` is the
+// command-line path being scanned, `gitignore` holds the ignore rules inherited from the
+// parent directory (if any), `depth` is the distance from the root.
+fn calculate_tree(
+    path: &Path,
+    dir_entry: Option<&fs::DirEntry>,
+    parent: Option<&Dir>,
+    ctx: &ScanContext,
+    root: &ScanRoot,
+    gitignore: Option<&Arc<Gitignore>>,
+    depth: usize,
+) -> DirEntry {
+    let dir = match visit(path, dir_entry, parent, ctx, root, gitignore, depth) {
+        Visit::Done(entry) => return entry,
+        Visit::Descend(dir) if depth >= PARALLEL_DEPTH => return calculate_tree_iterative(dir, path, ctx, root),
+        Visit::Descend(dir) => dir,
+    };
+
+    // 2. Parallel Processing (Rayon): Recursively calculate children concurrently
+    let totals = dir.children.par_iter()
+        .map(|child| {
+            let child_path = path.join(child.entry.file_name());
+            let entry = if child.is_symlink && !child.follow {
+                symlink_leaf(ctx, &child_path, &child.entry)
+            } else {
+                let mut entry = calculate_tree(&child_path, Some(&child.entry), dir.open.as_ref(), ctx, root, dir.gitignore.as_ref(), depth + 1);
+                if child.is_symlink {
+                    entry.is_symlink = true;
+                    entry.link_target = read_target(&child_path);
+                }
+                entry
+            };
+            ctx.finished(&child_path, &entry, depth + 1);
+            entry
+        })
+        .fold(DirTotals::new, |mut totals, entry| {
+            totals.add(entry, ctx);
+            totals
+        })
+        .reduce(DirTotals::new, |a, b| a.merge(b, ctx));
+    finish_dir(dir, path, totals, ctx, root)
+}
+
+// The same traversal as `calculate_tree` on one thread, keeping the directories being
+// read on a heap-allocated stack instead of the call stack. Its depth is only limited by
+// memory: each directory is opened relative to its parent (see dirfd), so paths longer
+// than the OS allows (PATH_MAX) are read like any other. The frames hold no paths, only
+// `path` (that of the directory on top) grows and shrinks with the stack, and each
+// frame's directory is closed once its last child is visited.
+fn calculate_tree_iterative(dir: PendingDir, path: &Path, ctx: &ScanContext, root: &ScanRoot) -> DirEntry {
+    struct Frame {
+        dir: PendingDir,
+        remaining: std::vec::IntoIter<Child>,
+        results: DirTotals,
+        // Whether the directory was reached through a followed symlink, and its target
+        is_symlink: bool,
+        link_target: Option<String>,
+    }
+    let frame = |mut dir: PendingDir, is_symlink: bool, link_target: Option<String>| {
+        let remaining = std::mem::take(&mut dir.children).into_iter();
+        Frame { dir, remaining, results: DirTotals::new(), is_symlink, link_target }
+    };
+
+    let mut path = path.to_path_buf();
+    let mut stack = vec![frame(dir, false, None)];
+    loop {
+        let top = stack.last_mut().unwrap();
+        if let Some(child) = top.remaining.next() {
+            let is_symlink = child.is_symlink;
+            let depth = top.dir.depth + 1;
+            path.push(child.entry.file_name());
+            if is_symlink && !child.follow {
+                let entry = symlink_leaf(ctx, &path, &child.entry);
+                ctx.finished(&path, &entry, depth);
+                top.results.add(entry, ctx);
+                path.pop();
+                continue;
+            }
+            let link_target = if is_symlink { read_target(&path) } else { None };
+            let visited = visit(&path, Some(&child.entry), top.dir.open.as_ref(), ctx, root, top.dir.gitignore.as_ref(), depth);
+            if top.remaining.len() == 0 {
+                top.dir.open = None;
+            }
+            match visited {
+                Visit::Done(entry) => {
+                    let entry = DirEntry { is_symlink, link_target, ..entry };
+                    ctx.finished(&path, &entry, depth);
+                    top.results.add(entry, ctx);
+                    path.pop();
+                }
+                Visit::Descend(dir) => stack.push(frame(dir, is_symlink, link_target)),
+            }
+            continue;
+        }
+
+        // All children are done: the directory is summed and handed to its parent
+        let done = stack.pop().unwrap();
+        let depth = done.dir.depth;
+        let entry = DirEntry {
+            is_symlink: done.is_symlink,
+            link_target: done.link_target,
+            ..finish_dir(done.dir, &path, done.results, ctx, root)
+        };
+        // The directory the walk started from is handed on by the caller
+        if !stack.is_empty() {
+            ctx.finished(&path, &entry, depth);
+            path.pop();
+        }
+        match stack.last_mut() {
+            Some(parent) => parent.results.add(entry, ctx),
+            None => return entry,
+        }
+    }
+}
+
+// What looking at one entry found: a finished leaf (or skipped directory), or a directory
+// whose children still have to be scanned.
+enum Visit {
+    Done(DirEntry),
+    Descend(PendingDir),
+}
+
+// A directory that has been read but whose children are not scanned yet.
+struct PendingDir {
+    name: String,
+    // The open directory its children are looked up in, closed once they all have been
+    open: Option<Dir>,
+    own_mtime: Option<SystemTime>,
+    // Space taken by the directory itself: (apparent, disk)
+    own_size: (u64, u64),
+    incomplete: bool,
+    // Some children were left out by --exclude, --no-hidden or .gitignore rules
+    filtered: bool,
+    gitignore: Option<Arc<Gitignore>>,
+    children: Vec<Child>,
+    depth: usize,
+}
+
+// An entry found while reading a directory.
+struct Child {
+    entry: fs::DirEntry,
+    is_symlink: bool,
+    // Whether a symlink is descended into (see --follow-symlinks)
+    follow: bool,
+}
+
+// Stats an entry and, for a directory, reads its list of children.
+fn visit(
+    path: &Path,
+    dir_entry: Option<&fs::DirEntry>,
+    parent: Option<&Dir>,
+    ctx: &ScanContext,
+    root: &ScanRoot,
+    gitignore: Option<&Arc<Gitignore>>,
+    depth: usize,
+) -> Visit {
+    let name = path.file_name()
+        .map(|os_str| os_str.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string_lossy().into_owned());
+    ctx.progress.entries.fetch_add(1, Ordering::Relaxed);
+
+    // After Ctrl+C every pending entry returns immediately without touching the disk
+    if ctx.cancelled.load(Ordering::Relaxed) {
+        return Visit::Done(DirEntry { name, incomplete: true, ..Default::default() });
+    }
+    
+    // One stat per entry: the read_dir entry answers it without following the path again
+    // (fstatat on Unix); only symlinks, which must be followed, and the roots need a
+    // lookup by path.
+    let metadata = match dir_entry {
+        Some(dir_entry) if !dir_entry.file_type().is_ok_and(|t| t.is_symlink()) => dir_entry.metadata(),
+        _ => fs::metadata(path),
+    };
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            // Dangling symlink (or an entry that vanished): a zero-size leaf, still an
+            // inode if the link itself is there
+            let link = fs::symlink_metadata(path);
+            let broken_link = link.as_ref().is_ok_and(|metadata| metadata.file_type().is_symlink())
+                && record_broken_link(ctx, path, &error);
+            let inodes = link.is_ok() as u64;
+            let mtime = link.and_then(|metadata| metadata.modified()).ok();
+            return Visit::Done(DirEntry { name, file_count: 1, inodes, mtime, broken_link, ..Default::default() });
+        }
+    };
+    
+    // Base case: Handle single files using Phase 2 logic (Hardlink Analysis)
+    if metadata.is_file() {
+        ctx.progress.files.fetch_add(1, Ordering::Relaxed);
+        let links = link_count(&metadata);
+        let mtime = metadata.modified().ok();
+        // Outside the time window: not counted, and dropped by the parent below
+        if !in_time_window(ctx, mtime) {
+            return Visit::Done(DirEntry { name, file_count: 1, mtime, ..Default::default() });
+        }
+        // Hard-linked files are attributed to owners and extensions once the scan is settled
+        let linked = tracks_links(ctx, links);
+        let owner = owner_id(&metadata);
+        let empty_files = (metadata.len() == 0) as u64;
+        let entry = match get_dir_size_unique_file(path, &metadata, root, ctx) {
+            Ok(None) => {
+                ctx.progress.hardlinks.fetch_add(1, Ordering::Relaxed);
+                let shared = Some((metadata.len(), allocated_size(&metadata)));
+                DirEntry { name, file_count: 1, mtime, links, shared, linked, owner, empty_files, ..Default::default() }
+            }
+            Ok(Some((apparent_size, disk_usage))) => {
+                if let Some(duplicates) = &ctx.duplicates {
+                    duplicates.record(path, &metadata, apparent_size, ctx.hardlinks == HardlinkPolicy::CountAll && links != 1);
+                }
+                if !linked
+                    && let Some(owners) = &ctx.owners
+                    && let Some(uid) = owner
+                {
+                    owners.add(uid, apparent_size, disk_usage);
+                }
+                let sparse = is_sparse(apparent_size, disk_usage);
+                if sparse {
+                    ctx.sparse_files.fetch_add(1, Ordering::Relaxed);
+                    ctx.sparse_excess.fetch_add(apparent_size - disk_usage, Ordering::Relaxed);
+                }
+                let entry = DirEntry { name, apparent_size, disk_usage, file_count: 1, inodes: 1, mtime, sparse, links, linked, owner, empty_files, ..Default::default() };
+                ctx.progress.bytes.fetch_add(entry.size(ctx.size_mode), Ordering::Relaxed);
+                entry
+            }
+            Err(e) => {
+                record_error(ctx, path, &e);
+                DirEntry { name, incomplete: true, file_count: 1, ..Default::default() }
+            }
+        };
+        return Visit::Done(entry);
+    }
+    
+    // Anything else that is not a directory (device nodes, FIFOs, sockets) is a leaf.
+    // Devices may report the size of a whole disk, so they only count when asked for;
+    // their inode always counts.
+    if !metadata.is_dir() {
+        let special = SpecialKind::from_file_type(&metadata.file_type());
+        let mtime = metadata.modified().ok();
+        let (apparent_size, disk_usage) = if special.is_some() && ctx.count_special {
+            (metadata.len(), allocated_size(&metadata))
+        } else {
+            (0, 0)
+        };
+        return Visit::Done(DirEntry { name, apparent_size, disk_usage, file_count: 1, inodes: 1, mtime, special, ..Default::default() });
+    }
+
+    let own_mtime = metadata.modified().ok();
+
+    // Pseudo-filesystems report made-up sizes (/proc/kcore) and can block on reads (/sys)
+    if let Some(device) = device_id(&metadata)
+        && ctx.virtual_devices.contains(&device)
+    {
+        return Visit::Done(DirEntry { name, is_dir: true, skipped_virtual: true, dir_count: 1, mtime: own_mtime, ..Default::default() });
+    }
+
+    // Mount points are kept as visible, empty entries instead of being traversed.
+    // The device comes from the child's own metadata so bind mounts are caught too.
+    // Their inode belongs to the other filesystem, so it is not counted either.
+    if let Some(root_device) = root.device
+        && device_id(&metadata) != Some(root_device)
+    {
+        return Visit::Done(DirEntry { name, is_dir: true, skipped_mount: true, dir_count: 1, mtime: own_mtime, ..Default::default() });
+    }
+
+    // Like du, a directory's own blocks count towards its total (unless --no-dir-sizes)
+    let own_size = if ctx.dir_sizes { (metadata.len(), allocated_size(&metadata)) } else { (0, 0) };
+    let own_entry = |entry: DirEntry| {
+        count_dir_size(ctx, own_size);
+        DirEntry { apparent_size: own_size.0, disk_usage: own_size.1, ..entry }
+    };
+
+    // Like `du --exclude-caches`, tagged cache directories are listed but their contents
+    // are neither read nor counted
+    if ctx.exclude_caches && has_cachedir_tag(path) {
+        return Visit::Done(own_entry(DirEntry { name, is_dir: true, skipped_cache: true, dir_count: 1, inodes: 1, mtime: own_mtime, ..Default::default() }));
+    }
+
+    // Cycle detection: a directory reached a second time (e.g. `a -> ../a`) is not descended again
+    if ctx.follow_symlinks && !mark_dir_visited(path, &metadata, ctx) {
+        return Visit::Done(DirEntry { name, is_dir: true, dir_count: 1, mtime: own_mtime, ..Default::default() });
+    }
+
+    if let Ok(mut current) = ctx.progress.current.try_lock() {
+        current.clear();
+        current.push(path);
+    }
+
+    let gitignore = if ctx.respect_gitignore {
+        Gitignore::descend(gitignore, path, &relative_path(path, root))
+    } else {
+        None
+    };
+
+    // 1. Sequential I/O: Collect immediate children paths, noting which ones are symlinks
+    let mut child_paths = Vec::new();
+    let mut incomplete = false;
+    let mut filtered = false;
+    let opened = Dir::open(parent, path).and_then(|open| open.read(path).map(|read_dir| (open, read_dir)));
+    let (open, read_dir) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            record_error(ctx, path, &e);
+            return Visit::Done(own_entry(DirEntry { name, is_dir: true, incomplete: true, dir_count: 1, inodes: 1, mtime: own_mtime, ..Default::default() }));
+        }
+    };
+    ctx.progress.directories.fetch_add(1, Ordering::Relaxed);
+    for entry in read_dir {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                record_error(ctx, path, &e);
+                incomplete = true;
+                continue;
+            }
+        };
+        // Excluded entries are dropped before they are stat'ed or read
+        if ctx.skip_hidden && entry.file_name().as_encoded_bytes().starts_with(b".") {
+            ctx.hidden_count.fetch_add(1, Ordering::Relaxed);
+            filtered = true;
+            continue;
+        }
+        // Not entry.path(): the entries may have been read through /proc/self/fd
+        let child_path = path.join(entry.file_name());
+        let file_type = entry.file_type().ok();
+        let is_symlink = file_type.is_some_and(|t| t.is_symlink());
+        let follow = is_symlink && follows_symlink(&child_path, root, ctx);
+        // A followed symlink counts as what it points to
+        let is_dir = file_type.is_some_and(|t| t.is_dir()) || (follow && child_path.is_dir());
+        if is_excluded(&child_path, is_dir, root, ctx) {
+            ctx.excluded_count.fetch_add(1, Ordering::Relaxed);
+            filtered = true;
+            continue;
+        }
+        if let Some(rules) = &gitignore {
+            let name = entry.file_name();
+            let is_dir = file_type.is_some_and(|t| t.is_dir());
+            if rules.is_ignored(&relative_path(&child_path, root), &name.to_string_lossy(), is_dir) {
+                filtered = true;
+                continue;
+            }
+        }
+        if is_symlink {
+            ctx.progress.symlinks.fetch_add(1, Ordering::Relaxed);
+        }
+        child_paths.push(Child { entry, is_symlink, follow });
+    }
+
+    count_dir_progress(ctx, own_size);
+    Visit::Descend(PendingDir {
+        name,
+        open: Some(open),
+        own_mtime,
+        own_size,
+        incomplete,
+        filtered,
+        gitignore,
+        children: child_paths,
+        depth,
+    })
+}
+
+// An unfollowed symlink: the link itself is counted as (roughly) zero bytes and never
+// descended into.
+fn symlink_leaf(ctx: &ScanContext, path: &Path, dir_entry: &fs::DirEntry) -> DirEntry {
+    ctx.progress.entries.fetch_add(1, Ordering::Relaxed);
+    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mtime = dir_entry.metadata().and_then(|metadata| metadata.modified()).ok();
+    let broken_link = fs::metadata(path).is_err_and(|error| record_broken_link(ctx, path, &error));
+    let link_target = read_target(path);
+    DirEntry { name, is_symlink: true, link_target, broken_link, file_count: 1, inodes: 1, mtime, ..Default::default() }
+}
+
+// Where the symlink at `path` points, as written in the link.
+fn read_target(path: &Path) -> Option<String> {
+    fs::read_link(path).ok().map(|target| target.to_string_lossy().into_owned())
+}
+
+// Sums up a directory once all of its children are scanned and streams it to the TUI.
+fn finish_dir(dir: PendingDir, path: &Path, mut totals: DirTotals, ctx: &ScanContext, root: &ScanRoot) -> DirEntry {
+    let PendingDir { name, own_mtime, own_size, incomplete, filtered, depth, .. } = dir;
+
+    // A directory reached through a followed symlink cannot be removed by its path
+    let through_symlink = ctx.follow_symlinks && path.is_symlink();
+    let empty = !incomplete && !filtered && !through_symlink && totals.all_empty;
+    if empty && let Some(empty_dirs) = &ctx.empty_dirs {
+        empty_dirs.lock().unwrap().push(path.to_path_buf());
+    }
+    if totals.own_empty_files > 0 && let Some(empty_files) = &ctx.empty_files {
+        empty_files.lock().unwrap().push((path.to_path_buf(), totals.own_empty_files));
+    }
+
+    // Extension totals are summed per directory, so the shared map is locked once per directory
+    let mut by_extension = totals.by_extension;
+    let directories = by_extension.entry(breakdown::DIRECTORIES.to_string()).or_default();
+    directories.apparent_size += own_size.0;
+    directories.disk_usage += own_size.1;
+    ctx.extensions.merge(by_extension);
+
+    // 3. Aggregate size
+    let mut children_results = totals.kept;
+    if let Some(max_children) = ctx.max_children {
+        fold_smallest(&mut children_results, &mut totals.folded, max_children, ctx.size_mode);
+    }
+    if let Some(mut folded) = totals.folded {
+        folded.name = format!("({} more items, not kept)", format_count(folded.pruned));
+        children_results.push(folded);
+    }
+    let entry = DirEntry {
+        name,
+        is_dir: true,
+        apparent_size: own_size.0 + totals.apparent_size,
+        disk_usage: own_size.1 + totals.disk_usage,
+        incomplete,
+        file_count: totals.file_count,
+        dir_count: 1 + totals.dir_count,
+        inodes: 1 + totals.inodes,
+        mtime: totals.mtime.max(own_mtime),
+        empty_files: totals.empty_files,
+        empty,
+        ..Default::default()
+    };
+
+    // Below --max-depth the children only contribute their sizes; dropping them here keeps
+    // memory bounded since each level discards its subtree as soon as it is summed.
+    // Directories that deep are not shown, so they are not sent to the TUI either.
+    let entry = match ctx.max_depth {
+        Some(max_depth) if depth > max_depth => return DirEntry { truncated: !children_results.is_empty(), ..entry },
+        Some(max_depth) if depth == max_depth => DirEntry { truncated: !children_results.is_empty(), ..entry },
+        _ => DirEntry { children: children_results, ..entry },
+    };
+
+    if let Some(updates) = &ctx.updates {
+        let path = path.strip_prefix(&root.path).unwrap_or(path)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let _ = updates.send(ScanEvent::Directory { root: root.index, path, entry: live::shallow_copy(&entry) });
+    }
+    entry
+}
+
+// The children of a directory, summed up one at a time as they are scanned. Children
+// outside the --newer-than/--older-than window are dropped as they arrive. With
+// --max-children-per-dir only the largest children are kept: once there are twice as
+// many, the smaller half is folded into one summary entry, so even a directory with
+// millions of entries never holds all of them. The totals always include every child.
+struct DirTotals {
+    kept: Vec<DirEntry>,
+    folded: Option<DirEntry>,
+    apparent_size: u64,
+    disk_usage: u64,
+    file_count: u64,
+    dir_count: u64,
+    inodes: u64,
+    mtime: Option<SystemTime>,
+    empty_files: u64,
+    // Zero-length files directly in the directory
+    own_empty_files: u64,
+    // Every child found, including those outside the time window, is an empty directory
+    all_empty: bool,
+    // Per-extension totals of the files among the children
+    by_extension: HashMap<String, breakdown::Usage>,
+}
+
+impl DirTotals {
+    fn new() -> DirTotals {
+        DirTotals {
+            kept: Vec::new(),
+            folded: None,
+            apparent_size: 0,
+            disk_usage: 0,
+            file_count: 0,
+            dir_count: 0,
+            inodes: 0,
+            mtime: None,
+            empty_files: 0,
+            own_empty_files: 0,
+            all_empty: true,
+            by_extension: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, child: DirEntry, ctx: &ScanContext) {
+        // Decided before the time filter drops anything: a file outside the window is still there
+        self.all_empty &= child.empty;
+
+        // With --newer-than/--older-than only matching files remain, and directories only
+        // while they still contain one
+        if (ctx.newer_than.is_some() || ctx.older_than.is_some())
+            && !if child.dir_count == 0 { in_time_window(ctx, child.mtime) } else { child.file_count > 0 }
+        {
+            return;
+        }
+
+        // Only files that were actually counted are attributed, hard-linked ones once the
+        // scan is settled. A subdirectory's size belongs to its contents, which are
+        // attributed on their own, and to itself, which it attributes when it is finished.
+        if child.dir_count > 0 {
+            self.by_extension.entry(breakdown::DIRECTORIES.to_string()).or_default().add(0, 0);
+        } else if !(child.is_symlink || child.special.is_some() || child.shared.is_some() || child.linked || child.incomplete) {
+            let key = breakdown::extension_of(&child.name).unwrap_or_else(|| breakdown::NO_EXTENSION.to_string());
+            self.by_extension.entry(key).or_default().add(child.apparent_size, child.disk_usage);
+        }
+
+        self.apparent_size += child.apparent_size;
+        self.disk_usage += child.disk_usage;
+        self.file_count += child.file_count;
+        self.dir_count += child.dir_count;
+        self.inodes += child.inodes;
+        self.mtime = self.mtime.max(child.mtime);
+        self.empty_files += child.empty_files;
+        if child.dir_count == 0 {
+            self.own_empty_files += child.empty_files;
+        }
+        self.kept.push(child);
+        if let Some(max_children) = ctx.max_children
+            && self.kept.len() >= 2 * max_children
+        {
+            fold_smallest(&mut self.kept, &mut self.folded, max_children, ctx.size_mode);
+        }
+    }
+
+    // Combines the totals of two runs of children (`other` coming after `self`).
+    fn merge(mut self, other: DirTotals, ctx: &ScanContext) -> DirTotals {
+        self.apparent_size += other.apparent_size;
+        self.disk_usage += other.disk_usage;
+        self.file_count += other.file_count;
+        self.dir_count += other.dir_count;
+        self.inodes += other.inodes;
+        self.mtime = self.mtime.max(other.mtime);
+        self.empty_files += other.empty_files;
+        self.own_empty_files += other.own_empty_files;
+        self.all_empty &= other.all_empty;
+        for (key, usage) in other.by_extension {
+            let total = self.by_extension.entry(key).or_default();
+            total.apparent_size += usage.apparent_size;
+            total.disk_usage += usage.disk_usage;
+            total.files += usage.files;
+        }
+        self.kept.extend(other.kept);
+        if let Some(folded) = other.folded {
+            add_to_summary(self.folded.get_or_insert_with(DirEntry::default), &folded);
+        }
+        if let Some(max_children) = ctx.max_children
+            && self.kept.len() >= 2 * max_children
+        {
+            fold_smallest(&mut self.kept, &mut self.folded, max_children, ctx.size_mode);
+        }
+        self
+    }
+}
+
+// Keeps the `max_children` largest of `kept` and adds the others to the `folded` summary.
+fn fold_smallest(kept: &mut Vec<DirEntry>, folded: &mut Option<DirEntry>, max_children: usize, mode: SizeMode) {
+    if kept.len() <= max_children {
+        return;
+    }
+    kept.sort_by_key(|child| std::cmp::Reverse(child.size(mode)));
+    let summary = folded.get_or_insert_with(DirEntry::default);
+    for child in kept.drain(max_children..) {
+        add_to_summary(summary, &child);
+    }
+}
+
+// Adds the own (apparent, disk) size of a directory that is not read any further to the
+// extension totals and the progress counter; directories that are read are attributed in
+// `finish_dir`.
+fn count_dir_size(ctx: &ScanContext, own_size: (u64, u64)) {
+    let usage = breakdown::Usage { apparent_size: own_size.0, disk_usage: own_size.1, files: 0 };
+    ctx.extensions.merge(HashMap::from([(breakdown::DIRECTORIES.to_string(), usage)]));
+    count_dir_progress(ctx, own_size);
+}
+
+// Adds a directory's own size to the live byte counter (which counts files only with
+// --count-inodes).
+fn count_dir_progress(ctx: &ScanContext, own_size: (u64, u64)) {
+    let bytes = match ctx.size_mode {
+        SizeMode::DiskUsage => own_size.1,
+        SizeMode::Apparent => own_size.0,
+        SizeMode::Inodes => 0,
+    };
+    ctx.progress.bytes.fetch_add(bytes, Ordering::Relaxed);
+}
+
+// Whether a file with this mtime passes --newer-than/--older-than (unknown times never do
+// once a filter is set).
+fn in_time_window(ctx: &ScanContext, mtime: Option<SystemTime>) -> bool {
+    if ctx.newer_than.is_none() && ctx.older_than.is_none() {
+        return true;
+    }
+    mtime.is_some_and(|mtime| {
+        ctx.newer_than.is_none_or(|newer| mtime >= newer) && ctx.older_than.is_none_or(|older| mtime < older)
+    })
+}
+
+// Hides the children below `min_size` throughout the tree. The hidden siblings of each
+// directory are replaced by a single summary row, so the rows shown still add up.
+fn prune_small(entry: &mut DirEntry, min_size: u64, mode: SizeMode) {
+    let (kept, small): (Vec<DirEntry>, Vec<DirEntry>) = std::mem::take(&mut entry.children)
+        .into_iter()
+        .partition(|child| child.size(mode) >= min_size);
+    entry.children = kept;
+    for child in &mut entry.children {
+        prune_small(child, min_size, mode);
+    }
+    if !small.is_empty() {
+        entry.children.push(summary_entry(format!("({} smaller items)", format_count(small.len() as u64)), &small));
+    }
+}
+
+// Synthetic row standing in for `entries`, carrying their combined totals.
+fn summary_entry<'e>(name: String, entries: impl IntoIterator<Item = &'e DirEntry>) -> DirEntry {
+    let mut summary = DirEntry { name, ..Default::default() };
+    for entry in entries {
+        add_to_summary(&mut summary, entry);
+    }
+    summary
+}
+
+// Adds an entry (or another summary row, with all the entries it stands for) to a summary row.
+fn add_to_summary(summary: &mut DirEntry, entry: &DirEntry) {
+    summary.apparent_size += entry.apparent_size;
+    summary.disk_usage += entry.disk_usage;
+    summary.file_count += entry.file_count;
+    summary.dir_count += entry.dir_count;
+    summary.inodes += entry.inodes;
+    summary.mtime = summary.mtime.max(entry.mtime);
+    summary.empty_files += entry.empty_files;
+    summary.pruned += entry.pruned.max(1);
+}
+
+// Synthetic parent for several command-line roots; it exists only in memory, so its
+// size and counts are just the totals of the roots.
+fn virtual_root(children: Vec<DirEntry>) -> DirEntry {
+    let names: Vec<&str> = children.iter().map(|child| child.name.as_str()).collect();
+    DirEntry {
+        name: names.join(", "),
+        is_dir: true,
+        apparent_size: children.iter().map(|c| c.apparent_size).sum(),
+        disk_usage: children.iter().map(|c| c.disk_usage).sum(),
+        file_count: children.iter().map(|c| c.file_count).sum(),
+        dir_count: children.iter().map(|c| c.dir_count).sum(),
+        inodes: children.iter().map(|c| c.inodes).sum(),
+        empty_files: children.iter().map(|c| c.empty_files).sum(),
+        mtime: children.iter().filter_map(|c| c.mtime).max(),
+        children,
+        ..Default::default()
+    }
+}
+
+// Columns `text` takes in a terminal: two for wide characters (CJK, most emoji), none
+// for combining marks.
+fn display_width(text: &str) -> usize {
+    text.width()
+}
+
+// The longest start of `text` at most `width` columns wide, ending between two graphemes
+// so that no accent or emoji is cut apart.
+fn head(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += display_width(grapheme);
+        if used > width {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+// The longest end of `text` at most `width` columns wide, starting on a grapheme.
+fn tail(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    let mut start = text.len();
+    for (index, grapheme) in text.grapheme_indices(true).rev() {
+        used += display_width(grapheme);
+        if used > width {
+            break;
+        }
+        start = index;
+    }
+    &text[start..]
+}
+
+// Keeps the end of `text` (the most specific part of a path), marking the cut with
+// `ellipsis`, in at most `max_width` columns.
+fn truncate_left(text: &str, max_width: usize, ellipsis: &str) -> String {
+    if display_width(text) <= max_width {
+        return text.to_string();
+    }
+    let mark = display_width(ellipsis);
+    if max_width < mark {
+        return tail(text, max_width).to_string();
+    }
+    format!("{}{}", ellipsis, tail(text, max_width - mark))
+}
+
+// Keeps the start and end of `text`, cutting the middle out with `ellipsis`, for names
+// that would crowd out everything else.
+fn truncate_middle(text: &str, max_width: usize, ellipsis: &str) -> String {
+    if display_width(text) <= max_width {
+        return text.to_string();
+    }
+    let mark = display_width(ellipsis);
+    if max_width < mark {
+        return head(text, max_width).to_string();
+    }
+    let end = (max_width - mark) / 2;
+    format!("{}{}{}", head(text, max_width - mark - end), ellipsis, tail(text, end))
+}
+
+// The part of `text` that fits in `max_width` columns after skipping `offset` of them,
+// with `ellipsis` where something is cut off at either end. The offset stops where the
+// end of the text is in view, so panning a long way shows the tail of every name.
+fn pan_text(text: &str, offset: usize, max_width: usize, ellipsis: &str) -> String {
+    let width = display_width(text);
+    if width <= max_width {
+        return text.to_string();
+    }
+    let mark = display_width(ellipsis);
+    if max_width < mark {
+        return head(text, max_width).to_string();
+    }
+    if max_width == mark || offset == 0 {
+        return format!("{}{}", head(text, max_width - mark), ellipsis);
+    }
+    let rest = tail(text, width.saturating_sub(offset).max(max_width - mark));
+    if display_width(rest) + mark <= max_width {
+        format!("{}{}", ellipsis, rest)
+    } else {
+        format!("{}{}{}", ellipsis, head(rest, max_width.saturating_sub(2 * mark)), ellipsis)
+    }
+}
+
+fn record_error(ctx: &ScanContext, path: &Path, error: &io::Error) {
+    ctx.errors.lock().unwrap().push(ScanError { path: path.to_path_buf(), kind: error.kind() });
+}
+
+// Notes a symlink that could not be followed, returning whether it is broken. Resolving
+// fails for a missing target, for a link to another broken link, and for a loop of
+// links; a target that exists but is not accessible is not broken.
+fn record_broken_link(ctx: &ScanContext, path: &Path, error: &io::Error) -> bool {
+    if error.kind() == io::ErrorKind::PermissionDenied {
+        return false;
+    }
+    match fs::read_link(path) {
+        Ok(target) => {
+            ctx.broken_links.lock().unwrap().push(BrokenLink { path: path.to_path_buf(), target });
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+// The broken links found so far, by path.
+fn sorted_broken_links(ctx: &ScanContext) -> std::sync::MutexGuard<'_, Vec<BrokenLink>> {
+    let mut links = ctx.broken_links.lock().unwrap();
+    links.sort_by(|a, b| a.path.cmp(&b.path));
+    links
+}
+
+// Path relative to the scan root with '/' separators ("" for the root itself).
+fn relative_path(path: &Path, root: &ScanRoot) -> String {
+    let components: Vec<_> = path.strip_prefix(&root.path).unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+// Whether `dir` holds a CACHEDIR.TAG (https://bford.info/cachedir/). Only the signature
+// at the start of the file is read.
+fn has_cachedir_tag(dir: &Path) -> bool {
+    use std::io::Read;
+    const SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+    let mut header = [0u8; SIGNATURE.len()];
+    fs::File::open(dir.join("CACHEDIR.TAG"))
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| header == SIGNATURE)
+}
+
+// Checks the exclude patterns against both the entry's name and its path relative to the
+// scan root, and the exclude regexes against the relative path. With include regexes, a
+// file (never a directory, which may still hold matching files) must match one of them;
+// exclusion wins over inclusion.
+fn is_excluded(path: &Path, is_dir: bool, root: &ScanRoot, ctx: &ScanContext) -> bool {
+    let check_includes = !is_dir && !ctx.include_regexes.is_empty();
+    if ctx.excludes.is_empty() && ctx.exclude_regexes.is_empty() && !check_includes {
+        return false;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let relative = relative_path(path, root);
+
+    ctx.excludes.iter().any(|pattern| pattern.matches(&name) || pattern.matches(&relative))
+        || ctx.exclude_regexes.iter().any(|regex| regex.is_match(&relative))
+        || (check_includes && !ctx.include_regexes.iter().any(|regex| regex.is_match(&relative)))
+}
+
+// Whether the symlink at `path` is descended into: never without --follow-symlinks,
+// always with it, and with --follow-symlinks=within-root only if its resolved target is
+// inside the (resolved) root. Links that cannot be resolved stay leaves.
+fn follows_symlink(path: &Path, root: &ScanRoot, ctx: &ScanContext) -> bool {
+    if !ctx.follow_symlinks {
+        return false;
+    }
+    if !ctx.symlinks_within_root {
+        return true;
+    }
+    root.canonical.as_ref().is_some_and(|canonical| {
+        fs::canonicalize(path).is_ok_and(|target| target.starts_with(canonical))
+    })
+}
+
+// Device id of the filesystem holding an entry (None where the platform does not expose it).
+#[cfg(unix)]
+fn device_id(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+// Size of a filesystem and the space left on it for unprivileged users, in bytes.
+#[derive(Debug, Clone, Copy)]
+struct FsSpace {
+    total: u64,
+    available: u64,
+}
+
+// Capacity of the filesystem holding `path`, via statvfs. None if the call fails (some
+// FUSE mounts do not implement it) or the platform has no equivalent here.
+#[cfg(unix)]
+fn filesystem_space(path: &Path) -> Option<FsSpace> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is only read once statvfs has filled it in
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    let block = stat.f_frsize as u64;
+    Some(FsSpace { total: stat.f_blocks as u64 * block, available: stat.f_bavail as u64 * block })
+}
+
+#[cfg(not(unix))]
+fn filesystem_space(_path: &Path) -> Option<FsSpace> {
+    None
+}
+
+// Filesystem types whose contents are generated by the kernel rather than stored anywhere.
+// tmpfs is not among them: it holds real files, and df lists it.
+const VIRTUAL_FILESYSTEMS: &[&str] = &[
+    "proc", "sysfs", "devtmpfs", "devpts", "cgroup", "cgroup2", "securityfs", "debugfs",
+    "tracefs", "pstore", "bpf", "configfs", "fusectl", "mqueue", "hugetlbfs", "binfmt_misc",
+    "autofs", "efivarfs", "selinuxfs", "rpc_pipefs", "nsfs",
+];
+
+// Devices of the pseudo-filesystems mounted according to /proc/mounts; empty where that
+// file does not exist (anything but Linux).
+fn virtual_filesystem_devices() -> HashSet<u64> {
+    let Ok(mounts) = fs::read_to_string("/proc/mounts") else {
+        return HashSet::new();
+    };
+    mounts.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let mount_point = fields.nth(1)?;
+            let fs_type = fields.next()?;
+            VIRTUAL_FILESYSTEMS.contains(&fs_type).then(|| unescape_mount_point(mount_point))
+        })
+        .filter_map(|mount_point| fs::metadata(mount_point).ok().and_then(|metadata| device_id(&metadata)))
+        .collect()
+}
+
+// /proc/mounts writes spaces, tabs, newlines and backslashes in paths as octal escapes.
+fn unescape_mount_point(field: &str) -> String {
+    field.replace("\\040", " ").replace("\\011", "\t").replace("\\012", "\n").replace("\\134", "\\")
+}
+
+// Records the directory's unique identity; returns false if it was already visited.
+fn mark_dir_visited(path: &Path, metadata: &fs::Metadata, ctx: &ScanContext) -> bool {
+    match file_id(path, metadata) {
+        Ok(id) => ctx.dirs_seen.insert(id),
+        // Without an identity we cannot detect a cycle, so treat it as unvisited
+        Err(_) => true,
+    }
+}
+
+// Phase 2: Hardlink Analysis (Apparent Size Logic)
+// Returns the file's (apparent size, disk usage), or None if it was already counted.
+// Until the scan is settled (see hardlinks.rs) the first link found carries the size.
+fn get_dir_size_unique_file(
+    path: &Path,
+    metadata: &fs::Metadata,
+    root: &ScanRoot,
+    ctx: &ScanContext,
+) -> io::Result<Option<(u64, u64)>> {
+    let sizes = (metadata.len(), allocated_size(metadata));
+    if !tracks_links(ctx, link_count(metadata)) {
+        return Ok(Some(sizes));
+    }
+
+    // Get unique system file identity (Inode/Device)
+    let id = file_id(path, metadata)?;
+    
+    // Count size only if the identity is new (deduplication)
+    if ctx.linked_files.record(id, root.index, path, sizes, owner_id(metadata)) {
+        Ok(Some(sizes))
+    } else {
+        Ok(None) // Hardlink: Size is 0
+    }
+}
+
+// Whether a file with `links` links goes through the hard link table. A file with a
+// single link cannot show up anywhere else, so the table is skipped, unless symlinks are
+// followed: the target of a followed symlink is reached by its path and by every link
+// to it, all with a link count of 1. --hardlinks=count-all counts every link like a
+// separate file.
+fn tracks_links(ctx: &ScanContext, links: u64) -> bool {
+    ctx.hardlinks != HardlinkPolicy::CountAll && (links != 1 || ctx.follow_symlinks)
+}
+
+#[cfg(unix)]
+fn file_id(_path: &Path, metadata: &fs::Metadata) -> io::Result<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _metadata: &fs::Metadata) -> io::Result<FileId> {
+    Handle::from_path(path)
+}
+
+#[cfg(unix)]
+fn owner_id(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn owner_id(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+// Permission bits (rwx for owner, group and others, with setuid, setgid and sticky).
+#[cfg(unix)]
+fn permission_bits(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permission_bits(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+// What the filesystem says about an entry beyond what the scan keeps, for the details
+// panel; each is None where the platform does not report it.
+#[derive(Debug, Default)]
+struct RawMetadata {
+    device: Option<u64>,
+    inode: Option<u64>,
+    // Permission bits, as permission_bits() reads them
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    links: Option<u64>,
+}
+
+#[cfg(unix)]
+fn raw_metadata(metadata: &fs::Metadata) -> RawMetadata {
+    use std::os::unix::fs::MetadataExt;
+    RawMetadata {
+        device: Some(metadata.dev()),
+        inode: Some(metadata.ino()),
+        mode: permission_bits(metadata),
+        uid: Some(metadata.uid()),
+        gid: Some(metadata.gid()),
+        links: Some(metadata.nlink()),
+    }
+}
+
+#[cfg(not(unix))]
+fn raw_metadata(_metadata: &fs::Metadata) -> RawMetadata {
+    RawMetadata::default()
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+// The link count is not exposed on stable std elsewhere, so every file counts as
+// possibly linked (0 = unknown)
+#[cfg(not(unix))]
+fn link_count(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+// Bytes allocated on disk; sparse files report less than their length, tiny files more.
+#[cfg(unix)]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // st_blocks is always counted in 512-byte units, regardless of the filesystem block size
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+// A file counts as sparse once at least SPARSE_MIN_HOLE of its length is unallocated;
+// smaller gaps are ordinary slack (inline data, compression) rather than holes.
+const SPARSE_MIN_HOLE: u64 = 1024 * 1024;
+
+fn is_sparse(apparent_size: u64, disk_usage: u64) -> bool {
+    apparent_size >= disk_usage + SPARSE_MIN_HOLE
+}
+
+// --- TUI RENDERING LOGIC (Phase 3) ---
+
+// How sizes in bytes are written: rounded in powers of 1024 (KiB, MiB, ...), rounded in
+// powers of 1000 (kB, MB, ...) with --si or 'U', or with every digit with --bytes or 'b',
+// for when sizes have to add up to what other tools report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Units {
+    #[default]
+    Binary,
+    Decimal,
+    Exact,
+}
+
+impl Units {
+    // The units of --bytes (`exact`) and --si, exact byte counts winning
+    fn new(exact: bool, si: bool) -> Units {
+        if exact {
+            Units::Exact
+        } else if si {
+            Units::Decimal
+        } else {
+            Units::Binary
+        }
+    }
+}
+
+// A number of bytes with a unit, rounded to a tenth, or with every digit (see Units).
+fn format_size(bytes: u64, units: Units) -> String {
+    let (base, names) = match units {
+        Units::Exact => return format_count(bytes),
+        Units::Binary => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB"]),
+        Units::Decimal => (1000.0, ["B", "kB", "MB", "GB", "TB"]),
+    };
+    let mut size = bytes as f64;
+    let mut unit_index = 0;
+
+    while size >= base && unit_index < names.len() - 1 {
+        size /= base;
+        unit_index += 1;
+    }
+    format!("{:.1} {}", size, names[unit_index])
+}
+
+// A value of the given mode: bytes as format_size shows them, inodes as a plain count.
+fn format_amount(value: u64, mode: SizeMode, units: Units) -> String {
+    match mode {
+        SizeMode::DiskUsage | SizeMode::Apparent => format_size(value, units),
+        SizeMode::Inodes => format_count(value),
+    }
+}
+
+// Groups digits in thousands: 1234567 -> "1,234,567".
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// "*.mp4" for an extension; the catch-all groups keep their own names.
+fn extension_label(extension: &str) -> String {
+    if extension == breakdown::NO_EXTENSION || extension == breakdown::DIRECTORIES {
+        extension.to_string()
+    } else {
+        format!("*.{}", extension)
+    }
+}
+
+// Compact timestamp: the time of day for today's changes, otherwise the date.
+fn format_mtime(mtime: Option<SystemTime>, today: NaiveDate) -> String {
+    match mtime {
+        Some(time) => {
+            let local: DateTime<Local> = time.into();
+            if local.date_naive() == today {
+                local.format("%H:%M").to_string()
+            } else {
+                local.format("%Y-%m-%d").to_string()
+            }
+        }
+        None => "-".to_string(),
+    }
+}
+
+// Bands of the age view, newest first: each applies to entries modified less than the
+// given number of days ago, with the last one for everything older. The theme has a style
+// for each.
+const AGE_BANDS: [(u64, &str); 5] = [
+    (7, "< 1 week"),
+    (30, "< 1 month"),
+    (365, "< 1 year"),
+    (3 * 365, "< 3 years"),
+    (u64::MAX, "older"),
+];
+
+// Style of an entry last modified at `mtime`; entries without a readable time keep the
+// terminal's default color, and times in the future count as new.
+fn age_style(mtime: Option<SystemTime>, now: SystemTime, theme: &Theme) -> Style {
+    let Some(mtime) = mtime else {
+        return Style::default();
+    };
+    let days = now.duration_since(mtime).map_or(0, |age| age.as_secs() / 86_400);
+    let band = AGE_BANDS.iter().position(|(max_days, _)| days < *max_days).unwrap_or(AGE_BANDS.len() - 1);
+    theme.ages[band]
+}
+
+// Legend for the bottom border while colors show ages.
+fn age_legend(theme: &Theme, swatch: &str) -> Line<'static> {
+    let mut legend = Line::raw(" ");
+    for ((_, label), style) in AGE_BANDS.iter().zip(theme.ages) {
+        legend.push_span(Span::styled(format!("{} {}  ", swatch, label), style));
+    }
+    legend
+}
+
+// The directories from the root to the one listed, like "/data ▸ media ▸ videos", in a
+// line `width` columns wide. Names longer than a third of it are cut in the middle,
+// and the directories nearest the root give way to an ellipsis first, so that the one
+// listed stays visible.
+fn breadcrumb(components: &[&str], width: usize, glyphs: &Glyphs, theme: &Theme) -> Line<'static> {
+    let separator = glyphs.separator;
+    let names: Vec<String> = components.iter().map(|name| truncate_middle(name, (width / 3).max(8), glyphs.ellipsis)).collect();
+    let separator_width = display_width(separator);
+    let mut hidden = names.len();
+    let mut used = 0;
+    while hidden > 0 {
+        let name_width = display_width(&names[hidden - 1]);
+        let gap = if hidden == names.len() { 0 } else { separator_width };
+        // Room for "… ▸ " in front unless this is the root
+        let ellipsis = if hidden > 1 { display_width(glyphs.ellipsis) + separator_width } else { 0 };
+        if used + name_width + gap + ellipsis > width && hidden < names.len() {
+            break;
+        }
+        used += name_width + gap;
+        hidden -= 1;
+    }
+    let mut spans = Vec::new();
+    if hidden > 0 {
+        spans.push(Span::styled(glyphs.ellipsis, theme.breadcrumb));
+        spans.push(Span::styled(separator.to_string(), theme.breadcrumb));
+    }
+    for (i, name) in names.into_iter().enumerate().skip(hidden) {
+        if i + 1 == components.len() {
+            spans.push(Span::styled(truncate_left(&name, width, glyphs.ellipsis), theme.breadcrumb.add_modifier(Modifier::BOLD)));
+        } else {
+            spans.push(Span::styled(name, theme.breadcrumb));
+            spans.push(Span::styled(separator.to_string(), theme.breadcrumb));
+        }
+    }
+    Line::from(spans)
+}
+
+// A rectangle of the given percentages of `area`'s size, in its middle.
+fn centered(area: Rect, width_percent: u16, height_percent: u16) -> Rect {
+    // Up to 100%, the rectangle fits in `area`
+    debug_assert!(width_percent <= 100 && height_percent <= 100);
+    // In u32, as a wide terminal times a percentage does not fit in a u16
+    let width = (u32::from(area.width) * u32::from(width_percent) / 100) as u16;
+    let height = (u32::from(area.height) * u32::from(height_percent) / 100) as u16;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+// Rows of the tree view, rebuilt for every frame. `rows` records what each one shows, so
+// keys can act on the selected row.
+struct TreeRows<'u> {
+    // Text and style of each row, as shown and as exported ('E')
+    items: Vec<(String, Style)>,
+    rows: Vec<TreeRow>,
+    // Directories whose entries are listed below them
+    expanded: &'u HashSet<Vec<String>>,
+    // Directories whose children are all listed even while folding is on
+    unfolded: &'u HashSet<Vec<String>>,
+    // Entries marked to be deleted together, drawn with a '*' before their names
+    marked: &'u BTreeSet<Vec<String>>,
+    // What ages are measured against in the age colors
+    now: SystemTime,
+    // Changes made today show the time instead of the date
+    today: NaiveDate,
+    // Characters between the brackets of the size bars
+    bar_width: usize,
+    // For each directory between the one viewed and the current row (exclusive),
+    // whether it is the last entry of its own directory
+    guides: Vec<bool>,
+    // Whether the entries at the top are the roots
+    multiple_roots: bool,
+    // Size of the whole tree, for shares of it ('P')
+    root_size: u64,
+    // Whether the tree is narrowed by a filter (Ctrl+F): every directory left in it is
+    // expanded and none is folded, so that every match is listed
+    filtered: bool,
+    // Columns each row has on the screen, which names are cut to fit in so that the sizes
+    // after them stay in view, and how many columns of the names are panned past ('['
+    // and ']'); None for an export, which keeps whole names
+    width: Option<usize>,
+    pan: usize,
+    // Columns by which the longest name is cut, as far as panning can go
+    overflow: usize,
+}
+
+// The rows of the tree view, listing the directory viewed below `root`, in a terminal
+// `columns` wide, with the names cut to fit in rows `width` columns wide if given. Also
+// returns how far the names can be panned.
+fn tree_rows(
+    root: &DirEntry,
+    app: &App,
+    columns: u16,
+    width: Option<usize>,
+    multiple_roots: bool,
+    display: &DisplayOptions,
+) -> (Vec<(String, Style)>, Vec<TreeRow>, usize) {
+    let mut rows = TreeRows {
+        items: Vec::new(),
+        rows: Vec::new(),
+        expanded: &app.expanded,
+        unfolded: &app.unfolded,
+        marked: &app.marked,
+        now: SystemTime::now(),
+        today: Local::now().date_naive(),
+        bar_width: bar_width(columns),
+        guides: Vec::new(),
+        multiple_roots,
+        root_size: root.size(display.size_mode),
+        filtered: app.filter.as_ref().is_some_and(|query| !query.is_empty()),
+        width,
+        pan: app.pan,
+        overflow: 0,
+    };
+    let viewed = entry_at(root, &app.view).unwrap_or(root);
+    build_child_items(viewed, &mut rows, &mut app.view.clone(), None, 0, display);
+    (rows.items, rows.rows, rows.overflow)
+}
+
+// The rows of the whole tree for --print, every directory expanded: the root's and those
+// below it, or with several roots those of each root.
+fn printed_rows(tree: &DirEntry, multiple_roots: bool, display: &DisplayOptions) -> Vec<(String, Style)> {
+    fn directories(entry: &DirEntry, path: &mut Vec<String>, expanded: &mut HashSet<Vec<String>>) {
+        if entry.children.is_empty() {
+            return;
+        }
+        expanded.insert(path.clone());
+        for child in &entry.children {
+            path.push(child.name.clone());
+            directories(child, path, expanded);
+            path.pop();
+        }
+    }
+    let mut expanded = HashSet::new();
+    directories(tree, &mut Vec::new(), &mut expanded);
+    let (unfolded, marked) = (HashSet::new(), BTreeSet::new());
+    let mut rows = TreeRows {
+        items: Vec::new(),
+        rows: Vec::new(),
+        expanded: &expanded,
+        unfolded: &unfolded,
+        marked: &marked,
+        now: SystemTime::now(),
+        today: Local::now().date_naive(),
+        bar_width: 0,
+        guides: Vec::new(),
+        multiple_roots,
+        root_size: tree.size(display.size_mode),
+        filtered: false,
+        width: None,
+        pan: 0,
+        overflow: 0,
+    };
+    if multiple_roots {
+        build_child_items(tree, &mut rows, &mut Vec::new(), None, 0, display);
+    } else {
+        build_list_items(tree, &mut rows, &mut Vec::new(), None, tree.size(display.size_mode), display);
+    }
+    rows.items
+}
+
+// Adds the row of `entry` and, if it is expanded, those of its children. `parent` is the
+// row of the directory it is listed in, None for the entries of the directory viewed, and
+// `parent_size` the size of that directory.
+fn build_list_items(
+    entry: &DirEntry,
+    rows: &mut TreeRows,
+    path: &mut Vec<String>,
+    parent: Option<usize>,
+    parent_size: u64,
+    display: &DisplayOptions,
+) {
+    let glyphs = display.glyphs();
+    let size = entry.size(display.size_mode);
+    let units = display.units();
+    let mut size_unit = format_amount(size, display.size_mode, units);
+    // Sparse files show the other size too, since the two differ so much
+    if entry.sparse {
+        size_unit = match display.size_mode {
+            SizeMode::DiskUsage => format!("{} ({} apparent)", size_unit, format_size(entry.apparent_size, units)),
+            SizeMode::Apparent => format!("{} ({} on disk)", size_unit, format_size(entry.disk_usage, units)),
+            SizeMode::Inodes => size_unit,
+        };
+    }
+    // Hard links explain themselves instead of silently showing 0 bytes; with
+    // --hardlinks=shared each link carries a share of the file
+    if let Some((shared_apparent, shared_disk)) = entry.shared {
+        let describe = |total: u64| if size > 0 {
+            format!("share of {}", format_size(total, units))
+        } else {
+            format!("{} shared", format_size(total, units))
+        };
+        size_unit = match display.size_mode {
+            SizeMode::DiskUsage => format!("{} (hardlink, {})", size_unit, describe(shared_disk)),
+            SizeMode::Apparent => format!("{} (hardlink, {})", size_unit, describe(shared_apparent)),
+            SizeMode::Inodes if size > 0 => format!("{} ({} links)", size_unit, entry.links),
+            SizeMode::Inodes => format!("{} (hardlink, counted elsewhere)", size_unit),
+        };
+    } else if entry.links > 1 {
+        size_unit = format!("{} ({} links)", size_unit, entry.links);
+    }
+    // Branch guides: a continuation column for each directory above that has more
+    // entries below, then the entry's own branch
+    let mut prefix = String::new();
+    if let Some((&last, above)) = rows.guides.split_last() {
+        for &ancestor_last in above {
+            prefix.push_str(if ancestor_last { "    " } else { glyphs.continuation });
+        }
+        prefix.push_str(if last { glyphs.last_branch } else { glyphs.branch });
+    }
+    let expandable = !entry.children.is_empty();
+    let expanded = expandable && (rows.filtered || rows.expanded.contains(path));
+    let indicator = match (expandable, expanded) {
+        (true, true) => glyphs.expanded,
+        (true, false) => glyphs.collapsed,
+        (false, _) => "  ",
+    };
+
+    let marked = rows.marked.contains(path);
+    let style = if marked { display.theme.marked } else { entry_style(entry, size, display, rows.now) };
+
+    let marker = if entry.skipped_virtual {
+        " [virtual filesystem, skipped]"
+    } else if entry.skipped_mount {
+        " [other filesystem, skipped]"
+    } else if entry.skipped_cache {
+        " [cache, skipped]"
+    } else if entry.is_symlink && entry.link_target.is_none() {
+        "@"
+    } else {
+        ""
+    };
+    // Symlinks show where they point, as written in the link
+    let link = match &entry.link_target {
+        Some(target) => format!(
+            " -> {}{}",
+            truncate_middle(target, LINK_TARGET_CHARS, glyphs.ellipsis),
+            if entry.broken_link { " [broken]" } else { "" },
+        ),
+        None => String::new(),
+    };
+    let sparse = if entry.sparse { " (sparse)" } else { "" };
+    let special = entry.special.map(|kind| format!(" [{}]", kind.label())).unwrap_or_default();
+    let hidden = if entry.truncated { " [...]" } else { "" };
+    let incomplete = if entry.incomplete { " [unreadable]" } else { "" };
+    let mut text = String::new();
+    if display.show_mtime {
+        text.push_str(&format!("{:>10}  ", format_mtime(entry.mtime, rows.today)));
+    }
+    // An empty directory has no shares to show
+    let whole = if display.share_of_root { rows.root_size } else { parent_size };
+    let share = if whole == 0 { 0.0 } else { size as f64 / whole as f64 };
+    match display.bars {
+        Bars::Bar => text.push_str(&format!("[{}] {:>5.1}%  ", size_bar(share, rows.bar_width, glyphs.bar), share * 100.0)),
+        Bars::Percent => text.push_str(&format!("{:>5.1}%  ", share * 100.0)),
+        Bars::Off => {}
+    }
+    text.push_str(&format!("{}{}{}", prefix, indicator, if marked { "*" } else { "" }));
+    // The icon is part of the row's text, so it takes the row's color and its column
+    // counts against the room left for the name
+    if display.icons && !display.ascii {
+        text.push_str(icons::icon(entry));
+        text.push(' ');
+    }
+    // Directories end in '/', like `ls -F`, unless the name already does ("/")
+    let name = if entry.is_dir && !entry.name.ends_with('/') { format!("{}/", entry.name) } else { entry.name.clone() };
+    let mut after = format!("{}{}{}{}{}{} | {}", link, marker, special, sparse, hidden, incomplete, size_unit);
+    if entry.dir_count > 0 {
+        let noun = if entry.file_count == 1 { "file" } else { "files" };
+        after.push_str(&format!(" | {} {}", format_count(entry.file_count), noun));
+    }
+    // The name gets what the rest of the row leaves, but never less than a few columns
+    match rows.width {
+        Some(width) => {
+            let room = width.saturating_sub(display_width(&text) + display_width(&after)).max(MIN_NAME_WIDTH);
+            let name_width = display_width(&name);
+            if name_width > room {
+                rows.overflow = rows.overflow.max(name_width - (room - 1));
+            }
+            text.push_str(&pan_text(&name, rows.pan, room, glyphs.ellipsis));
+        }
+        None => text.push_str(&name),
+    }
+    text.push_str(&after);
+    rows.items.push((text, style));
+    let row = rows.rows.len();
+    rows.rows.push(TreeRow { path: path.clone(), parent, expandable, summary: entry.pruned > 0 });
+    if expanded {
+        build_child_items(entry, rows, path, Some(row), rows.guides.len() + 1, display);
+    }
+}
+
+// Style of the row (or treemap cell) of `entry`, `size` big: by what it is, and then by
+// its size, or by its age with 'H'. Directories add the theme's directory style to that.
+fn entry_style(entry: &DirEntry, size: u64, display: &DisplayOptions, now: SystemTime) -> Style {
+    let theme = display.theme;
+    let style = if entry.skipped_mount || entry.skipped_cache || entry.skipped_virtual || entry.pruned > 0 {
+        theme.skipped
+    } else if entry.incomplete || entry.broken_link {
+        theme.unreadable
+    } else if display.color_by_age {
+        age_style(entry.mtime, now, theme)
+    } else if entry.is_symlink {
+        theme.symlink
+    } else if entry.special.is_some() {
+        theme.special
+    } else if display.size_colors.crit > 0 && size > display.size_colors.crit {
+        theme.large
+    } else if display.size_colors.warn > 0 && size > display.size_colors.warn {
+        theme.medium
+    } else {
+        theme.small
+    };
+    if entry.is_dir { style.patch(theme.directory) } else { style }
+}
+
+// Draws the entries of `entry` as a treemap in `area` ('t'), and hands `app` where each
+// one is. Entries without a size take no room, so they are left out.
+fn render_treemap(f: &mut Frame, entry: &DirEntry, area: Rect, app: &mut App, display: &DisplayOptions, multiple_roots: bool) {
+    let mut children: Vec<&DirEntry> = entry.children.iter()
+        .filter(|child| child.size(display.size_mode) > 0 && !display.hides(child, &app.view, multiple_roots))
+        .collect();
+    children.sort_by(|a, b| SortOrder::Size.compare(a, b, display.size_mode, false));
+    let sizes: Vec<u64> = children.iter().map(|child| child.size(display.size_mode)).collect();
+    let mut cells = Vec::new();
+    let mut shown = Vec::new();
+    for (child, rect) in children.into_iter().zip(treemap::layout(&sizes, area)) {
+        if rect.width == 0 || rect.height == 0 {
+            continue;
+        }
+        let mut path = app.view.clone();
+        path.push(child.name.clone());
+        cells.push(Cell { area: rect, path, expandable: !child.children.is_empty() });
+        shown.push((child, rect));
+    }
+    app.set_cells(cells);
+    if shown.is_empty() {
+        f.render_widget(Paragraph::new("Nothing here takes any space"), area);
+        return;
+    }
+    let selected = app.treemap.selected();
+    let now = SystemTime::now();
+    for (index, (child, rect)) in shown.into_iter().enumerate() {
+        let size = child.size(display.size_mode);
+        let mut style = entry_style(child, size, display, now);
+        if selected == Some(index) {
+            style = style.patch(display.theme.selection);
+        }
+        // Cells too small for a border and a name are filled in instead
+        if rect.width >= 4 && rect.height >= 3 {
+            let suffix = if child.is_dir { "/" } else { "" };
+            let name = truncate_middle(&format!("{}{}", child.name, suffix), rect.width as usize - 2, display.glyphs().ellipsis);
+            let cell = Paragraph::new(format_amount(size, display.size_mode, display.units()))
+                .style(style)
+                .block(display.block(Borders::ALL).title(name).border_style(style));
+            f.render_widget(cell, rect);
+        } else {
+            let fill = vec![display.glyphs().bar.repeat(rect.width as usize); rect.height as usize].join("\n");
+            f.render_widget(Paragraph::new(fill).style(style), rect);
+        }
+    }
+}
+
+// Adds the rows of the children of the directory at `path`, `parent` being its row and
+// `level` how far they are below the directory viewed (those directly in it get no
+// branches).
+fn build_child_items(
+    entry: &DirEntry,
+    rows: &mut TreeRows,
+    path: &mut Vec<String>,
+    parent: Option<usize>,
+    level: usize,
+    display: &DisplayOptions,
+) {
+    let size = entry.size(display.size_mode);
+    let (mut listed, mut folded) = match display.fold {
+        Some(fold) if !rows.filtered && !rows.unfolded.contains(path) => fold_children(entry, fold, display.size_mode),
+        _ => (entry.children.iter().collect(), Vec::new()),
+    };
+    listed.retain(|child| !display.hides(child, path, rows.multiple_roots));
+    folded.retain(|child| !display.hides(child, path, rows.multiple_roots));
+    listed.sort_by(|a, b| display.compare(a, b));
+    let other = (!folded.is_empty())
+        .then(|| summary_entry(format!("({} other items)", format_count(folded.len() as u64)), folded));
+    let count = listed.len() + other.is_some() as usize;
+    // The "(N other items)" row has the path of the directory itself
+    let children = listed.into_iter().map(|child| (child, false)).chain(other.iter().map(|other| (other, true)));
+    for (index, (child, summary)) in children.enumerate() {
+        if level > 0 {
+            rows.guides.push(index + 1 == count);
+        }
+        if !summary {
+            path.push(child.name.clone());
+        }
+        build_list_items(child, rows, path, parent, size, display);
+        if !summary {
+            path.pop();
+        }
+        if level > 0 {
+            rows.guides.pop();
+        }
+    }
+}
+
+// Width of the size bars for a terminal `columns` wide, leaving most of a row to the
+// names and sizes.
+fn bar_width(columns: u16) -> usize {
+    (columns as usize / 8).clamp(4, 30)
+}
+
+// A bar `width` characters long, filled in proportion to `share` (0 to 1).
+fn size_bar(share: f64, width: usize, fill: &str) -> String {
+    let filled = ((share * width as f64).round() as usize).min(width);
+    format!("{}{}", fill.repeat(filled), " ".repeat(width - filled))
+}
+
+// The entry at `path` below `entry`, if there is one.
+fn entry_at<'e>(entry: &'e DirEntry, path: &[String]) -> Option<&'e DirEntry> {
+    path.iter().try_fold(entry, |node, name| node.children.iter().find(|child| &child.name == name))
+}
+
+// Path of the entry at `path` below the root. With several roots the first component is
+// already the path a root was given as.
+fn entry_path(root: &DirEntry, path: &[String], multiple_roots: bool) -> PathBuf {
+    match path.split_first() {
+        Some((first, rest)) if multiple_roots => rest.iter().fold(PathBuf::from(first), |full, name| full.join(name)),
+        _ => path.iter().fold(PathBuf::from(&root.name), |full, name| full.join(name)),
+    }
+}
+
+// The roots of the whole tree, by their absolute paths and their paths below the tree:
+// the tree itself, or with several roots the entries at its top.
+fn absolute_roots(tree: &DirEntry, multiple_roots: bool) -> Vec<(PathBuf, Vec<String>)> {
+    let roots: Vec<(&str, Vec<String>)> = if multiple_roots {
+        tree.children.iter().map(|root| (root.name.as_str(), vec![root.name.clone()])).collect()
+    } else {
+        vec![(tree.name.as_str(), Vec::new())]
+    };
+    roots.into_iter()
+        .filter_map(|(name, path)| Some((std::path::absolute(name).ok()?, path)))
+        .collect()
+}
+
+// Path below the whole tree of the entry at the absolute path `full`, if it is below one
+// of `roots`.
+fn tree_path(full: &Path, roots: &[(PathBuf, Vec<String>)]) -> Option<Vec<String>> {
+    roots.iter().find_map(|(root, top)| {
+        let rest = full.strip_prefix(root).ok()?;
+        let names = rest.components().map(|component| component.as_os_str().to_string_lossy().into_owned());
+        Some(top.iter().cloned().chain(names).collect())
+    })
+}
+
+// The other way around: the absolute path of the entry at `path` below the whole tree.
+fn absolute_path(path: &[String], roots: &[(PathBuf, Vec<String>)]) -> Option<PathBuf> {
+    roots.iter()
+        .find(|(_, top)| path.starts_with(top))
+        .map(|(root, top)| path[top.len()..].iter().fold(root.clone(), |full, name| full.join(name)))
+}
+
+// Writes the bookmarks to the file they are read from at startup, saying so if that fails.
+fn save_bookmarks(app: &mut App, bookmarked: &[Vec<String>], roots: &[(PathBuf, Vec<String>)]) {
+    let dirs: Vec<PathBuf> = roots.iter().map(|(root, _)| root.clone()).collect();
+    let full: Vec<PathBuf> = bookmarked.iter().filter_map(|path| absolute_path(path, roots)).collect();
+    if let Err(e) = bookmarks::save(&dirs, &full) {
+        app.set_status(format!("Could not save the bookmarks: {}", e));
+    }
+}
+
+// Looks for entries whose name contains `query` ignoring case or, if the query has a '/'
+// in it, whose path below the root does. Returns the path of the first match after
+// `after` in the order of the tree view, wrapping around to the first one.
+fn search_tree(
+    root: &DirEntry,
+    query: &str,
+    after: Option<&[String]>,
+    multiple_roots: bool,
+    display: &DisplayOptions,
+) -> Option<Vec<String>> {
+    let mut search = Search {
+        query: query.to_lowercase(),
+        by_path: query.contains('/'),
+        multiple_roots,
+        after,
+        passed: after.is_none(),
+        first: None,
+        found: None,
+    };
+    search.visit(root, &mut Vec::new(), display);
+    search.found.or(search.first)
+}
+
+struct Search<'a> {
+    query: String,
+    by_path: bool,
+    multiple_roots: bool,
+    after: Option<&'a [String]>,
+    // Whether the entry at `after` was visited
+    passed: bool,
+    // The first match in the whole tree, and the first one after `after`
+    first: Option<Vec<String>>,
+    found: Option<Vec<String>>,
+}
+
+impl Search<'_> {
+    fn visit(&mut self, entry: &DirEntry, path: &mut Vec<String>, display: &DisplayOptions) {
+        let mut children: Vec<&DirEntry> = entry.children.iter()
+            .filter(|child| child.pruned == 0 && !display.hides(child, path, self.multiple_roots))
+            .collect();
+        children.sort_by(|a, b| display.compare(a, b));
+        for child in children {
+            if self.found.is_some() {
+                return;
+            }
+            path.push(child.name.clone());
+            let text = if self.by_path { path.join("/") } else { child.name.clone() };
+            if text.to_lowercase().contains(&self.query) {
+                if self.passed {
+                    self.found = Some(path.clone());
+                } else if self.first.is_none() {
+                    self.first = Some(path.clone());
+                }
+            }
+            if self.after == Some(path.as_slice()) {
+                self.passed = true;
+            }
+            self.visit(child, path, display);
+            path.pop();
+        }
+    }
+}
+
+// The largest files anywhere in the tree ('f'). Finding them walks the whole tree, so it is
+// done again only once the tree or what is counted changes, not for every frame.
+struct LargestFiles {
+    // Version of the tree, size mode, user whose files are shown and whether dot entries
+    // are hidden, as of the last search
+    key: Option<(u64, SizeMode, Option<u32>, bool)>,
+    // Path below the root and size of each file, largest first
+    files: Vec<(Vec<String>, u64)>,
+}
+
+impl LargestFiles {
+    fn update(&mut self, key: (u64, SizeMode, Option<u32>, bool), root: &DirEntry, display: &DisplayOptions, multiple_roots: bool) {
+        if self.key == Some(key) {
+            return;
+        }
+        let mut largest = BinaryHeap::new();
+        let mut path = Vec::new();
+        collect_largest(root, &mut path, &mut largest, LARGEST_FILES, display, multiple_roots);
+        self.files = largest.into_sorted_vec().into_iter().map(|Reverse((size, path))| (path, size)).collect();
+        self.key = Some(key);
+    }
+}
+
+// Keeps the `count` largest files below `entry` in `largest`, smallest on top. The path
+// of a file is only copied when it makes it in. Symlinks are left out, and so is a hard
+// link that counts nothing because another link of its file carries the size, so that
+// a file is there once.
+fn collect_largest(
+    entry: &DirEntry,
+    path: &mut Vec<String>,
+    largest: &mut BinaryHeap<Reverse<(u64, Vec<String>)>>,
+    count: usize,
+    display: &DisplayOptions,
+    multiple_roots: bool,
+) {
+    for child in &entry.children {
+        if child.pruned > 0 || display.hides(child, path, multiple_roots) {
+            continue;
+        }
+        path.push(child.name.clone());
+        if child.dir_count > 0 {
+            collect_largest(child, path, largest, count, display, multiple_roots);
+        } else {
+            let size = child.size(display.size_mode);
+            if child.is_symlink || (child.shared.is_some() && size == 0) {
+                // Not a file, or one already counted at another path
+            } else if largest.len() < count {
+                largest.push(Reverse((size, path.clone())));
+            } else if largest.peek().is_some_and(|Reverse((smallest, _))| size > *smallest) {
+                largest.pop();
+                largest.push(Reverse((size, path.clone())));
+            }
+        }
+        path.pop();
+    }
+}
+
+// The largest of the entries listed in `entry`, which is at `path`, the one first by
+// name among those as large.
+fn largest_child<'a>(entry: &'a DirEntry, path: &[String], display: &DisplayOptions, multiple_roots: bool) -> Option<&'a DirEntry> {
+    entry.children.iter()
+        .filter(|child| child.pruned == 0 && !display.hides(child, path, multiple_roots))
+        .min_by(|a, b| b.size(display.size_mode).cmp(&a.size(display.size_mode)).then_with(|| a.name.cmp(&b.name)))
+}
+
+// Keeps the size and path of the largest file below `entry` in `largest`, the one first
+// by path among those as large. The path is only copied for a file larger than the last.
+fn largest_file(
+    entry: &DirEntry,
+    path: &mut Vec<String>,
+    largest: &mut Option<(u64, Vec<String>)>,
+    display: &DisplayOptions,
+    multiple_roots: bool,
+) {
+    for child in &entry.children {
+        if child.pruned > 0 || display.hides(child, path, multiple_roots) {
+            continue;
+        }
+        path.push(child.name.clone());
+        if child.dir_count > 0 {
+            largest_file(child, path, largest, display, multiple_roots);
+        } else {
+            let size = child.size(display.size_mode);
+            if largest.as_ref().is_none_or(|(most, first)| size > *most || (size == *most && *path < *first)) {
+                *largest = Some((size, path.clone()));
+            }
+        }
+        path.pop();
+    }
+}
+
+// The filter in lower case, version of the tree, user whose files are shown, directory
+// viewed and whether dot entries are hidden
+type FilterKey = (String, u64, Option<u32>, Vec<String>, bool);
+
+// The tree narrowed by the filter (Ctrl+F), worked out again only when the filter or
+// the tree changes rather than in every frame.
+#[derive(Default)]
+struct NameFilter {
+    // What the tree was last filtered for
+    key: Option<FilterKey>,
+    // The entries that match and the directories above them (and the one viewed with
+    // those above it, which stay even without matches), with their true sizes
+    tree: DirEntry,
+    // Entries whose names match
+    matches: u64,
+}
+
+impl NameFilter {
+    fn update(&mut self, app: &App, version: u64, root: &DirEntry, display: &DisplayOptions, multiple_roots: bool) {
+        let query = app.filter.as_deref().unwrap_or_default().to_lowercase();
+        let key = (query, version, app.owner_filter, app.view.clone(), display.hide_dotfiles);
+        if self.key.as_ref() == Some(&key) {
+            return;
+        }
+        let mut matches = 0;
+        let children = filtered_children(root, &key.0, Some(&app.view), 0, &mut matches, display, multiple_roots);
+        self.tree = DirEntry { name: root.name.clone(), link_target: root.link_target.clone(), children, ..*root };
+        self.matches = matches;
+        self.key = Some(key);
+    }
+}
+
+// Copies of the children of `entry` that match `query` (in lower case) or have entries
+// below them that do, counting the matches. `view` is what is left of the path of the
+// directory viewed when `entry` is on it, whose directories are kept so it can still be
+// listed. Only the names and the children are copied, children being few once filtered.
+fn filtered_children(
+    entry: &DirEntry,
+    query: &str,
+    view: Option<&[String]>,
+    depth: usize,
+    matches: &mut u64,
+    display: &DisplayOptions,
+    multiple_roots: bool,
+) -> Vec<DirEntry> {
+    let mut kept = Vec::new();
+    for child in &entry.children {
+        if child.pruned > 0 || display.hides_at(child, depth == 0, multiple_roots) {
+            continue;
+        }
+        let on_view = view.and_then(|view| view.split_first()).filter(|(name, _)| **name == child.name).map(|(_, rest)| rest);
+        let found = contains_ignore_case(&child.name, query);
+        if found {
+            *matches += 1;
+        }
+        let children = if child.children.is_empty() {
+            Vec::new()
+        } else {
+            filtered_children(child, query, on_view, depth + 1, matches, display, multiple_roots)
+        };
+        if found || on_view.is_some() || !children.is_empty() {
+            kept.push(DirEntry { name: child.name.clone(), link_target: child.link_target.clone(), children, ..*child });
+        }
+    }
+    kept
+}
+
+// Drops the filter, keeping the selected entry selected in the whole tree, where the
+// directories above it may be collapsed.
+fn clear_filter(app: &mut App) {
+    app.filter = None;
+    app.filtering = false;
+    if let Some(path) = app.selected_path().cloned() {
+        app.reveal(path);
+    }
+}
+
+// Whether `text` has `query`, which is in lower case, in it ignoring case. ASCII names,
+// most of them, are compared without lowering them into a new string.
+fn contains_ignore_case(text: &str, query: &str) -> bool {
+    if text.is_ascii() && query.is_ascii() {
+        let (text, query) = (text.as_bytes(), query.as_bytes());
+        query.is_empty() || text.windows(query.len()).any(|window| window.eq_ignore_ascii_case(query))
+    } else {
+        text.to_lowercase().contains(query)
+    }
+}
+
+// Rows of the largest-files view: the size of each file and its path below the root.
+fn build_largest_items(
+    largest: &LargestFiles,
+    items: &mut Vec<ListItem>,
+    marked: &BTreeSet<Vec<String>>,
+    mode: SizeMode,
+    units: Units,
+    theme: &Theme,
+) {
+    for (path, size) in &largest.files {
+        let mark = if marked.contains(path) { "*" } else { "" };
+        let text = format!("{:>10} | {}{}", format_amount(*size, mode, units), mark, path.iter().collect::<PathBuf>().display());
+        items.push(ListItem::new(text).style(if mark.is_empty() { theme.rows } else { theme.marked }));
+    }
+    if largest.files.is_empty() {
+        items.push(ListItem::new("No files found"));
+    }
+}
+
+// What deleting the entries at `paths` would free, none of them being inside another.
+fn marked_size(root: &DirEntry, paths: &[&Vec<String>], mode: SizeMode) -> u64 {
+    paths.iter().filter_map(|path| entry_at(root, path)).map(|entry| entry.size(mode)).sum()
+}
+
+// Exports the tree view listing `viewed` to `path`, telling how that went on the status line.
+fn export_tree(path: &Path, heading: &str, root: &DirEntry, app: &mut App, columns: u16, multiple_roots: bool, display: &DisplayOptions) {
+    let (rows, _, _) = tree_rows(root, app, columns, None, multiple_roots, display);
+    app.set_status(match export_rows(path, heading, &rows) {
+        Ok(()) => format!("Exported {} rows to {}", format_count(rows.len() as u64), path.display()),
+        Err(e) => format!("Could not export to {}: {}", path.display(), e),
+    });
+}
+
+// Writes the rows of the tree view to `path` ('E') as they are shown, below `heading` and
+// the time of the export.
+fn export_rows(path: &Path, heading: &str, rows: &[(String, Style)]) -> io::Result<()> {
+    let mut text = format!("{}\nExported {}\n\n", heading, Local::now().format("%Y-%m-%d %H:%M:%S"));
+    for (row, _) in rows {
+        text.push_str(row);
+        text.push('\n');
+    }
+    fs::write(path, text)
+}
+
+// Deletes what is at `path` from the disk for good ('d' with --delete-permanently): a whole
+// directory, or a file or symlink (never what the symlink points to).
+fn delete_path(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+// Opens what is at `path` with the desktop's default application for it ('o'), without
+// waiting for it: the child is reaped by a thread of its own, and its output is thrown
+// away so it cannot draw over the TUI.
+fn open_path(path: &Path) -> io::Result<()> {
+    let path = std::path::absolute(path)?;
+    let mut command = if cfg!(target_os = "macos") {
+        process::Command::new("open")
+    } else if cfg!(windows) {
+        // The empty argument is the window title, which start would otherwise take the
+        // path for
+        let mut command = process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        process::Command::new("xdg-open")
+    };
+    let mut child = command
+        .arg(&path)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                e.kind(),
+                format!("{} is not installed", command.get_program().to_string_lossy()),
+            ),
+            _ => e,
+        })?;
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+// Splits the children of a directory into those listed (in tree order) and those folded
+// into one row: all but the `max_children` largest, and those below `min_percent` of the
+// directory. Folding a single child would not save a row, so it stays listed.
+fn fold_children(entry: &DirEntry, fold: Fold, mode: SizeMode) -> (Vec<&DirEntry>, Vec<&DirEntry>) {
+    let mut fold_out = vec![false; entry.children.len()];
+    if let Some(max_children) = fold.max_children
+        && entry.children.len() > max_children
+    {
+        let mut by_size: Vec<usize> = (0..entry.children.len()).collect();
+        by_size.sort_by_key(|&index| std::cmp::Reverse(entry.children[index].size(mode)));
+        for &index in &by_size[max_children..] {
+            fold_out[index] = true;
+        }
+    }
+    if let Some(min_percent) = fold.min_percent {
+        let threshold = entry.size(mode) as f64 * min_percent / 100.0;
+        for (index, child) in entry.children.iter().enumerate() {
+            if (child.size(mode) as f64) < threshold {
+                fold_out[index] = true;
+            }
+        }
+    }
+    if fold_out.iter().filter(|&&folded| folded).count() < 2 {
+        return (entry.children.iter().collect(), Vec::new());
+    }
+    let (folded, listed): (Vec<_>, Vec<_>) = entry.children.iter().zip(fold_out).partition(|(_, folded)| *folded);
+    (listed.into_iter().map(|(child, _)| child).collect(), folded.into_iter().map(|(child, _)| child).collect())
+}
+
+// The part of the tree made of `uid`'s files, with directory totals recomputed from them;
+// directories holding none of them are left out. Directories folded by --max-depth or
+// --min-size no longer know whose files they hold, so they are left out as well.
+fn files_of(entry: &DirEntry, uid: u32) -> DirEntry {
+    let children: Vec<DirEntry> = entry.children.iter()
+        .filter_map(|child| {
+            if child.dir_count == 0 {
+                (child.owner == Some(uid)).then(|| child.clone())
+            } else {
+                Some(files_of(child, uid)).filter(|dir| !dir.children.is_empty())
+            }
+        })
+        .collect();
+    DirEntry {
+        name: entry.name.clone(),
+        is_dir: true,
+        apparent_size: children.iter().map(|c| c.apparent_size).sum(),
+        disk_usage: children.iter().map(|c| c.disk_usage).sum(),
+        file_count: children.iter().map(|c| c.file_count).sum(),
+        dir_count: 1 + children.iter().map(|c| c.dir_count).sum::<u64>(),
+        // Only the files count, so the root matches the user's total in every mode
+        inodes: children.iter().map(|c| c.inodes).sum(),
+        empty_files: children.iter().map(|c| c.empty_files).sum(),
+        mtime: children.iter().filter_map(|c| c.mtime).max(),
+        incomplete: entry.incomplete,
+        children,
+        ..Default::default()
+    }
+}
+
+// Rows of the per-user view, largest owner first; `uids` gets the owner of each row.
+fn build_owner_items(
+    owners: &Breakdown<u32>,
+    names: &HashMap<u32, String>,
+    items: &mut Vec<ListItem>,
+    uids: &mut Vec<u32>,
+    mode: SizeMode,
+    units: Units,
+    theme: &Theme,
+) {
+    for (uid, usage) in owners.rows(mode) {
+        let noun = if usage.files == 1 { "file" } else { "files" };
+        let text = format!(
+            "{} | {} | {} {}",
+            owner_label(uid, names),
+            format_amount(usage.size(mode), mode, units),
+            format_count(usage.files),
+            noun,
+        );
+        items.push(ListItem::new(text).style(theme.rows));
+        uids.push(uid);
+    }
+    if uids.is_empty() {
+        items.push(ListItem::new("No file owners (uids are only available on Unix)"));
+    }
+}
+
+// Permissions as `ls -l` shows them: "drwxr-xr-x".
+fn format_permissions(file_type: fs::FileType, bits: u32) -> String {
+    let kind = if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_file() {
+        '-'
+    } else {
+        '?'
+    };
+    let mut text = String::from(kind);
+    // Owner, group, others; setuid, setgid and sticky show in the execute column
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let rwx = bits >> shift;
+        text.push(if rwx & 4 != 0 { 'r' } else { '-' });
+        text.push(if rwx & 2 != 0 { 'w' } else { '-' });
+        text.push(match (rwx & 1 != 0, bits & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
+
+// Lines of the details panel ('i') for `entry`, found at `full`. What the scan does not
+// keep (owner of a directory, permissions, device and inode numbers) is read from the disk
+// for the entry shown, rather than stored for every entry of the tree; the entry may be
+// gone by then, deleted by something else since the scan.
+fn build_details(entry: &DirEntry, full: &Path, display: &DisplayOptions, names: &HashMap<u32, String>) -> Vec<String> {
+    let units = display.units();
+    let absolute = std::path::absolute(full).unwrap_or_else(|_| full.to_path_buf());
+    let mut lines = vec![absolute.display().to_string(), String::new()];
+    lines.push(format!("On disk   {}", format_size(entry.disk_usage, units)));
+    lines.push(format!("Apparent  {}", format_size(entry.apparent_size, units)));
+    if entry.dir_count > 0 {
+        lines.push(format!(
+            "Items     {} {}, {} {}",
+            format_count(entry.file_count),
+            if entry.file_count == 1 { "file" } else { "files" },
+            format_count(entry.dir_count),
+            if entry.dir_count == 1 { "directory" } else { "directories" },
+        ));
+    }
+    lines.push(format!("Inodes    {}", format_count(entry.inodes)));
+    let modified = match entry.mtime {
+        Some(time) => DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M").to_string(),
+        None => "-".to_string(),
+    };
+    lines.push(format!("Modified  {}", modified));
+    let metadata = fs::symlink_metadata(full).ok();
+    if metadata.is_none() {
+        lines.push("Missing   no longer on disk".to_string());
+    }
+    let raw = metadata.as_ref().map(raw_metadata).unwrap_or_default();
+    let known = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let mode = metadata.as_ref().zip(raw.mode)
+        .map(|(metadata, bits)| format!("{} ({:04o})", format_permissions(metadata.file_type(), bits), bits));
+    lines.push(format!("Mode      {}", known(mode)));
+    lines.push(format!("Owner     {}", known(raw.uid.map(|uid| format!("{} ({})", owner_label(uid, names), uid)))));
+    lines.push(format!("Group     {}", known(raw.gid.map(|gid| gid.to_string()))));
+    lines.push(format!("Links     {}", known(raw.links.map(|links| links.to_string()))));
+    lines.push(format!("Device    {}", known(raw.device.map(|device| device.to_string()))));
+    lines.push(format!("Inode no  {}", known(raw.inode.map(|inode| inode.to_string()))));
+    if let Some(target) = &entry.link_target {
+        lines.push(format!("Target    {}{}", target, if entry.broken_link { " (broken)" } else { "" }));
+    }
+    if !entry.children.is_empty() {
+        let mut largest: Vec<&DirEntry> = entry.children.iter().collect();
+        largest.sort_by(|a, b| SortOrder::Size.compare(a, b, display.size_mode, false));
+        lines.push(String::new());
+        lines.push("Largest".to_string());
+        for child in largest.into_iter().take(DETAILS_LARGEST) {
+            lines.push(format!("  {:>10}  {}", format_amount(child.size(display.size_mode), display.size_mode, units), child.name));
+        }
+    }
+    lines
+}
+
+// Account name of a uid; uids without one (deleted users) are shown as numbers.
+fn owner_label(uid: u32, names: &HashMap<u32, String>) -> String {
+    names.get(&uid).cloned().unwrap_or_else(|| uid.to_string())
+}
+
+// Rows of the extension view, largest group first.
+fn build_extension_items(
+    extensions: &Breakdown<String>,
+    items: &mut Vec<ListItem>,
+    mode: SizeMode,
+    units: Units,
+    theme: &Theme,
+) {
+    for (extension, usage) in extensions.rows(mode) {
+        let noun = if usage.files == 1 { "item" } else { "items" };
+        let text = format!(
+            "{} | {} | {} {}",
+            extension_label(&extension),
+            format_amount(usage.size(mode), mode, units),
+            format_count(usage.files),
+            noun,
+        );
+        items.push(ListItem::new(text).style(theme.rows));
+    }
+}
+
+// Rows of the duplicates view: each group, largest waste first, followed by its files.
+fn build_duplicate_items(duplicates: Option<&Duplicates>, items: &mut Vec<ListItem>, units: Units, glyphs: &Glyphs, theme: &Theme) {
+    let Some(duplicates) = duplicates else {
+        items.push(ListItem::new("Start rdu with --find-duplicates to look for identical files"));
+        return;
+    };
+    duplicates.with_groups(|groups| {
+        let Some(groups) = groups else {
+            items.push(ListItem::new(format!("Comparing files{}", glyphs.ellipsis)));
+            return;
+        };
+        if groups.is_empty() {
+            items.push(ListItem::new("No duplicate files found"));
+        }
+        for group in groups {
+            let text = format!(
+                "{} wasted | {} copies of {}",
+                format_size(group.wasted(), units),
+                format_count(group.paths.len() as u64),
+                format_size(group.size, units),
+            );
+            items.push(ListItem::new(text).style(theme.group));
+            for path in &group.paths {
+                items.push(ListItem::new(format!("  {}", path.display())).style(theme.rows));
+            }
+        }
+    });
+}
+
+// Rows a turn of the mouse wheel moves the selection by
+const WHEEL_ROWS: isize = 3;
+
+// Longest wait for input between two frames
+const TICK: Duration = Duration::from_millis(200);
+
+// Narrowest terminal the details panel ('i') is shown in, and the share of it the panel takes
+const DETAILS_MIN_WIDTH: u16 = 100;
+const DETAILS_PERCENT: u16 = 40;
+// Children of a directory the details panel lists
+const DETAILS_LARGEST: usize = 5;
+
+// Files the largest-files view ('f') lists
+const LARGEST_FILES: usize = 100;
+
+// Longest symlink target shown in a row of the tree; longer ones lose their middle
+const LINK_TARGET_CHARS: usize = 40;
+
+// Columns a name keeps in a row of the tree however long the rest of the row is, and
+// how many columns '[' and ']' pan the names by
+const MIN_NAME_WIDTH: usize = 8;
+const PAN_COLUMNS: usize = 8;
+
+// A tab of the TUI, with its own view, selection, marks, sort order and the other
+// settings of the display.
+struct Tab {
+    // Path of the entry the tab lists below the root of the tree, empty for the whole tree
+    top: Vec<String>,
+    // The state of the tab while another one is shown; that of the tab shown is taken
+    // out meanwhile
+    app: App,
+    display: DisplayOptions,
+}
+
+// Shows the tab at `index` instead of the one at `active`, whose state goes back into
+// `tabs`.
+fn show_tab(tabs: &mut [Tab], active: &mut usize, index: usize, app: &mut App, display: &mut DisplayOptions) {
+    std::mem::swap(app, &mut tabs[*active].app);
+    std::mem::swap(display, &mut tabs[*active].display);
+    *active = index;
+    std::mem::swap(app, &mut tabs[index].app);
+    std::mem::swap(display, &mut tabs[index].display);
+    // Its rows are those of the tree when it was last shown
+    app.keep_selection();
+}
+
+// The tab of `count` that Tab, Shift+Tab or the digit `key` (counting from 1) goes to
+// from the tab at `active`; `active` itself for a digit with no tab.
+fn tab_for_key(action: Action, key: KeyCode, active: usize, count: usize) -> usize {
+    let index = match action {
+        Action::NextTab => (active + 1) % count,
+        Action::PreviousTab => (active + count - 1) % count,
+        _ => match key {
+            KeyCode::Char(c) => c.to_digit(10).and_then(|n| (n as usize).checked_sub(1)).unwrap_or(active),
+            _ => active,
+        },
+    };
+    if index < count { index } else { active }
+}
+
+fn run_tui(
+    mut live: LiveTree,
+    events: &mpsc::Receiver<ScanEvent>,
+    ctx: &ScanContext,
+    mut display: DisplayOptions,
+    options: TuiOptions,
+    // Asks the scan thread to scan the roots or a directory again
+    rescans: mpsc::Sender<Rescan>,
+    space: Option<FsSpace>,
+) -> Result<(), Box<dyn Error>> {
+    let TuiOptions { quiet, deletion, mouse, keymap, snapshot } = options;
+    // Setup terminal for TUI (raw mode, alternate screen), until the guard is dropped
+    let guard = TerminalGuard::new(mouse)?;
+    let backend = CrosstermBackend::new(io::stdout());
+    let mut terminal = Terminal::new(backend)?;
+
+    let mut app = App::new();
+    // Width of the terminal in the last frame, which the size bars of an export follow
+    let mut columns = 0;
+    // Rows of marked entries the deletion dialog had room for in the last frame, which a
+    // page scrolls by
+    let mut dialog_rows = 0;
+    let mut largest = LargestFiles { key: None, files: Vec::new() };
+    let mut name_filter = NameFilter::default();
+    // The whole tree, and with several roots a tab for each of them after it
+    let mut tabs = vec![Tab { top: Vec::new(), app: App::new(), display }];
+    if live.has_multiple_roots() {
+        for root in &live.tree().children {
+            tabs.push(Tab { top: vec![root.name.clone()], app: App::new(), display });
+        }
+    }
+    let mut active = 0;
+    // The byte size 'c' switches back to after showing inode counts
+    let mut byte_mode = if display.size_mode == SizeMode::Inodes { SizeMode::default() } else { display.size_mode };
+    // What 'z' folds by
+    let fold = display.fold.unwrap_or(Fold::DEFAULT);
+    // Account names for the per-user view, where Enter shows the tree of the selected
+    // user's files until Esc goes back to the whole tree
+    let names = breakdown::user_names();
+    // Bookmarked entries ('B'), by path below the whole tree, as saved by earlier sessions
+    // for these roots
+    let roots = absolute_roots(live.tree(), live.has_multiple_roots());
+    let dirs: Vec<PathBuf> = roots.iter().map(|(root, _)| root.clone()).collect();
+    let mut bookmarked: Vec<Vec<String>> = Vec::new();
+    for path in bookmarks::load(&dirs).iter().filter_map(|full| tree_path(full, &roots)) {
+        if !bookmarked.contains(&path) {
+            bookmarked.push(path);
+        }
+    }
+
+    // Main TUI Loop
+    loop {
+        // Take in whatever the scan finished since the last frame. Directories that grow
+        // move in the order of their siblings, and the complete tree replaces the one
+        // built up: the same entry stays selected either way
+        while let Ok(event) = events.try_recv() {
+            app.keep_selection();
+            live.handle(event);
+        }
+        let scanning = !live.is_finished();
+        // The tree of the tab shown: the whole tree, or one of its roots on its own
+        let top = tabs[active].top.clone();
+        let (tab_tree, multiple_roots) = match entry_at(live.tree(), &top) {
+            Some(tree) if !top.is_empty() => (tree, false),
+            _ => (live.tree(), live.has_multiple_roots()),
+        };
+        let filtered = app.owner_filter.map(|uid| files_of(tab_tree, uid));
+        let root_entry = filtered.as_ref().unwrap_or(tab_tree);
+        // The directory viewed may be gone from a user's tree
+        while entry_at(root_entry, &app.view).is_none() {
+            app.go_up();
+        }
+        let viewed = entry_at(root_entry, &app.view).unwrap_or(root_entry);
+        // What the tree view lists and an export writes: the tree narrowed by the filter,
+        // if one is typed
+        let shown = match &app.filter {
+            Some(query) if !query.is_empty() => {
+                name_filter.update(&app, live.version(), root_entry, &display, multiple_roots);
+                &name_filter.tree
+            }
+            _ => root_entry,
+        };
+        let stats = ctx.stats();
+        // Marks and expanded directories of entries that were deleted or are gone after a
+        // rescan
+        app.marked.retain(|path| entry_at(tab_tree, path).is_some());
+        app.expanded.retain(|path| entry_at(tab_tree, path).is_some());
+        app.unfolded.retain(|path| entry_at(tab_tree, path).is_some());
+
+        // What is listed, as the title says and an export ('E') starts with
+        let mut heading = format!(
+            "rdu: Disk Usage of {}: {} [{}]",
+            entry_path(root_entry, &app.view, multiple_roots).display(),
+            format_amount(viewed.size(display.size_mode), display.size_mode, display.units()),
+            display.size_mode.label(),
+        );
+        if let Some(uid) = app.owner_filter {
+            heading.push_str(&format!(" (files of {})", owner_label(uid, &names)));
+        }
+
+        terminal.draw(|f| {
+            let size = f.area(); 
+            columns = size.width;
+            let mut title = heading.clone();
+            if let Some(space) = space
+                && space.total > 0
+            {
+                title.push_str(&format!(
+                    " (filesystem: {} free of {}, this tree uses {:.1}%)",
+                    format_size(space.available, display.units()),
+                    format_size(space.total, display.units()),
+                    root_entry.disk_usage as f64 * 100.0 / space.total as f64,
+                ));
+            }
+            if stats.excluded > 0 {
+                title.push_str(&format!(" (skipped {} entries by pattern)", stats.excluded));
+            }
+            if stats.hidden > 0 {
+                title.push_str(&format!(" (skipped {} hidden entries)", stats.hidden));
+            }
+            if stats.cancelled {
+                title.push_str(" [scan cancelled, partial results]");
+            }
+            if stats.broken_links > 0 {
+                let noun = if stats.broken_links == 1 { "broken symlink" } else { "broken symlinks" };
+                title.push_str(&format!(" [{} {}, L lists them]", format_count(stats.broken_links as u64), noun));
+            }
+            let mut block = display.block(Borders::ALL).title(title);
+            if display.color_by_age && app.screen == Screen::Tree {
+                block = block.title_bottom(age_legend(display.theme, display.glyphs().swatch));
+            }
+            if display.hide_dotfiles && app.screen == Screen::Tree {
+                let hidden = viewed.children.iter()
+                    .filter(|child| display.hides(child, &app.view, multiple_roots))
+                    .count();
+                block = block.title_bottom(format!(" {} dot entries hidden here ('.' shows them) ", format_count(hidden as u64)));
+            }
+            if display.exact_bytes && display.size_mode != SizeMode::Inodes {
+                block = block.title_bottom(" sizes in bytes ('b' rounds them) ");
+            } else if display.si && display.size_mode != SizeMode::Inodes {
+                block = block.title_bottom(" sizes in units of 1000 ('U' for 1024) ");
+            }
+            f.render_widget(block, size);
+
+            let mut list_items = Vec::new();
+            match app.screen {
+                Screen::Tree => {
+                    // The rows are as wide as the list, less the details panel beside it
+                    let mut width = size.width.saturating_sub(2);
+                    if app.show_details && size.width >= DETAILS_MIN_WIDTH {
+                        width -= width * DETAILS_PERCENT / 100;
+                    }
+                    let (items, rows, overflow) = tree_rows(shown, &app, size.width, Some(width as usize), multiple_roots, &display);
+                    list_items = items.into_iter().map(|(text, style)| ListItem::new(text).style(style)).collect();
+                    app.set_rows(rows);
+                    // Names shorter than before need less panning
+                    app.pan = app.pan.min(overflow);
+                }
+                Screen::Extensions => build_extension_items(&ctx.extensions, &mut list_items, display.size_mode, display.units(), display.theme),
+                Screen::Duplicates => build_duplicate_items(ctx.duplicates.as_ref(), &mut list_items, display.units(), display.glyphs(), display.theme),
+                Screen::Largest => {
+                    let key = (live.version(), display.size_mode, app.owner_filter, display.hide_dotfiles);
+                    largest.update(key, root_entry, &display, multiple_roots);
+                    app.largest_rows = largest.files.iter().map(|(path, _)| path.clone()).collect();
+                    build_largest_items(&largest, &mut list_items, &app.marked, display.size_mode, display.units(), display.theme);
+                }
+                // Laid out once the room for it is known
+                Screen::Treemap => {}
+                Screen::Owners => {
+                    app.owner_rows.clear();
+                    if let Some(owners) = &ctx.owners {
+                        build_owner_items(
+                            owners,
+                            &names,
+                            &mut list_items,
+                            &mut app.owner_rows,
+                            display.size_mode,
+                            display.units(),
+                            display.theme,
+                        );
+                    }
+                }
+            }
+            if app.screen != Screen::Treemap {
+                app.selection().set_len(list_items.len());
+            }
+
+            let rescanned = live.rescanned().map(|path| entry_path(live.tree(), path, live.has_multiple_roots()));
+            let scanning_label = match &rescanned {
+                Some(path) => format!("Rescanning {}{}", path.display(), display.glyphs().ellipsis),
+                None if live.is_rescanning() => format!("Rescanning{}", display.glyphs().ellipsis),
+                None => format!("Scanning{}", display.glyphs().ellipsis),
+            };
+            let list_title = if !scanning && let Some(elapsed) = live.elapsed() {
+                let heading = match app.screen {
+                    Screen::Tree => "Directory Tree",
+                    Screen::Extensions => "Usage by Extension",
+                    Screen::Duplicates => "Duplicate Files",
+                    Screen::Owners => "Usage by User",
+                    Screen::Largest => "Largest Files",
+                    Screen::Treemap => "Treemap",
+                };
+                // The counters are those of the last scan, which may have been of one directory
+                let scope = match (&rescanned, snapshot) {
+                    (Some(path), _) => format!("rescanned {}: ", path.display()),
+                    (None, Some(time)) => format!("snapshot of {}: ", DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M")),
+                    (None, None) => String::new(),
+                };
+                format!("{} ({}{} in {:.2}s)", heading, scope, stats.coverage(), elapsed.as_secs_f64())
+            } else if quiet {
+                scanning_label
+            } else {
+                let mut counters = format!(
+                    "{} {} entries, {}: ",
+                    scanning_label,
+                    format_count(ctx.progress.entries.load(Ordering::Relaxed)),
+                    format_amount(ctx.progress.bytes.load(Ordering::Relaxed), ctx.size_mode, display.units()),
+                );
+                let current = ctx.progress.current.lock().unwrap().display().to_string();
+                let room = (size.width as usize).saturating_sub(display_width(&counters) + 2);
+                counters.push_str(&truncate_left(&current, room, display.glyphs().ellipsis));
+                counters
+            };
+            let mut list_area = size.inner(Margin::new(1, 1));
+            // The totals of the scan on the last line, with what a key just did at its end
+            if list_area.height > 0 {
+                list_area.height -= 1;
+                let footer_area = Rect { y: list_area.y + list_area.height, height: 1, ..list_area };
+                let mut footer = format!(
+                    "{} in {} items | {} entries here | sorted by {}",
+                    format_amount(root_entry.size(display.size_mode), display.size_mode, display.units()),
+                    format_count(root_entry.file_count + root_entry.dir_count),
+                    format_count(viewed.children.len() as u64),
+                    display.sort_label(),
+                );
+                if display.share_of_root && display.bars != Bars::Off {
+                    footer.push_str(" | shares of the whole tree");
+                }
+                if !app.marked.is_empty() {
+                    footer.push_str(&format!(
+                        " | {} marked, {}",
+                        format_count(app.marked.len() as u64),
+                        format_amount(marked_size(tab_tree, &app.marked_tops(), display.size_mode), display.size_mode, display.units()),
+                    ));
+                }
+                if let Some(query) = &app.filter
+                    && !app.filtering
+                    && !query.is_empty()
+                {
+                    footer.push_str(&format!(" | filter: {} ({} matches)", query, format_count(name_filter.matches)));
+                }
+                if stats.errors > 0 {
+                    footer.push_str(&format!(" | {} errors, listed on exit", format_count(stats.errors as u64)));
+                }
+                f.render_widget(Paragraph::new(footer).style(display.theme.footer), footer_area);
+                if let Some(status) = app.status() {
+                    let width = (display_width(status) as u16 + 1).min(footer_area.width);
+                    let status_area = Rect { x: footer_area.x + footer_area.width - width, width, ..footer_area };
+                    f.render_widget(Clear, status_area);
+                    f.render_widget(Paragraph::new(format!(" {}", status)).style(display.theme.prompt), status_area);
+                }
+            }
+            // The tabs, each with its number, name and total, on the top line
+            if tabs.len() > 1 && list_area.height > 0 {
+                let mut spans = Vec::new();
+                for (index, tab) in tabs.iter().enumerate() {
+                    let tab_display = if index == active { &display } else { &tab.display };
+                    let name = tab.top.first().map_or("all roots", String::as_str);
+                    let total = entry_at(live.tree(), &tab.top).map_or(0, |entry| entry.size(tab_display.size_mode));
+                    let label = format!(
+                        " {} {} {} ",
+                        index + 1,
+                        name,
+                        format_amount(total, tab_display.size_mode, tab_display.units()),
+                    );
+                    let style = if index == active { display.theme.selection } else { display.theme.footer };
+                    spans.push(Span::styled(label, style));
+                    spans.push(Span::raw(" "));
+                }
+                f.render_widget(Paragraph::new(Line::from(spans)), Rect { height: 1, ..list_area });
+                list_area.y += 1;
+                list_area.height -= 1;
+            }
+            // Where the tree view is, on a line of its own above the list
+            if matches!(app.screen, Screen::Tree | Screen::Treemap) && list_area.height > 0 {
+                let mut components: Vec<&str> = app.view.iter().map(String::as_str).collect();
+                if !multiple_roots || app.view.is_empty() {
+                    components.insert(0, &root_entry.name);
+                }
+                let line = breadcrumb(&components, list_area.width as usize, display.glyphs(), display.theme);
+                f.render_widget(Paragraph::new(line), Rect { height: 1, ..list_area });
+                list_area.y += 1;
+                list_area.height -= 1;
+            }
+            // The details of the selected entry on the right, unless that leaves the list too
+            // little room
+            if app.show_details && matches!(app.screen, Screen::Tree | Screen::Largest | Screen::Treemap) && size.width >= DETAILS_MIN_WIDTH {
+                let width = list_area.width * DETAILS_PERCENT / 100;
+                list_area.width -= width;
+                let details_area = Rect { x: list_area.x + list_area.width, width, ..list_area };
+                let text = match app.selected_entry().and_then(|path| Some((path, entry_at(root_entry, path)?))) {
+                    Some((path, entry)) => {
+                        let full = entry_path(root_entry, path, multiple_roots);
+                        build_details(entry, &full, &display, &names).join("\n")
+                    }
+                    None => "Select an entry to see its details".to_string(),
+                };
+                let details = Paragraph::new(text)
+                    .wrap(Wrap { trim: false })
+                    .block(display.block(Borders::LEFT).title("Details (i to close)"));
+                f.render_widget(details, details_area);
+            }
+            let list_block = Block::default().title(list_title).borders(Borders::NONE);
+            // Follow the selection with the rows that fit in this frame, which change
+            // with the size of the terminal
+            let selection = app.selection();
+            let rows_area = list_block.inner(list_area);
+            selection.scroll(rows_area.height as usize);
+            app.list_area = rows_area;
+            if app.screen == Screen::Treemap {
+                f.render_widget(list_block, list_area);
+                render_treemap(f, viewed, rows_area, &mut app, &display, multiple_roots);
+            } else {
+                let selection = app.selection();
+                let list = List::new(list_items)
+                    .block(list_block)
+                    .highlight_style(display.theme.selection);
+                f.render_stateful_widget(list, list_area, &mut selection.state);
+            }
+
+            if app.show_broken {
+                let area = centered(size, 80, 60);
+                let links = sorted_broken_links(ctx);
+                let items: Vec<ListItem> = links.iter()
+                    .map(|link| ListItem::new(format!("{} -> {}", link.path.display(), link.target.display())))
+                    .collect();
+                let empty = items.is_empty();
+                let popup = List::new(if empty { vec![ListItem::new("No broken symlinks found")] } else { items })
+                    .style(display.theme.popup)
+                    .block(display.block(Borders::ALL)
+                        .title(format!("Broken symlinks ({}), L or Esc to close", format_count(links.len() as u64))));
+                f.render_widget(Clear, area);
+                f.render_widget(popup, area);
+            }
+
+            // The bookmarks with their sizes now, and those of entries deleted since struck out
+            if app.show_bookmarks {
+                let area = centered(size, 80, 60);
+                let width = (area.width as usize).saturating_sub(4);
+                let items: Vec<ListItem> = bookmarked.iter()
+                    .map(|path| {
+                        let full = entry_path(live.tree(), path, live.has_multiple_roots()).display().to_string();
+                        let (amount, style) = match entry_at(live.tree(), path) {
+                            Some(entry) => (format_amount(entry.size(display.size_mode), display.size_mode, display.units()), display.theme.popup),
+                            None => ("deleted".to_string(), display.theme.skipped.add_modifier(Modifier::CROSSED_OUT)),
+                        };
+                        let room = width.saturating_sub(display_width(&amount) + 2);
+                        ListItem::new(format!("{}  {}", truncate_left(&full, room, display.glyphs().ellipsis), amount)).style(style)
+                    })
+                    .collect();
+                let empty = items.is_empty();
+                app.bookmark_list.set_len(items.len());
+                app.bookmark_list.scroll(area.height.saturating_sub(2) as usize);
+                let popup = List::new(if empty { vec![ListItem::new("No bookmarks; B bookmarks the selected entry")] } else { items })
+                    .style(display.theme.popup)
+                    .highlight_style(display.theme.selection)
+                    .block(display.block(Borders::ALL).title(format!(
+                        "Bookmarks ({}), Enter to go there, d to remove, ' or Esc to close",
+                        format_count(bookmarked.len() as u64),
+                    )));
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(popup, area, &mut app.bookmark_list.state);
+            }
+
+            let mut scrolled = app.confirm_scroll;
+            if let Some(paths) = &app.confirm_delete {
+                let doomed: Vec<(&Vec<String>, &DirEntry)> = paths.iter()
+                    .filter_map(|path| entry_at(root_entry, path).map(|entry| (path, entry)))
+                    .collect();
+                let (mut text, area) = if let [(path, entry)] = doomed[..] {
+                    let mut text = format!(
+                        "{}\n\n{}",
+                        entry_path(root_entry, path, multiple_roots).display(),
+                        format_amount(entry.size(display.size_mode), display.size_mode, display.units()),
+                    );
+                    if entry.dir_count > 0 {
+                        text.push_str(&format!(
+                            ", {} {} in {} {}",
+                            format_count(entry.file_count),
+                            if entry.file_count == 1 { "file" } else { "files" },
+                            format_count(entry.dir_count),
+                            if entry.dir_count == 1 { "directory" } else { "directories" },
+                        ));
+                    }
+                    text.push('\n');
+                    (text, centered(size, 60, 30))
+                } else {
+                    // Every marked entry with its size, as many as fit, and what they free
+                    // together
+                    let area = centered(size, 70, 60);
+                    let total: u64 = doomed.iter().map(|(_, entry)| entry.size(display.size_mode)).sum();
+                    let mut text = format!(
+                        "{} marked entries, {} in all:\n\n",
+                        format_count(doomed.len() as u64),
+                        format_amount(total, display.size_mode, display.units()),
+                    );
+                    // As many as fit, scrolled with the arrows when they do not all fit
+                    let room = (area.height as usize).saturating_sub(6).max(1);
+                    let room = if doomed.len() > room { (room - 1).max(1) } else { room };
+                    dialog_rows = room;
+                    let first = app.confirm_scroll.min(doomed.len().saturating_sub(room));
+                    scrolled = first;
+                    let end = (first + room).min(doomed.len());
+                    for (path, entry) in &doomed[first..end] {
+                        let amount = format_amount(entry.size(display.size_mode), display.size_mode, display.units());
+                        let full = entry_path(root_entry, path, multiple_roots).display().to_string();
+                        let width = (area.width as usize).saturating_sub(display_width(&amount) + 4);
+                        text.push_str(&format!("{}  {}\n", truncate_left(&full, width, display.glyphs().ellipsis), amount));
+                    }
+                    if end - first < doomed.len() {
+                        text.push_str(&format!(
+                            "(entries {} to {} of {}, Up and Down scroll)\n",
+                            format_count(first as u64 + 1),
+                            format_count(end as u64),
+                            format_count(doomed.len() as u64),
+                        ));
+                    }
+                    (text, area)
+                };
+                let them = if doomed.len() == 1 { "it" } else { "them" };
+                text.push_str(&if deletion == Deletion::Permanent {
+                    format!("\ny to delete {} for good, any other key to cancel", them)
+                } else {
+                    format!("\ny to move {} to the trash, any other key to cancel", them)
+                });
+                let dialog = Paragraph::new(text)
+                    .wrap(Wrap { trim: false })
+                    .style(display.theme.danger)
+                    .block(display.block(Borders::ALL)
+                        .title(if deletion == Deletion::Permanent { "Delete?" } else { "Move to the trash?" }));
+                if !doomed.is_empty() {
+                    f.render_widget(Clear, area);
+                    f.render_widget(dialog, area);
+                }
+            }
+
+            app.confirm_scroll = scrolled;
+
+            if app.confirm_quit {
+                let area = centered(size, 60, 30);
+                let count = app.marked.len() + tabs.iter().map(|tab| tab.app.marked.len()).sum::<usize>();
+                let text = format!(
+                    "{} {} marked, quit anyway?\n\ny to quit, any other key to stay",
+                    format_count(count as u64),
+                    if count == 1 { "entry" } else { "entries" },
+                );
+                let dialog = Paragraph::new(text)
+                    .wrap(Wrap { trim: false })
+                    .style(display.theme.danger)
+                    .block(display.block(Borders::ALL).title("Quit?"));
+                f.render_widget(Clear, area);
+                f.render_widget(dialog, area);
+            }
+
+            if let Some(name) = &app.export {
+                let area = Rect {
+                    x: size.x + 1,
+                    y: size.y + size.height.saturating_sub(2),
+                    width: size.width.saturating_sub(2),
+                    height: 1,
+                };
+                let line = Paragraph::new(format!("Export to: {}{}  (Enter to write, Esc to cancel)", name, display.glyphs().cursor))
+                    .style(display.theme.prompt);
+                f.render_widget(Clear, area);
+                f.render_widget(line, area);
+            }
+
+            if let Some(path) = &app.confirm_export {
+                let area = centered(size, 60, 30);
+                let text = format!("{} already exists\n\ny to overwrite it, any other key to cancel", path.display());
+                let dialog = Paragraph::new(text)
+                    .wrap(Wrap { trim: false })
+                    .style(display.theme.danger)
+                    .block(display.block(Borders::ALL).title("Overwrite?"));
+                f.render_widget(Clear, area);
+                f.render_widget(dialog, area);
+            }
+
+            if let Some(query) = &app.search {
+                let area = Rect {
+                    x: size.x + 1,
+                    y: size.y + size.height.saturating_sub(2),
+                    width: size.width.saturating_sub(2),
+                    height: 1,
+                };
+                let line = Paragraph::new(format!("/{}{}  (Enter to find, Esc to cancel)", query, display.glyphs().cursor))
+                    .style(display.theme.prompt);
+                f.render_widget(Clear, area);
+                f.render_widget(line, area);
+            }
+
+            if let Some(query) = &app.filter
+                && app.filtering
+            {
+                let area = Rect {
+                    x: size.x + 1,
+                    y: size.y + size.height.saturating_sub(2),
+                    width: size.width.saturating_sub(2),
+                    height: 1,
+                };
+                let matches = if query.is_empty() { String::new() } else { format!("{} matches ", format_count(name_filter.matches)) };
+                let line = Paragraph::new(format!("Filter: {}{}  {}(Enter to keep, Esc to clear)", query, display.glyphs().cursor, matches))
+                    .style(display.theme.prompt);
+                f.render_widget(Clear, area);
+                f.render_widget(line, area);
+            }
+
+            if app.show_help {
+                let help = keymap.help();
+                let label_width = help.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0);
+                let lines: Vec<String> = help.iter()
+                    .map(|(label, help)| format!("{:<width$}  {}", label, help, width = label_width))
+                    .collect();
+                // As large as the list needs, but no larger than the terminal: what does
+                // not fit is cut off
+                let width = (lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as u16 + 2).min(size.width);
+                let height = (lines.len() as u16 + 2).min(size.height);
+                let area = Rect {
+                    x: size.x + (size.width - width) / 2,
+                    y: size.y + (size.height - height) / 2,
+                    width,
+                    height,
+                };
+                let help = Paragraph::new(lines.join("\n"))
+                    .block(display.block(Borders::ALL).title("Keys (any key to close)"));
+                f.render_widget(Clear, area);
+                f.render_widget(help, area);
+            }
+
+            if let Some(message) = &app.message {
+                let area = centered(size, 60, 30);
+                let text = format!("{}\n\nPress any key", message);
+                let dialog = Paragraph::new(text)
+                    .wrap(Wrap { trim: false })
+                    .block(display.block(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(dialog, area);
+            }
+
+        })?;
+
+        // Wait for input for one tick at most, then draw again: the scan's progress, the
+        // hashing of duplicates and what arrives from the scan thread show up without a
+        // key press, and the status line fades on time
+        if !event::poll(TICK)? {
+            continue;
+        }
+
+        let event = event::read()?;
+        // The frame is laid out from the size of the terminal, so drawing it again is all
+        // a resize takes
+        if let Event::Resize(..) = event {
+            continue;
+        }
+        // The wheel moves the selection and a click selects a row, a double click
+        // opening it like Enter; the dialogs only take keys
+        if let Event::Mouse(mouse) = event {
+            let dialog = app.confirm_delete.is_some() || app.confirm_export.is_some() || app.export.is_some() || app.confirm_quit;
+            if app.message.is_some() || app.show_help || dialog || app.search.is_some() || app.filtering || app.show_broken || app.show_bookmarks {
+                continue;
+            }
+            match mouse.kind {
+                MouseEventKind::ScrollUp => app.selection().move_by(-WHEEL_ROWS),
+                MouseEventKind::ScrollDown => app.selection().move_by(WHEEL_ROWS),
+                // Selects the row clicked, and opens it on a double click
+                MouseEventKind::Down(MouseButton::Left) if app.click(mouse.column, mouse.row) => app.open_selected(),
+                _ => {}
+            }
+            continue;
+        }
+
+        // Event handling: keys are looked up in the keymap, which '?' lists
+        if let Event::Key(key) = event {
+            // The search line takes all keys while it is open
+            if let Some(query) = &mut app.search {
+                match key.code {
+                    KeyCode::Esc => app.search = None,
+                    KeyCode::Enter => {
+                        let query = std::mem::take(query);
+                        app.search = None;
+                        if query.is_empty() {
+                            continue;
+                        }
+                        match search_tree(root_entry, &query, app.selected_path().map(Vec::as_slice), multiple_roots, &display) {
+                            Some(path) => {
+                                app.reveal(path);
+                                app.clear_status();
+                            }
+                            None => app.set_status(format!("Nothing matches '{}'", query)),
+                        }
+                    }
+                    KeyCode::Backspace => {
+                        query.pop();
+                    }
+                    KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => query.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+            // And so does the filter, which the tree follows at every key, but for the
+            // arrows moving the selection; Esc drops it, showing the selected entry in the
+            // whole tree
+            if app.filtering {
+                match key.code {
+                    KeyCode::Esc => clear_filter(&mut app),
+                    KeyCode::Up => app.selection().move_by(-1),
+                    KeyCode::Down => app.selection().move_by(1),
+                    KeyCode::Enter => {
+                        app.filtering = false;
+                        if app.filter.as_ref().is_some_and(String::is_empty) {
+                            app.filter = None;
+                        }
+                    }
+                    KeyCode::Backspace => {
+                        app.filter.get_or_insert_default().pop();
+                        app.keep_selection();
+                    }
+                    KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.filter.get_or_insert_default().push(c);
+                        app.keep_selection();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            // So does the file name of an export, which is written unless that would
+            // overwrite a file without asking
+            if let Some(name) = &mut app.export {
+                match key.code {
+                    KeyCode::Esc => app.export = None,
+                    KeyCode::Enter => {
+                        let path = PathBuf::from(std::mem::take(name));
+                        app.export = None;
+                        if path.as_os_str().is_empty() {
+                            continue;
+                        }
+                        if fs::symlink_metadata(&path).is_ok() {
+                            app.confirm_export = Some(path);
+                        } else {
+                            export_tree(&path, &heading, shown, &mut app, columns, multiple_roots, &display);
+                        }
+                    }
+                    KeyCode::Backspace => {
+                        name.pop();
+                    }
+                    KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => name.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+            if let Some(path) = app.confirm_export.take() {
+                if key.code == KeyCode::Char('y') {
+                    export_tree(&path, &heading, shown, &mut app, columns, multiple_roots, &display);
+                }
+                continue;
+            }
+            if app.message.is_some() {
+                app.message = None;
+                continue;
+            }
+            if app.show_help {
+                app.show_help = false;
+                continue;
+            }
+            if app.confirm_quit {
+                app.confirm_quit = false;
+                if key.code == KeyCode::Char('y') {
+                    break;
+                }
+                continue;
+            }
+            // The list of bookmarks takes the keys that move in it and act on a bookmark
+            if app.show_bookmarks {
+                let selected = app.bookmark_list.selected().filter(|&row| row < bookmarked.len());
+                match keymap.action(&key) {
+                    Some(Action::Up) => app.bookmark_list.move_by(-1),
+                    Some(Action::Down) => app.bookmark_list.move_by(1),
+                    Some(Action::First) => app.bookmark_list.select_first(),
+                    Some(Action::Last) => app.bookmark_list.select_last(),
+                    Some(Action::Delete) => {
+                        if let Some(row) = selected {
+                            bookmarked.remove(row);
+                            save_bookmarks(&mut app, &bookmarked, &roots);
+                        }
+                    }
+                    // The tab shown if the entry is in it, or else the whole tree
+                    Some(Action::Open) => {
+                        if let Some(row) = selected {
+                            let path = bookmarked[row].clone();
+                            app.show_bookmarks = false;
+                            if !path.starts_with(&top) {
+                                show_tab(&mut tabs, &mut active, 0, &mut app, &mut display);
+                                largest.key = None;
+                                name_filter.key = None;
+                            }
+                            let below = path[tabs[active].top.len()..].to_vec();
+                            app.owner_filter = None;
+                            app.filter = None;
+                            app.reveal(below);
+                        }
+                    }
+                    Some(Action::Back | Action::Quit | Action::Bookmarks) => app.show_bookmarks = false,
+                    _ => {}
+                }
+                continue;
+            }
+            // The arrows scroll the list of marked entries to be deleted
+            if app.confirm_delete.as_ref().is_some_and(|paths| paths.len() > 1) {
+                let rows = dialog_rows.max(1) as isize;
+                let step = match keymap.action(&key) {
+                    Some(Action::Up) => -1,
+                    Some(Action::Down) => 1,
+                    Some(Action::PageUp | Action::HalfPageUp) => -rows,
+                    Some(Action::PageDown | Action::HalfPageDown) => rows,
+                    _ => 0,
+                };
+                if step != 0 {
+                    app.confirm_scroll = app.confirm_scroll.saturating_add_signed(step);
+                    continue;
+                }
+            }
+            if let Some(paths) = app.confirm_delete.take() {
+                app.confirm_scroll = 0;
+                if key.code == KeyCode::Char('y') {
+                    let targets: Vec<(Vec<String>, PathBuf, u64)> = paths.into_iter()
+                        .filter_map(|path| {
+                            let size = entry_at(root_entry, &path)?.size(display.size_mode);
+                            let full = entry_path(root_entry, &path, multiple_roots);
+                            Some((path, full, size))
+                        })
+                        .collect();
+                    let batch = targets.len() > 1;
+                    // Rows below the deleted ones move up
+                    app.keep_selection();
+                    let (mut deleted, mut freed, mut errors) = (0, 0, Vec::new());
+                    for (path, full, size) in targets {
+                        let done = if deletion == Deletion::Permanent {
+                            delete_path(&full).map(|()| format!("Deleted {} for good", full.display()))
+                        } else {
+                            trash::move_to_trash(&full)
+                                .map(|trash| format!("Moved {} to the trash in {}", full.display(), trash.display()))
+                        };
+                        match done {
+                            Ok(status) => {
+                                live.remove(&[top.as_slice(), &path].concat());
+                                deleted += 1;
+                                freed += size;
+                                if !batch {
+                                    app.set_status(status);
+                                }
+                            }
+                            Err(e) => errors.push(format!("Could not delete {}: {}", full.display(), e)),
+                        }
+                    }
+                    // A root deleted from the first tab takes its own tab along
+                    if tabs.iter().any(|tab| entry_at(live.tree(), &tab.top).is_none()) {
+                        let shown = tabs[active].top.clone();
+                        tabs.retain(|tab| entry_at(live.tree(), &tab.top).is_some());
+                        active = tabs.iter().position(|tab| tab.top == shown).unwrap_or(0);
+                    }
+                    if batch && deleted > 0 {
+                        let count = format_count(deleted);
+                        let freed = format_amount(freed, display.size_mode, display.units());
+                        app.set_status(if deletion == Deletion::Permanent {
+                            format!("Deleted {} entries ({}) for good", count, freed)
+                        } else {
+                            format!("Moved {} entries ({}) to the trash", count, freed)
+                        });
+                    }
+                    if !errors.is_empty() {
+                        // Each failure on a line of its own, after what the others did
+                        if batch {
+                            errors.insert(0, format!(
+                                "{} of {} entries deleted; the tree shows what is gone:\n",
+                                format_count(deleted),
+                                format_count(deleted + errors.len() as u64),
+                            ));
+                        }
+                        app.message = Some(errors.join("\n"));
+                    }
+                }
+                continue;
+            }
+            let tree = app.screen == Screen::Tree;
+            let map = app.screen == Screen::Treemap;
+            // Whether the rows are entries of the tree, which can be copied, opened or deleted
+            let entries = tree || map || app.screen == Screen::Largest;
+            match keymap.action(&key) {
+                Some(Action::Help) => app.show_help = true,
+                Some(Action::Rescan | Action::RescanDirectory) if scanning => app.message = Some("The scan is still running".to_string()),
+                Some(Action::Rescan | Action::RescanDirectory) if snapshot.is_some() => {
+                    app.message = Some("A snapshot is not scanned again; run rdu on the paths to see them as they are now".to_string());
+                }
+                // Sending only fails if the scan thread is gone
+                Some(Action::Rescan) if rescans.send(Rescan::Everything).is_ok() => {
+                    live.rescan(None);
+                    app.clear_status();
+                }
+                Some(Action::RescanDirectory) if tree => {
+                    // The selected directory, or else the one listed
+                    let path = app.selected_entry()
+                        .filter(|path| entry_at(root_entry, path).is_some_and(|entry| entry.dir_count > 0))
+                        .cloned()
+                        .unwrap_or_else(|| app.view.clone());
+                    // Below the root of the tree rather than that of the tab
+                    let path = [top.as_slice(), &path].concat();
+                    let request = if path.is_empty() { Rescan::Everything } else { Rescan::Directory(path.clone()) };
+                    if rescans.send(request).is_ok() {
+                        live.rescan(Some(path).filter(|path| !path.is_empty()));
+                        app.clear_status();
+                    }
+                }
+                Some(Action::Search) if tree => app.search = Some(String::new()),
+                Some(action @ (Action::NextTab | Action::PreviousTab | Action::GoToTab)) => {
+                    let index = tab_for_key(action, key.code, active, tabs.len());
+                    if index != active {
+                        show_tab(&mut tabs, &mut active, index, &mut app, &mut display);
+                        largest.key = None;
+                        name_filter.key = None;
+                    }
+                }
+                Some(Action::CloseTab) if tabs.len() > 1 => {
+                    tabs.remove(active);
+                    active = active.min(tabs.len() - 1);
+                    std::mem::swap(&mut app, &mut tabs[active].app);
+                    std::mem::swap(&mut display, &mut tabs[active].display);
+                    app.keep_selection();
+                    largest.key = None;
+                    name_filter.key = None;
+                }
+                // Typing goes on from the filter kept last
+                Some(Action::Filter) if tree => {
+                    app.filter.get_or_insert_default();
+                    app.filtering = true;
+                }
+                Some(Action::Export) if tree => app.export = Some(String::new()),
+                Some(Action::Details) => app.show_details = !app.show_details,
+                Some(Action::Copy) if entries => {
+                    if let Some(path) = app.selected_entry() {
+                        let full = entry_path(root_entry, path, multiple_roots);
+                        app.set_status(match std::path::absolute(&full).and_then(|full| {
+                            clipboard::copy(&full.to_string_lossy()).map(|()| full)
+                        }) {
+                            Ok(full) => format!("Copied {}", full.display()),
+                            Err(e) => format!("Could not copy {}: {}", full.display(), e),
+                        });
+                    }
+                }
+                Some(Action::Launch) if entries => {
+                    if let Some(path) = app.selected_entry() {
+                        let full = entry_path(root_entry, path, multiple_roots);
+                        app.set_status(match open_path(&full) {
+                            Ok(()) => format!("Opened {}", full.display()),
+                            Err(e) => format!("Could not open {}: {}", full.display(), e),
+                        });
+                    }
+                }
+                Some(Action::Delete) if entries && deletion != Deletion::Disabled => {
+                    if scanning {
+                        app.message = Some("Entries can be deleted once the scan is done".to_string());
+                    } else if tree && app.owner_filter.is_some() {
+                        // A directory holds other users' files too
+                        app.message = Some("Entries cannot be deleted from a user's tree; Esc goes back to the whole tree".to_string());
+                    } else if !app.marked.is_empty() {
+                        app.confirm_delete = Some(app.marked_tops().into_iter().cloned().collect());
+                    } else {
+                        app.confirm_delete = app.selected_entry().cloned().map(|path| vec![path]);
+                    }
+                }
+                Some(Action::Mark) if entries => app.toggle_mark(),
+                Some(Action::Bookmark) if entries => {
+                    if let Some(path) = app.selected_entry() {
+                        let full = entry_path(root_entry, path, multiple_roots);
+                        let path = [top.as_slice(), path].concat();
+                        if let Some(index) = bookmarked.iter().position(|bookmark| *bookmark == path) {
+                            bookmarked.remove(index);
+                            app.set_status(format!("Removed the bookmark of {}", full.display()));
+                        } else {
+                            bookmarked.push(path);
+                            app.set_status(format!("Bookmarked {}", full.display()));
+                        }
+                        save_bookmarks(&mut app, &bookmarked, &roots);
+                    }
+                }
+                Some(Action::Bookmarks) => app.show_bookmarks = true,
+                Some(Action::ClearMarks) => app.marked.clear(),
+                Some(Action::Back | Action::BrokenLinks) if app.show_broken => app.show_broken = false,
+                Some(Action::BrokenLinks) => app.show_broken = true,
+                Some(Action::Back) if tree && app.filter.is_some() => clear_filter(&mut app),
+                Some(Action::Back) if tree && app.owner_filter.is_some() => app.owner_filter = None,
+                // Marks would go with the TUI, so quitting asks first
+                Some(Action::Quit | Action::Back)
+                    if !app.marked.is_empty() || tabs.iter().any(|tab| !tab.app.marked.is_empty()) =>
+                {
+                    app.confirm_quit = true;
+                }
+                Some(Action::Quit | Action::Back) => break,
+                // The arrows move between the cells of the treemap
+                Some(Action::Up) if map => app.move_in_treemap(Direction::Up),
+                Some(Action::Down) if map => app.move_in_treemap(Direction::Down),
+                Some(Action::Collapse) if map => app.move_in_treemap(Direction::Left),
+                Some(Action::Expand) if map => app.move_in_treemap(Direction::Right),
+                Some(Action::Up) => app.selection().move_by(-1),
+                Some(Action::Down) => app.selection().move_by(1),
+                // Pages are rows of a list, which the treemap does not have
+                Some(Action::PageUp) if !map => app.page(-2),
+                Some(Action::PageDown) if !map => app.page(2),
+                Some(Action::HalfPageUp) if !map => app.page(-1),
+                Some(Action::HalfPageDown) if !map => app.page(1),
+                Some(Action::First) => app.selection().select_first(),
+                Some(Action::Last) => app.selection().select_last(),
+                Some(Action::Mtime) => display.show_mtime = !display.show_mtime,
+                Some(Action::AgeColors) => display.color_by_age = !display.color_by_age,
+                Some(Action::Extensions) => app.toggle(Screen::Extensions),
+                Some(Action::Duplicates) => app.toggle(Screen::Duplicates),
+                Some(Action::Owners) => app.toggle(Screen::Owners),
+                Some(Action::Largest) => app.toggle(Screen::Largest),
+                Some(Action::Treemap) => app.toggle(Screen::Treemap),
+                Some(Action::Open) => app.open_selected(),
+                Some(Action::GoUp) if tree || map => app.go_up(),
+                Some(Action::PanLeft) if tree => app.pan = app.pan.saturating_sub(PAN_COLUMNS),
+                Some(Action::PanRight) if tree => app.pan += PAN_COLUMNS,
+                // Drills into whatever is biggest, listing the directory with its largest
+                // entry selected, so that pressing again goes on down
+                Some(Action::LargestChild) if tree => {
+                    let viewed = entry_at(shown, &app.view).unwrap_or(shown);
+                    if let Some(child) = largest_child(viewed, &app.view, &display, multiple_roots) {
+                        let mut path = app.view.clone();
+                        path.push(child.name.clone());
+                        if child.children.is_empty() {
+                            app.reveal(path);
+                        } else {
+                            let next = largest_child(child, &path, &display, multiple_roots).map(|next| next.name.clone());
+                            app.enter(path.clone());
+                            if let Some(next) = next {
+                                path.push(next);
+                                app.reveal(path);
+                            }
+                        }
+                    }
+                }
+                Some(Action::LargestFile) if tree => {
+                    let viewed = entry_at(shown, &app.view).unwrap_or(shown);
+                    let mut largest = None;
+                    largest_file(viewed, &mut app.view.clone(), &mut largest, &display, multiple_roots);
+                    match largest {
+                        Some((_, path)) => app.reveal(path),
+                        None => app.set_status("There are no files here".to_string()),
+                    }
+                }
+                Some(Action::Toggle) if tree => app.toggle_selected(),
+                Some(Action::Bars) => display.bars = display.bars.next(),
+                Some(Action::ShareOfRoot) => display.share_of_root = !display.share_of_root,
+                Some(Action::Dotfiles) => {
+                    display.hide_dotfiles = !display.hide_dotfiles;
+                    app.keep_selection();
+                }
+                Some(Action::Sort) => {
+                    display.sort = display.sort.next();
+                    app.keep_selection();
+                }
+                Some(Action::ReverseSort) => {
+                    display.reverse_sort = !display.reverse_sort;
+                    app.keep_selection();
+                }
+                Some(Action::Expand) if tree => app.expand_selected(),
+                Some(Action::Collapse) if tree => app.collapse_selected(),
+                Some(Action::ApparentSize) => {
+                    byte_mode = if byte_mode == SizeMode::Apparent { SizeMode::DiskUsage } else { SizeMode::Apparent };
+                    display.size_mode = byte_mode;
+                }
+                Some(Action::Fold) => display.fold = if display.fold.is_some() { None } else { Some(fold) },
+                Some(Action::Unfold) if tree => {
+                    if let Some(path) = app.selected_path().cloned()
+                        && !app.unfolded.remove(&path)
+                    {
+                        app.unfolded.insert(path);
+                    }
+                }
+                Some(Action::ExactBytes) => display.exact_bytes = !display.exact_bytes,
+                Some(Action::DecimalUnits) => display.si = !display.si,
+                Some(Action::Inodes) => {
+                    display.size_mode = if display.size_mode == SizeMode::Inodes { byte_mode } else { SizeMode::Inodes };
+                }
+                // Raw mode turns Ctrl+C into a key press: the first one cancels the scan
+                // (keeping the partial tree on screen), a second one quits
+                Some(Action::Cancel) if scanning => {
+                    let already_cancelled = ctx.cancelled.swap(true, Ordering::SeqCst);
+                    if already_cancelled {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Quitting mid-scan stops the traversal (or the hashing) instead of waiting for it
+    if !live.is_finished() || ctx.duplicates.as_ref().is_some_and(|duplicates| !duplicates.is_done()) {
+        ctx.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    // Restore terminal state upon exit
+    drop(guard);
+
+    Ok(())
+}
+
+// The terminal as the TUI sets it up, which is put back the way it was when the guard is
+// dropped: on quitting, and on leaving run_tui early with an error. A panic of the TUI's
+// thread puts it back too, before the message is printed, so that the message can be
+// read and the shell used.
+struct TerminalGuard {
+    mouse: bool,
+    previous_hook: PreviousHook,
+}
+
+impl TerminalGuard {
+    fn new(mouse: bool) -> io::Result<TerminalGuard> {
+        let previous_hook = install_panic_hook(move || restore_terminal(mouse));
+        // From here on an error drops the guard
+        let guard = TerminalGuard { mouse, previous_hook };
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        if mouse {
+            execute!(stdout, EnableMouseCapture)?;
+        }
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal(self.mouse);
+        // Panics after the TUI are reported by the hook from before it; while one unwinds,
+        // the TUI's hook is in use and stays
+        if !thread::panicking() {
+            restore_panic_hook(&self.previous_hook);
+        }
+    }
+}
+
+type PanicHook = Box<dyn Fn(&std::panic::PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+// The panic hook that was installed before the TUI's, which the TUI's calls on.
+type PreviousHook = Arc<Mutex<Option<PanicHook>>>;
+
+// Installs a panic hook that runs `restore` for panics of the calling thread (the scan's
+// threads panicking leave the TUI running) before the hook installed until now.
+fn install_panic_hook(restore: impl Fn() + Sync + Send + 'static) -> PreviousHook {
+    let thread = thread::current().id();
+    let previous: PreviousHook = Arc::new(Mutex::new(Some(std::panic::take_hook())));
+    let hook_previous = Arc::clone(&previous);
+    std::panic::set_hook(Box::new(move |info| {
+        if thread::current().id() == thread {
+            restore();
+        }
+        if let Some(previous) = &*hook_previous.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            previous(info);
+        }
+    }));
+    previous
+}
+
+// Takes the hook of `install_panic_hook` away again, putting back the one it called on.
+fn restore_panic_hook(previous: &PreviousHook) {
+    drop(std::panic::take_hook());
+    if let Some(previous) = previous.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+        std::panic::set_hook(previous);
+    }
+}
+
+// Leaves raw mode, the mouse capture and the alternate screen. Failing to is not worth
+// reporting: there is nothing left to do about it.
+fn restore_terminal(mouse: bool) {
+    let _ = disable_raw_mode();
+    let mut stdout = io::stdout();
+    if mouse {
+        let _ = execute!(stdout, DisableMouseCapture);
+    }
+    let _ = execute!(stdout, LeaveAlternateScreen, cursor::Show);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // A directory of its own below the system's temporary directory, removed again when
+    // the test is done with it.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new() -> Fixture {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!("rdu-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+            let path = env::temp_dir().join(name);
+            fs::create_dir_all(&path).unwrap();
+            Fixture(path)
+        }
+
+        // Writes a file of `size` bytes at `name` below the fixture, creating the
+        // directories it is in.
+        fn file(&self, name: &str, size: usize) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, vec![b'x'; size]).unwrap();
+            path
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // The context main sets up for `options`, without a TUI or any of the reports.
+    fn context(options: Options) -> ScanContext {
+        ScanContext {
+            linked_files: LinkedFiles::new(),
+            hardlinks: options.hardlinks,
+            dirs_seen: SeenSet::new(),
+            follow_symlinks: options.follow_symlinks,
+            symlinks_within_root: options.symlinks_within_root,
+            excludes: options.excludes,
+            exclude_regexes: options.exclude_regexes,
+            include_regexes: options.include_regexes,
+            excluded_count: AtomicU64::new(0),
+            newer_than: options.newer_than,
+            older_than: options.older_than,
+            skip_hidden: options.skip_hidden,
+            hidden_count: AtomicU64::new(0),
+            respect_gitignore: options.respect_gitignore,
+            max_depth: options.max_depth,
+            max_children: options.max_children,
+            errors: Mutex::new(Vec::new()),
+            broken_links: Mutex::new(Vec::new()),
+            size_mode: options.size_mode,
+            progress: Arc::default(),
+            cancelled: Arc::default(),
+            count_special: options.count_special,
+            dir_sizes: options.dir_sizes,
+            exclude_caches: options.exclude_caches,
+            virtual_devices: HashSet::new(),
+            sparse_files: AtomicU64::new(0),
+            sparse_excess: AtomicU64::new(0),
+            updates: None,
+            owners: None,
+            duplicates: None,
+            empty_dirs: None,
+            empty_files: None,
+            extensions: Breakdown::new(),
+            ndjson: None,
+        }
+    }
+
+    // Scans `dir` with the options of the command line `args`, like -s would.
+    fn scan(dir: &Path, args: &[&str]) -> DirEntry {
+        scan_with(dir, args, |_| {}).0
+    }
+
+    // The same, with `setup` changing the context first; the context comes back with the tree.
+    fn scan_with(dir: &Path, args: &[&str], setup: impl FnOnce(&mut ScanContext)) -> (DirEntry, ScanContext) {
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        args.push(dir.display().to_string());
+        let options = parse_args(&args).unwrap().unwrap();
+        let roots: Vec<ScanRoot> = options.paths.iter().enumerate().map(|(index, path)| {
+            let device = if options.one_file_system { fs::metadata(path).ok().and_then(|metadata| device_id(&metadata)) } else { None };
+            let canonical = if options.symlinks_within_root { fs::canonicalize(path).ok() } else { None };
+            ScanRoot { index, path: path.clone(), device, canonical }
+        }).collect();
+        let min_size = options.min_size;
+        let mut ctx = context(options);
+        setup(&mut ctx);
+        (scan_roots(&roots, &ctx, min_size), ctx)
+    }
+
+    // The entry at the '/'-separated `path` below `tree`.
+    fn entry<'e>(tree: &'e DirEntry, path: &str) -> &'e DirEntry {
+        let path: Vec<String> = path.split('/').map(str::to_string).collect();
+        entry_at(tree, &path).unwrap_or_else(|| panic!("no entry at {:?}", path))
+    }
+
+    #[test]
+    fn parse_size_reads_units_of_1024() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_size("2MiB"), Ok(2 << 20));
+        assert_eq!(parse_size(" 7 kb "), Ok(7 * 1024));
+    }
+
+    #[test]
+    fn parse_size_rejects_unknown_units_and_overflow() {
+        assert_eq!(parse_size("10X"), Err("unknown size unit in '10X'".to_string()));
+        assert_eq!(parse_size("K"), Err("invalid size 'K'".to_string()));
+        assert_eq!(parse_size("20000000T"), Err("size '20000000T' is too large".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_linked_files_count_once() {
+        let fixture = Fixture::new();
+        let first = fixture.file("a/one", 8192);
+        fs::create_dir(fixture.0.join("b")).unwrap();
+        fs::hard_link(&first, fixture.0.join("b/two")).unwrap();
+        let tree = scan(&fixture.0, &["--apparent-size", "--no-dir-sizes"]);
+        assert_eq!(tree.apparent_size, 8192);
+        assert_eq!(tree.inodes, 4);
+        assert_eq!(entry(&tree, "a/one").apparent_size + entry(&tree, "b/two").apparent_size, 8192);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn followed_symlinks_count_their_target_once() {
+        let fixture = Fixture::new();
+        let target = fixture.file("real", 8192);
+        std::os::unix::fs::symlink(&target, fixture.0.join("link")).unwrap();
+        std::os::unix::fs::symlink(&target, fixture.0.join("other link")).unwrap();
+        let tree = scan(&fixture.0, &["-L", "--apparent-size", "--no-dir-sizes"]);
+        assert_eq!(tree.apparent_size, 8192);
+        let counted = ["real", "link", "other link"].iter().filter(|name| entry(&tree, name).apparent_size > 0).count();
+        assert_eq!(counted, 1);
+    }
+
+    // A file of 9001 bytes linked from two sibling directories, scanned with `policy`:
+    // the apparent sizes of the two links and of the whole tree.
+    #[cfg(unix)]
+    fn linked_siblings(policy: &str) -> (u64, u64, u64) {
+        let fixture = Fixture::new();
+        let first = fixture.file("a/file", 9001);
+        fs::create_dir(fixture.0.join("b")).unwrap();
+        fs::hard_link(&first, fixture.0.join("b/file")).unwrap();
+        let tree = scan(&fixture.0, &["--apparent-size", "--no-dir-sizes", "--hardlinks", policy]);
+        (entry(&tree, "a/file").apparent_size, entry(&tree, "b/file").apparent_size, tree.apparent_size)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_policies_attribute_sibling_links() {
+        assert_eq!(linked_siblings("dedup"), (9001, 0, 9001));
+        assert_eq!(linked_siblings("count-all"), (9001, 9001, 18002));
+        // The remainder of the split goes to the first path
+        assert_eq!(linked_siblings("shared"), (4501, 4500, 9001));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedup_and_shared_do_not_depend_on_the_scan_order() {
+        for policy in ["dedup", "shared"] {
+            let first = linked_siblings(policy);
+            for _ in 0..20 {
+                assert_eq!(linked_siblings(policy), first, "--hardlinks {}", policy);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_that_do_not_carry_the_size_are_marked_shared() {
+        let fixture = Fixture::new();
+        let first = fixture.file("a/file", 9001);
+        fs::create_dir(fixture.0.join("b")).unwrap();
+        fs::hard_link(&first, fixture.0.join("b/file")).unwrap();
+        let tree = scan(&fixture.0, &["--hardlinks", "shared"]);
+        let disk_usage = fs::metadata(&first).map(|metadata| allocated_size(&metadata)).unwrap();
+        assert_eq!(entry(&tree, "a/file").shared, Some((9001, disk_usage)));
+        assert_eq!(entry(&tree, "b/file").shared, Some((9001, disk_usage)));
+    }
+
+    // 50,000 nested directories named d, far deeper than a stack of calls would survive
+    // and with paths far longer than PATH_MAX. Each is made in the one above through its
+    // file descriptor, since their paths cannot be used.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn deep_directory_chains_are_walked_without_recursion() {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+        const LEVELS: usize = 50_000;
+        let fixture = Fixture::new();
+        let mut dir = fs::File::open(&fixture.0).map(OwnedFd::from).unwrap();
+        for _ in 0..LEVELS {
+            // SAFETY: the name is NUL-terminated and `dir` stays open across both calls
+            let fd = unsafe {
+                assert_eq!(libc::mkdirat(dir.as_raw_fd(), c"d".as_ptr(), 0o755), 0);
+                libc::openat(dir.as_raw_fd(), c"d".as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC)
+            };
+            assert!(fd >= 0);
+            // SAFETY: openat just returned `fd`, which nothing else owns
+            dir = unsafe { OwnedFd::from_raw_fd(fd) };
+        }
+        drop(dir);
+
+        let (tree, ctx) = scan_with(&fixture.0, &["--threads", "1"], |_| {});
+        let errors = ctx.errors.into_inner().unwrap();
+        assert_eq!(tree.dir_count, LEVELS as u64 + 1);
+        assert!(errors.is_empty(), "{} errors, the first at depth {}", errors.len(),
+            errors.first().map_or(0, |error| error.path.components().count()));
+        let mut deepest = &tree;
+        while let Some(child) = deepest.children.first() {
+            deepest = child;
+        }
+        assert!(!deepest.incomplete);
+        assert!(deepest.children.is_empty());
+        // Dropping a tree recurses into its children, so this one is taken apart from the top
+        let mut rest = tree.children;
+        while let Some(mut child) = rest.pop() {
+            rest.append(&mut child.children);
+        }
+
+        // Moving the second level up in place of the first shortens the chain by one
+        let (first, second, next) = (fixture.0.join("d"), fixture.0.join("d/d"), fixture.0.join("next"));
+        while second.exists() {
+            fs::rename(&second, &next).unwrap();
+            fs::remove_dir(&first).unwrap();
+            fs::rename(&next, &first).unwrap();
+        }
+    }
+
+    // A few directories of files, one of them hard-linked, for comparing with du.
+    #[cfg(unix)]
+    fn du_fixture() -> Fixture {
+        let fixture = Fixture::new();
+        for (name, size) in [("a/one", 100), ("a/two", 5000), ("a/deeper/three", 70_000), ("b/four", 0), ("five", 4096)] {
+            fixture.file(name, size);
+        }
+        fs::create_dir_all(fixture.0.join("empty/too")).unwrap();
+        fs::hard_link(fixture.0.join("a/two"), fixture.0.join("b/two")).unwrap();
+        fixture
+    }
+
+    // What `du -B1` says every directory below `dir` takes, by its path below it.
+    #[cfg(unix)]
+    fn du_totals(dir: &Path) -> HashMap<String, u64> {
+        let output = std::process::Command::new("du").arg("-B1").arg(dir).output().expect("du is needed to compare with");
+        assert!(output.status.success(), "du failed: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().lines()
+            .map(|line| {
+                let (size, path) = line.split_once('\t').unwrap();
+                let below = Path::new(path).strip_prefix(dir).unwrap();
+                (below.to_string_lossy().into_owned(), size.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn totals_match_du() {
+        let fixture = du_fixture();
+        let du = du_totals(&fixture.0);
+        let tree = scan(&fixture.0, &[]);
+        assert_eq!(du.len(), 6, "{:?}", du);
+        assert_eq!(tree.disk_usage, du[""]);
+        for dir in ["a/deeper", "empty", "empty/too"] {
+            assert_eq!(entry(&tree, dir).disk_usage, du[dir], "{}", dir);
+        }
+        // du counts the hard-linked file in whichever of a and b it reads first
+        assert_eq!(entry(&tree, "a").disk_usage + entry(&tree, "b").disk_usage, du["a"] + du["b"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_dir_sizes_counts_only_the_files() {
+        let fixture = du_fixture();
+        let files: u64 = ["a/one", "a/two", "a/deeper/three", "b/four", "five"].iter()
+            .map(|name| allocated_size(&fs::metadata(fixture.0.join(name)).unwrap()))
+            .sum();
+        assert_eq!(scan(&fixture.0, &["--no-dir-sizes"]).disk_usage, files);
+    }
+
+    #[test]
+    fn format_size_switches_units_at_1024_or_1000() {
+        let binary: Vec<String> = [999, 1000, 1023, 1024].map(|bytes| format_size(bytes, Units::Binary)).to_vec();
+        assert_eq!(binary, ["999.0 B", "1000.0 B", "1023.0 B", "1.0 KiB"]);
+        let decimal: Vec<String> = [999, 1000, 1023, 1024].map(|bytes| format_size(bytes, Units::Decimal)).to_vec();
+        assert_eq!(decimal, ["999.0 B", "1.0 kB", "1.0 kB", "1.0 kB"]);
+        assert_eq!(format_size(1024, Units::Exact), "1,024");
+    }
+
+    #[test]
+    fn format_size_labels() {
+        assert_eq!(format_size(3 << 29, Units::Binary), "1.5 GiB");
+        assert_eq!(format_size(5 << 20, Units::Binary), "5.0 MiB");
+        assert_eq!(format_size(1_500_000_000, Units::Decimal), "1.5 GB");
+        assert_eq!(format_size(5_000_000, Units::Decimal), "5.0 MB");
+        assert_eq!(format_size(2 << 40, Units::Binary), "2.0 TiB");
+    }
+
+    #[test]
+    fn parse_age_reads_durations_and_dates() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        const DAY: u64 = 24 * 60 * 60;
+        assert_eq!(parse_age("7d", now), Ok(now - Duration::from_secs(7 * DAY)));
+        assert_eq!(parse_age("6months", now), Ok(now - Duration::from_secs(180 * DAY)));
+        assert_eq!(parse_age("6mo", now), parse_age("6months", now));
+        assert_eq!(parse_age("30min", now), Ok(now - Duration::from_secs(30 * 60)));
+        let midnight = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let local: SystemTime = midnight.and_local_timezone(Local).earliest().unwrap().into();
+        assert_eq!(parse_age("2024-01-31", now), Ok(local));
+    }
+
+    #[test]
+    fn parse_age_rejects_unknown_units_empty_ages_and_overflow() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        assert!(parse_age("7x", now).unwrap_err().starts_with("invalid age '7x'"));
+        assert!(parse_age("", now).unwrap_err().starts_with("invalid age ''"));
+        assert!(parse_age("5m", now).unwrap_err().starts_with("ambiguous age '5m'"));
+        // More seconds than a u64 holds
+        assert_eq!(parse_age("99999999999999999y", now), Err("age '99999999999999999y' is too large".to_string()));
+    }
+
+    #[test]
+    fn exclude_from_skips_comments_blank_lines_and_trailing_whitespace() {
+        let fixture = Fixture::new();
+        for name in ["keep.txt", "a.log", "sub/b.log", "sub/keep.bin", "build/out.bin", "sub/build/x"] {
+            fixture.file(name, 10);
+        }
+        let patterns = Fixture::new();
+        let list = patterns.0.join("excludes");
+        fs::write(&list, "# build output\r\n\r\n*.log  \r\nbuild\t\r\n   \n#keep.txt\n").unwrap();
+        let tree = scan(&fixture.0, &["--exclude-from", list.to_str().unwrap()]);
+
+        let mut names = Vec::new();
+        let mut pending = vec![(String::new(), &tree)];
+        while let Some((path, dir)) = pending.pop() {
+            for child in &dir.children {
+                let child_path = if path.is_empty() { child.name.clone() } else { format!("{}/{}", path, child.name) };
+                pending.push((child_path.clone(), child));
+                names.push(child_path);
+            }
+        }
+        names.sort();
+        assert_eq!(names, ["keep.txt", "sub", "sub/keep.bin"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedup_labels_the_link_that_does_not_carry_the_size() {
+        let fixture = Fixture::new();
+        let first = fixture.file("a/file", 9001);
+        fs::create_dir(fixture.0.join("b")).unwrap();
+        fs::hard_link(&first, fixture.0.join("b/file")).unwrap();
+        let tree = scan(&fixture.0, &["--apparent-size"]);
+        let disk_usage = fs::metadata(&first).map(|metadata| allocated_size(&metadata)).unwrap();
+        let (carrier, other) = (entry(&tree, "a/file"), entry(&tree, "b/file"));
+        assert_eq!((carrier.apparent_size, carrier.links, carrier.shared), (9001, 2, None));
+        assert_eq!((other.apparent_size, other.links), (0, 2));
+        assert_eq!(other.shared, Some((9001, disk_usage)));
+    }
+
+    #[test]
+    fn centered_fits_wide_terminals() {
+        let area = Rect { x: 10, y: 5, width: 1000, height: 300 };
+        assert_eq!(centered(area, 80, 60), Rect { x: 110, y: 65, width: 800, height: 180 });
+        let widest = Rect { x: 0, y: 0, width: u16::MAX, height: u16::MAX };
+        assert_eq!(centered(widest, 100, 100), widest);
+    }
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn help_is_asked_for_where_an_option_goes() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert!(parse(&["-x", "--help", "--no-such-option"]).unwrap().is_none());
+        // As the value of an option it is just text
+        let options = parse(&["--exclude", "--help", "."]).unwrap().unwrap();
+        assert!(options.excludes[0].matches("--help"));
+        assert_eq!(options.paths, [PathBuf::from(".")]);
+    }
+
+    #[test]
+    fn human_readable_only_goes_with_du_summarize_and_print() {
+        for args in [&["--du", "-h", "."][..], &["-sh", "."], &["--print", "-h", "."]] {
+            assert!(parse(args).is_ok_and(|options| options.is_some()), "{:?}", args);
+        }
+        assert_eq!(parse(&["-h", "."]).unwrap_err(), "-h only applies to --du, -s and --print");
+        assert_eq!(parse(&["--json", "-h", "."]).unwrap_err(), "-h only applies to --du, -s and --print");
+    }
+
+    #[test]
+    fn ndjson_writes_directories_after_everything_in_them() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl io::Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let fixture = Fixture::new();
+        for name in ["a/b/one", "a/b/two", "a/three", "c/four", "five"] {
+            fixture.file(name, 10);
+        }
+        let out = Shared::default();
+        scan_with(&fixture.0, &[], |ctx| {
+            ctx.ndjson = Some(json::Lines::new(out.clone(), SizeMode::DiskUsage, None, None, Arc::default()));
+        });
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let paths: Vec<String> = out.lines()
+            .map(|line| {
+                let path = line.strip_prefix("{\"path\":\"").unwrap().split('"').next().unwrap();
+                path.strip_prefix(fixture.0.to_str().unwrap()).unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(paths.len(), 9, "{:?}", paths);
+        let position = |path: &str| paths.iter().position(|written| written == path).unwrap();
+        for (inside, dir) in [("/a/b/one", "/a/b"), ("/a/b/two", "/a/b"), ("/a/b", "/a"), ("/a/three", "/a"), ("/c/four", "/c")] {
+            assert!(position(inside) < position(dir), "{} after {}: {:?}", inside, dir, paths);
+        }
+        assert_eq!(paths.last().unwrap(), "");
+    }
+
+    #[test]
+    fn tab_keys_stay_within_the_tabs() {
+        let digit = |c: char, active: usize| tab_for_key(Action::GoToTab, KeyCode::Char(c), active, 3);
+        assert_eq!(digit('1', 2), 0);
+        assert_eq!(digit('3', 0), 2);
+        // There is no tab 0, nor any past the last
+        assert_eq!(digit('0', 1), 1);
+        assert_eq!(digit('4', 1), 1);
+        assert_eq!(digit('9', 2), 2);
+        assert_eq!(tab_for_key(Action::NextTab, KeyCode::Tab, 2, 3), 0);
+        assert_eq!(tab_for_key(Action::PreviousTab, KeyCode::BackTab, 0, 3), 2);
+        assert_eq!(tab_for_key(Action::NextTab, KeyCode::Tab, 0, 1), 0);
+    }
+
+    #[test]
+    fn the_panic_hook_from_before_the_tui_is_put_back() {
+        let custom = Arc::new(AtomicUsize::new(0));
+        let restored = Arc::new(AtomicUsize::new(0));
+        // Panics of other tests in the meantime still reach the hook of the test harness
+        let harness: Arc<PanicHook> = Arc::new(std::panic::take_hook());
+        let (hook_custom, hook_harness) = (Arc::clone(&custom), Arc::clone(&harness));
+        std::panic::set_hook(Box::new(move |info| {
+            hook_custom.fetch_add(1, Ordering::SeqCst);
+            hook_harness(info);
+        }));
+
+        let hook_restored = Arc::clone(&restored);
+        let previous = install_panic_hook(move || {
+            hook_restored.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(std::panic::catch_unwind(|| panic!("in the TUI")).is_err());
+        assert_eq!((restored.load(Ordering::SeqCst), custom.load(Ordering::SeqCst)), (1, 1));
+        // Other threads leave the terminal alone
+        assert!(thread::spawn(|| panic!("in a scan")).join().is_err());
+        assert_eq!((restored.load(Ordering::SeqCst), custom.load(Ordering::SeqCst)), (1, 2));
+
+        restore_panic_hook(&previous);
+        assert!(std::panic::catch_unwind(|| panic!("after the TUI")).is_err());
+        assert_eq!((restored.load(Ordering::SeqCst), custom.load(Ordering::SeqCst)), (1, 3));
+
+        drop(std::panic::take_hook());
+        std::panic::set_hook(Box::new(move |info| harness(info)));
+    }
+}
@@ -0,0 +1,106 @@
+// The tree written to stdout as text (--print) instead of opening the TUI: the rows of the
+// tree view with every directory expanded, largest first, and whole names.
+//
+// With --color=always, or with the default --color=auto when stdout is a terminal that is
+// not "dumb" and NO_COLOR is not set, each row is drawn in the colors the TUI gives it,
+// as ANSI escape sequences; otherwise the text is plain.
+
+use std::io::{self, IsTerminal, Write};
+
+use ratatui::style::{Color, Modifier, Style};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorWhen {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorWhen {
+    pub fn parse(value: &str) -> Result<ColorWhen, String> {
+        match value {
+            "auto" => Ok(ColorWhen::Auto),
+            "always" => Ok(ColorWhen::Always),
+            "never" => Ok(ColorWhen::Never),
+            other => Err(format!("expected auto, always or never, got '{}'", other)),
+        }
+    }
+
+    // Whether the rows written to stdout get colors.
+    pub fn colors(self) -> bool {
+        match self {
+            ColorWhen::Always => true,
+            ColorWhen::Never => false,
+            ColorWhen::Auto => {
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                let dumb = std::env::var_os("TERM").is_some_and(|term| term == "dumb");
+                io::stdout().is_terminal() && !no_color && !dumb
+            }
+        }
+    }
+}
+
+// Writes `rows` to `out`, one per line, in their styles if `colors`.
+pub fn write(out: impl Write, rows: &[(String, Style)], colors: bool) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    for (text, style) in rows {
+        let codes = if colors { sgr_codes(*style) } else { Vec::new() };
+        if codes.is_empty() {
+            writeln!(out, "{}", text)?;
+        } else {
+            writeln!(out, "\x1b[{}m{}\x1b[0m", codes.join(";"), text)?;
+        }
+    }
+    out.flush()
+}
+
+// The parameters of the SGR escape sequence that sets `style`.
+fn sgr_codes(style: Style) -> Vec<String> {
+    let mut codes = Vec::new();
+    for (modifier, code) in [
+        (Modifier::BOLD, "1"),
+        (Modifier::DIM, "2"),
+        (Modifier::ITALIC, "3"),
+        (Modifier::UNDERLINED, "4"),
+        (Modifier::REVERSED, "7"),
+        (Modifier::CROSSED_OUT, "9"),
+    ] {
+        if style.add_modifier.contains(modifier) {
+            codes.push(code.to_string());
+        }
+    }
+    if let Some(color) = style.fg.and_then(|color| color_code(color, 30)) {
+        codes.push(color);
+    }
+    if let Some(color) = style.bg.and_then(|color| color_code(color, 40)) {
+        codes.push(color);
+    }
+    codes
+}
+
+// The SGR parameter of `color` as a foreground (`base` 30) or background (40) color.
+fn color_code(color: Color, base: u8) -> Option<String> {
+    let code = match color {
+        Color::Reset => return None,
+        Color::Black => base,
+        Color::Red => base + 1,
+        Color::Green => base + 2,
+        Color::Yellow => base + 3,
+        Color::Blue => base + 4,
+        Color::Magenta => base + 5,
+        Color::Cyan => base + 6,
+        Color::Gray => base + 7,
+        Color::DarkGray => base + 60,
+        Color::LightRed => base + 61,
+        Color::LightGreen => base + 62,
+        Color::LightYellow => base + 63,
+        Color::LightBlue => base + 64,
+        Color::LightMagenta => base + 65,
+        Color::LightCyan => base + 66,
+        Color::White => base + 67,
+        Color::Indexed(index) => return Some(format!("{};5;{}", base + 8, index)),
+        Color::Rgb(red, green, blue) => return Some(format!("{};2;{};{};{}", base + 8, red, green, blue)),
+    };
+    Some(code.to_string())
+}
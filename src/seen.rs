@@ -0,0 +1,37 @@
+// Concurrent "have we counted this already?" set shared by all scan workers.
+//
+// A single Mutex<HashSet> makes every rayon worker queue up on one lock for every file.
+// The set is split into shards picked by the value's hash instead, so workers only
+// contend when two of them happen to hit the same shard at the same moment.
+
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::sync::Mutex;
+
+const SHARDS: usize = 64;
+
+pub struct SeenSet<T> {
+    shards: Vec<Mutex<HashSet<T>>>,
+    hasher: RandomState,
+}
+
+impl<T: Hash + Eq> SeenSet<T> {
+    pub fn new() -> SeenSet<T> {
+        SeenSet {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashSet::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    // Adds the value, returning true if it was not present yet (same as HashSet::insert).
+    pub fn insert(&self, value: T) -> bool {
+        let shard = self.hasher.hash_one(&value) as usize % SHARDS;
+        self.shards[shard].lock().unwrap().insert(value)
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap().clear();
+        }
+    }
+}
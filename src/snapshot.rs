@@ -0,0 +1,475 @@
+// Snapshots of a finished scan (--save-snapshot), which the TUI opens later without
+// reading the disk again (--load-snapshot).
+//
+// A snapshot holds the roots, when the scan was made and how long it took, its command
+// line, its statistics, errors and broken links, the per-owner and per-extension totals,
+// and the whole tree with every metric, before --min-size drops anything, so that the
+// TUI shows what it would have shown after the scan. It is binary: after a header of the
+// magic line and the format's version, numbers are written as LEB128 varints and strings
+// as their length and UTF-8 bytes, each entry followed by its children. The file is read
+// into memory whole and decoded in one pass, so loading takes about as long as reading it.
+//
+// Every change to what is written gets a new VERSION; a file of a version this build
+// cannot read is turned away with the reason instead of being decoded into nonsense.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::breakdown::Usage;
+use crate::{BrokenLink, DirEntry, ScanContext, ScanError, SpecialKind};
+
+const MAGIC: &[u8] = b"rdu snapshot\n";
+const VERSION: u64 = 1;
+// Oldest version this build still reads
+const OLDEST_VERSION: u64 = 1;
+
+// What --load-snapshot gets back: the tree and what the TUI shows about the scan.
+pub struct Snapshot {
+    pub roots: Vec<PathBuf>,
+    pub scanned_at: SystemTime,
+    pub elapsed: Duration,
+    // The command line of the scan, without the program
+    pub args: Vec<String>,
+    pub tree: DirEntry,
+    // What the scan gathered besides the tree, handed to the context by `restore`
+    counts: Vec<u64>,
+    errors: Vec<ScanError>,
+    broken_links: Vec<BrokenLink>,
+    owners: HashMap<u32, Usage>,
+    extensions: HashMap<String, Usage>,
+}
+
+impl Snapshot {
+    // Hands the statistics, errors, broken links and per-owner and per-extension totals of
+    // the scan to `ctx`, as if it had just made it.
+    pub fn restore(&mut self, ctx: &ScanContext) {
+        let progress = &ctx.progress;
+        let counters = [
+            &progress.entries,
+            &progress.bytes,
+            &progress.files,
+            &progress.directories,
+            &progress.symlinks,
+            &progress.hardlinks,
+            &ctx.excluded_count,
+            &ctx.hidden_count,
+        ];
+        for (counter, &count) in counters.iter().zip(&self.counts) {
+            counter.store(count, Ordering::Relaxed);
+        }
+        *ctx.errors.lock().unwrap() = std::mem::take(&mut self.errors);
+        *ctx.broken_links.lock().unwrap() = std::mem::take(&mut self.broken_links);
+        if let Some(owners) = &ctx.owners {
+            owners.merge(std::mem::take(&mut self.owners));
+        }
+        ctx.extensions.merge(std::mem::take(&mut self.extensions));
+    }
+}
+
+// Bits of the flags written before each entry
+const IS_DIR: u64 = 1;
+const IS_SYMLINK: u64 = 1 << 1;
+const BROKEN_LINK: u64 = 1 << 2;
+const SKIPPED_MOUNT: u64 = 1 << 3;
+const SKIPPED_CACHE: u64 = 1 << 4;
+const SKIPPED_VIRTUAL: u64 = 1 << 5;
+const TRUNCATED: u64 = 1 << 6;
+const INCOMPLETE: u64 = 1 << 7;
+const SPARSE: u64 = 1 << 8;
+const LINKED: u64 = 1 << 9;
+const EMPTY: u64 = 1 << 10;
+const HAS_TARGET: u64 = 1 << 11;
+const HAS_MTIME: u64 = 1 << 12;
+const HAS_SPECIAL: u64 = 1 << 13;
+const HAS_SHARED: u64 = 1 << 14;
+const HAS_OWNER: u64 = 1 << 15;
+
+const SPECIAL_KINDS: [SpecialKind; 4] = [SpecialKind::CharDevice, SpecialKind::BlockDevice, SpecialKind::Fifo, SpecialKind::Socket];
+
+// The kinds of the errors a scan records, by their number in the file. Any other kind is
+// written as the last one, `Other`.
+const ERROR_KINDS: [io::ErrorKind; 6] = [
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::Other,
+];
+
+struct Encoder<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Encoder<W> {
+    fn number(&mut self, mut value: u64) -> io::Result<()> {
+        let mut bytes = [0u8; 10];
+        let mut length = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes[length] = byte;
+                length += 1;
+                break;
+            }
+            bytes[length] = byte | 0x80;
+            length += 1;
+        }
+        self.out.write_all(&bytes[..length])
+    }
+
+    fn string(&mut self, text: &str) -> io::Result<()> {
+        self.number(text.len() as u64)?;
+        self.out.write_all(text.as_bytes())
+    }
+
+    fn path(&mut self, path: &Path) -> io::Result<()> {
+        self.string(&path.to_string_lossy())
+    }
+
+    // A point in time as seconds since 1970, negative before it (zigzag-encoded), and
+    // nanoseconds.
+    fn time(&mut self, time: SystemTime) -> io::Result<()> {
+        let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+            Err(e) => {
+                let before = e.duration();
+                let nanos = before.subsec_nanos();
+                let seconds = -(before.as_secs() as i64) - i64::from(nanos > 0);
+                (seconds, if nanos > 0 { 1_000_000_000 - nanos } else { 0 })
+            }
+        };
+        self.number(((seconds << 1) ^ (seconds >> 63)) as u64)?;
+        self.number(nanos.into())
+    }
+
+    fn usage(&mut self, usage: &Usage) -> io::Result<()> {
+        self.number(usage.apparent_size)?;
+        self.number(usage.disk_usage)?;
+        self.number(usage.files)
+    }
+
+    fn entry(&mut self, entry: &DirEntry) -> io::Result<()> {
+        let mut flags = 0;
+        for (set, flag) in [
+            (entry.is_dir, IS_DIR),
+            (entry.is_symlink, IS_SYMLINK),
+            (entry.broken_link, BROKEN_LINK),
+            (entry.skipped_mount, SKIPPED_MOUNT),
+            (entry.skipped_cache, SKIPPED_CACHE),
+            (entry.skipped_virtual, SKIPPED_VIRTUAL),
+            (entry.truncated, TRUNCATED),
+            (entry.incomplete, INCOMPLETE),
+            (entry.sparse, SPARSE),
+            (entry.linked, LINKED),
+            (entry.empty, EMPTY),
+            (entry.link_target.is_some(), HAS_TARGET),
+            (entry.mtime.is_some(), HAS_MTIME),
+            (entry.special.is_some(), HAS_SPECIAL),
+            (entry.shared.is_some(), HAS_SHARED),
+            (entry.owner.is_some(), HAS_OWNER),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+        self.string(&entry.name)?;
+        self.number(flags)?;
+        for value in [
+            entry.apparent_size,
+            entry.disk_usage,
+            entry.file_count,
+            entry.dir_count,
+            entry.inodes,
+            entry.pruned,
+            entry.links,
+            entry.empty_files,
+        ] {
+            self.number(value)?;
+        }
+        if let Some(target) = &entry.link_target {
+            self.string(target)?;
+        }
+        if let Some(mtime) = entry.mtime {
+            self.time(mtime)?;
+        }
+        if let Some(special) = entry.special {
+            self.number(SPECIAL_KINDS.iter().position(|&kind| kind == special).unwrap_or(0) as u64)?;
+        }
+        if let Some((apparent_size, disk_usage)) = entry.shared {
+            self.number(apparent_size)?;
+            self.number(disk_usage)?;
+        }
+        if let Some(owner) = entry.owner {
+            self.number(owner.into())?;
+        }
+        self.number(entry.children.len() as u64)?;
+        for child in &entry.children {
+            self.entry(child)?;
+        }
+        Ok(())
+    }
+}
+
+// Writes the scan of `roots`, `tree`, with what `ctx` gathered about it, to `file`. The
+// file is written under another name first, so that a snapshot that fails half way does
+// not replace an earlier one.
+pub fn save(file: &Path, roots: &[&Path], args: &[String], tree: &DirEntry, ctx: &ScanContext, elapsed: Duration) -> io::Result<()> {
+    let mut partial = file.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut snapshot = Encoder { out: BufWriter::new(fs::File::create(&partial)?) };
+    snapshot.out.write_all(MAGIC)?;
+    snapshot.number(VERSION)?;
+
+    snapshot.number(roots.len() as u64)?;
+    for root in roots {
+        snapshot.path(root)?;
+    }
+    snapshot.time(SystemTime::now())?;
+    snapshot.number(elapsed.as_secs())?;
+    snapshot.number(elapsed.subsec_nanos().into())?;
+    snapshot.number(args.len() as u64)?;
+    for arg in args {
+        snapshot.string(arg)?;
+    }
+
+    let stats = ctx.stats();
+    for count in [stats.entries, stats.bytes, stats.files, stats.directories, stats.symlinks, stats.hardlinks, stats.excluded, stats.hidden] {
+        snapshot.number(count)?;
+    }
+    let errors = ctx.errors.lock().unwrap();
+    snapshot.number(errors.len() as u64)?;
+    for error in errors.iter() {
+        snapshot.path(&error.path)?;
+        let kind = ERROR_KINDS.iter().position(|&kind| kind == error.kind).unwrap_or(ERROR_KINDS.len() - 1);
+        snapshot.number(kind as u64)?;
+    }
+    drop(errors);
+    let broken_links = ctx.broken_links.lock().unwrap();
+    snapshot.number(broken_links.len() as u64)?;
+    for link in broken_links.iter() {
+        snapshot.path(&link.path)?;
+        snapshot.path(&link.target)?;
+    }
+    drop(broken_links);
+
+    let owners = ctx.owners.as_ref().map(|owners| owners.rows(ctx.size_mode)).unwrap_or_default();
+    snapshot.number(owners.len() as u64)?;
+    for (uid, usage) in &owners {
+        snapshot.number((*uid).into())?;
+        snapshot.usage(usage)?;
+    }
+    let extensions = ctx.extensions.rows(ctx.size_mode);
+    snapshot.number(extensions.len() as u64)?;
+    for (extension, usage) in &extensions {
+        snapshot.string(extension)?;
+        snapshot.usage(usage)?;
+    }
+
+    snapshot.entry(tree)?;
+    snapshot.out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&partial, file)
+}
+
+struct Decoder<'d> {
+    data: &'d [u8],
+    at: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.at).ok_or("the snapshot is cut short")?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("the snapshot is damaged (a number is too long)".to_string())
+    }
+
+    // A count of things that follow, none of which takes less than a byte, so that a
+    // damaged count cannot ask for more memory than the file could fill.
+    fn count(&mut self) -> Result<usize, String> {
+        let count = self.number()?;
+        if count > (self.data.len() - self.at) as u64 {
+            return Err("the snapshot is damaged (a count is too large)".to_string());
+        }
+        Ok(count as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.count()?;
+        let bytes = &self.data[self.at..self.at + length];
+        self.at += length;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "the snapshot is damaged (a name is not UTF-8)".to_string())
+    }
+
+    fn time(&mut self) -> Result<SystemTime, String> {
+        let zigzag = self.number()?;
+        let seconds = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        let nanos = Duration::from_nanos(self.number()?);
+        let time = if seconds >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+        };
+        time.and_then(|time| time.checked_add(nanos)).ok_or_else(|| "the snapshot is damaged (a time is out of range)".to_string())
+    }
+
+    fn usage(&mut self) -> Result<Usage, String> {
+        Ok(Usage { apparent_size: self.number()?, disk_usage: self.number()?, files: self.number()? })
+    }
+
+    fn entry(&mut self) -> Result<DirEntry, String> {
+        let name = self.string()?;
+        let flags = self.number()?;
+        let set = |flag: u64| flags & flag != 0;
+        let mut entry = DirEntry {
+            name,
+            is_dir: set(IS_DIR),
+            is_symlink: set(IS_SYMLINK),
+            broken_link: set(BROKEN_LINK),
+            skipped_mount: set(SKIPPED_MOUNT),
+            skipped_cache: set(SKIPPED_CACHE),
+            skipped_virtual: set(SKIPPED_VIRTUAL),
+            truncated: set(TRUNCATED),
+            incomplete: set(INCOMPLETE),
+            sparse: set(SPARSE),
+            linked: set(LINKED),
+            empty: set(EMPTY),
+            apparent_size: self.number()?,
+            disk_usage: self.number()?,
+            file_count: self.number()?,
+            dir_count: self.number()?,
+            inodes: self.number()?,
+            pruned: self.number()?,
+            links: self.number()?,
+            empty_files: self.number()?,
+            ..Default::default()
+        };
+        if set(HAS_TARGET) {
+            entry.link_target = Some(self.string()?);
+        }
+        if set(HAS_MTIME) {
+            entry.mtime = Some(self.time()?);
+        }
+        if set(HAS_SPECIAL) {
+            let kind = self.number()?;
+            entry.special = Some(*SPECIAL_KINDS.get(kind as usize).ok_or("the snapshot is damaged (unknown kind of file)")?);
+        }
+        if set(HAS_SHARED) {
+            entry.shared = Some((self.number()?, self.number()?));
+        }
+        if set(HAS_OWNER) {
+            entry.owner = Some(u32::try_from(self.number()?).map_err(|_| "the snapshot is damaged (an owner is out of range)")?);
+        }
+        let children = self.count()?;
+        entry.children.reserve_exact(children);
+        for _ in 0..children {
+            entry.children.push(self.entry()?);
+        }
+        Ok(entry)
+    }
+}
+
+// Reads the snapshot in `file`.
+pub fn load(file: &Path) -> Result<Snapshot, String> {
+    let data = fs::read(file).map_err(|e| e.to_string())?;
+    let Some(rest) = data.strip_prefix(MAGIC) else {
+        return Err("not an rdu snapshot".to_string());
+    };
+    let mut snapshot = Decoder { data: rest, at: 0 };
+    let version = snapshot.number()?;
+    if version < OLDEST_VERSION {
+        return Err(format!("snapshot format too old (version {}; this rdu reads versions {} to {})", version, OLDEST_VERSION, VERSION));
+    }
+    if version > VERSION {
+        return Err(format!("snapshot format too new (version {}; this rdu reads versions {} to {})", version, OLDEST_VERSION, VERSION));
+    }
+
+    let roots = (0..snapshot.count()?).map(|_| snapshot.string().map(PathBuf::from)).collect::<Result<Vec<_>, _>>()?;
+    if roots.is_empty() {
+        return Err("the snapshot is damaged (it has no roots)".to_string());
+    }
+    let scanned_at = snapshot.time()?;
+    let elapsed = Duration::new(snapshot.number()?, snapshot.number()? as u32);
+    let args = (0..snapshot.count()?).map(|_| snapshot.string()).collect::<Result<Vec<_>, _>>()?;
+
+    let counts = (0..8).map(|_| snapshot.number()).collect::<Result<Vec<_>, _>>()?;
+    let mut errors = Vec::new();
+    for _ in 0..snapshot.count()? {
+        let path = PathBuf::from(snapshot.string()?);
+        let kind = *ERROR_KINDS.get(snapshot.number()? as usize).ok_or("the snapshot is damaged (unknown kind of error)")?;
+        errors.push(ScanError { path, kind });
+    }
+    let mut broken_links = Vec::new();
+    for _ in 0..snapshot.count()? {
+        broken_links.push(BrokenLink { path: PathBuf::from(snapshot.string()?), target: PathBuf::from(snapshot.string()?) });
+    }
+
+    let mut owners = HashMap::new();
+    for _ in 0..snapshot.count()? {
+        let uid = u32::try_from(snapshot.number()?).map_err(|_| "the snapshot is damaged (an owner is out of range)")?;
+        owners.insert(uid, snapshot.usage()?);
+    }
+    let mut extensions = HashMap::new();
+    for _ in 0..snapshot.count()? {
+        extensions.insert(snapshot.string()?, snapshot.usage()?);
+    }
+
+    let tree = snapshot.entry()?;
+    if snapshot.at != snapshot.data.len() {
+        return Err("the snapshot is damaged (something follows the tree)".to_string());
+    }
+    Ok(Snapshot { roots, scanned_at, elapsed, args, tree, counts, errors, broken_links, owners, extensions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What loading a file holding `data` says about it.
+    fn load_error(name: &str, data: &[u8]) -> String {
+        let file = std::env::temp_dir().join(format!("rdu-snapshot-{}-{}", std::process::id(), name));
+        fs::write(&file, data).unwrap();
+        let loaded = load(&file);
+        fs::remove_file(&file).unwrap();
+        loaded.err().expect("the file was loaded")
+    }
+
+    fn header(version: u64) -> Vec<u8> {
+        let mut encoder = Encoder { out: MAGIC.to_vec() };
+        encoder.number(version).unwrap();
+        encoder.out
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        assert_eq!(load_error("old", &header(OLDEST_VERSION - 1)), "snapshot format too old (version 0; this rdu reads versions 1 to 1)");
+        let newer = format!("snapshot format too new (version {}; this rdu reads versions {} to {})", VERSION + 1, OLDEST_VERSION, VERSION);
+        assert_eq!(load_error("new", &header(VERSION + 1)), newer);
+        // A version from far in the future is refused before anything else is read
+        assert!(load_error("future", &header(u64::MAX)).starts_with("snapshot format too new"));
+    }
+
+    #[test]
+    fn other_files_and_cut_snapshots_are_refused() {
+        assert_eq!(load_error("text", b"just some text\n"), "not an rdu snapshot");
+        assert_eq!(load_error("empty", MAGIC), "the snapshot is cut short");
+        assert_eq!(load_error("header", &header(VERSION)), "the snapshot is cut short");
+    }
+}
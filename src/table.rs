@@ -0,0 +1,118 @@
+// The scan written to stdout as a table with a row per entry (--csv, --tsv), for
+// spreadsheets, instead of opening the TUI.
+//
+// The columns are the path below the root (with several roots, starting with the root
+// as given), the type, the size of the entry itself, its total with everything below
+// it, the number of entries below a directory, and the modification time in seconds
+// since 1970 (empty when unknown). Sizes are in the metric of the scan: bytes on disk,
+// apparent bytes, or inodes with --count-inodes. A directory's `size` is the space it
+// takes itself, so the `size` column adds up to the total of the root; a file's is the
+// same as its total.
+//
+// CSV quotes the fields that need it (RFC 4180), so names with commas, quotes or line
+// breaks come out whole. TSV cannot quote, so tabs, line breaks and backslashes in names
+// are written as \t, \n, \r and \\. Names that were not valid UTF-8 have U+FFFD in place
+// of the bytes that were not, as in the tree. --files-only leaves out the directories.
+
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+use crate::{DirEntry, SizeMode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Tsv,
+}
+
+const HEADER: [&str; 6] = ["path", "type", "size", "total", "items", "mtime"];
+
+struct Table<W: Write> {
+    out: W,
+    format: Format,
+    mode: SizeMode,
+    files_only: bool,
+}
+
+impl<W: Write> Table<W> {
+    fn row(&mut self, fields: &[&str]) -> io::Result<()> {
+        let separator = match self.format {
+            Format::Csv => ",",
+            Format::Tsv => "\t",
+        };
+        for (index, field) in fields.iter().enumerate() {
+            if index > 0 {
+                self.out.write_all(separator.as_bytes())?;
+            }
+            match self.format {
+                Format::Csv if field.contains([',', '"', '\n', '\r']) => {
+                    write!(self.out, "\"{}\"", field.replace('"', "\"\""))?;
+                }
+                Format::Tsv if field.contains(['\t', '\n', '\r', '\\']) => {
+                    let escaped = field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r");
+                    self.out.write_all(escaped.as_bytes())?;
+                }
+                _ => self.out.write_all(field.as_bytes())?,
+            }
+        }
+        self.out.write_all(b"\n")
+    }
+
+    // Writes the row of `entry`, at `path`, and those of everything below it. The path
+    // grows and shrinks again on the way down instead of being built for every row.
+    fn entry(&mut self, entry: &DirEntry, path: &mut String) -> io::Result<()> {
+        let total = entry.size(self.mode);
+        let below: u64 = entry.children.iter().map(|child| child.size(self.mode)).sum();
+        let kind = if entry.pruned > 0 {
+            "summary"
+        } else if entry.is_symlink {
+            "symlink"
+        } else if entry.is_dir {
+            "dir"
+        } else if let Some(special) = entry.special {
+            special.label()
+        } else {
+            "file"
+        };
+        let items = if entry.pruned > 0 {
+            entry.pruned.to_string()
+        } else if entry.is_dir {
+            (entry.file_count + entry.dir_count).saturating_sub(1).to_string()
+        } else {
+            String::new()
+        };
+        let mtime = entry.mtime
+            .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|since| since.as_secs().to_string())
+            .unwrap_or_default();
+        if !(self.files_only && entry.is_dir) {
+            let name = if path.is_empty() { "." } else { path.as_str() };
+            self.row(&[name, kind, &total.saturating_sub(below).to_string(), &total.to_string(), &items, &mtime])?;
+        }
+        for child in &entry.children {
+            let length = path.len();
+            if !path.is_empty() && !path.ends_with('/') {
+                path.push('/');
+            }
+            path.push_str(&child.name);
+            self.entry(child, path)?;
+            path.truncate(length);
+        }
+        Ok(())
+    }
+}
+
+// Writes a header and the rows of every entry of `tree` to `out`. With several roots the
+// tree's own row, which only adds them up, is left out.
+pub fn write(out: impl Write, tree: &DirEntry, multiple_roots: bool, format: Format, mode: SizeMode, files_only: bool) -> io::Result<()> {
+    let mut table = Table { out: io::BufWriter::new(out), format, mode, files_only };
+    table.row(&HEADER)?;
+    if multiple_roots {
+        for root in &tree.children {
+            table.entry(root, &mut root.name.clone())?;
+        }
+    } else {
+        table.entry(tree, &mut String::new())?;
+    }
+    table.out.flush()
+}
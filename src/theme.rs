@@ -0,0 +1,146 @@
+// Colors of the TUI (--theme), so that one switch restyles every row, border and popup.
+//
+// "dark" is the original scheme, meant for dark backgrounds; "light" swaps the pale
+// colors for ones that read on a light background; "mono" uses no colors at all, only
+// bold and reverse video. Without --theme, "mono" is picked when NO_COLOR is set
+// (https://no-color.org) or TERM says the terminal has no colors, "dark" otherwise.
+
+use ratatui::style::{Color, Modifier, Style};
+
+#[derive(Debug)]
+pub struct Theme {
+    pub name: &'static str,
+    // Rows of the tree by size: above --color-threshold-crit (50 MiB), above
+    // --color-threshold-warn (10 MiB), and the rest
+    pub large: Style,
+    pub medium: Style,
+    pub small: Style,
+    // Added to the style of a directory's row, so that it stands out from the files
+    pub directory: Style,
+    pub symlink: Style,
+    // Devices, FIFOs and sockets
+    pub special: Style,
+    // Entries left out of the scan (other filesystems, caches, pruned rows)
+    pub skipped: Style,
+    pub unreadable: Style,
+    // Rows of the age view, newest first, one per band of main::AGE_BANDS
+    pub ages: [Style; 5],
+    // Rows of the extension, per-user and duplicate views, and the header of each group of
+    // duplicates
+    pub rows: Style,
+    pub group: Style,
+    pub selection: Style,
+    // Entries marked to be deleted together ('M')
+    pub marked: Style,
+    pub border: Style,
+    // The directories above the tree, the one listed in bold
+    pub breadcrumb: Style,
+    // The totals on the bottom line
+    pub footer: Style,
+    // The list of broken symlinks, the deletion dialog, and the search and status lines
+    pub popup: Style,
+    pub danger: Style,
+    pub prompt: Style,
+}
+
+pub const DARK: Theme = Theme {
+    name: "dark",
+    large: Style::new().fg(Color::Red),
+    medium: Style::new().fg(Color::LightYellow),
+    small: Style::new().fg(Color::Green),
+    directory: Style::new().add_modifier(Modifier::BOLD),
+    symlink: Style::new().fg(Color::Cyan),
+    special: Style::new().fg(Color::Blue),
+    skipped: Style::new().fg(Color::DarkGray),
+    unreadable: Style::new().fg(Color::Magenta),
+    ages: [
+        Style::new().fg(Color::LightRed),
+        Style::new().fg(Color::LightYellow),
+        Style::new().fg(Color::Green),
+        Style::new().fg(Color::Gray),
+        Style::new().fg(Color::DarkGray),
+    ],
+    rows: Style::new().fg(Color::Green),
+    group: Style::new().fg(Color::Yellow),
+    selection: Style::new().add_modifier(Modifier::REVERSED),
+    marked: Style::new().fg(Color::LightMagenta).add_modifier(Modifier::BOLD),
+    border: Style::new(),
+    breadcrumb: Style::new().fg(Color::LightBlue),
+    footer: Style::new().fg(Color::Gray),
+    popup: Style::new().fg(Color::Cyan),
+    danger: Style::new().fg(Color::Red),
+    prompt: Style::new().fg(Color::Yellow),
+};
+
+pub const LIGHT: Theme = Theme {
+    name: "light",
+    large: Style::new().fg(Color::Red),
+    medium: Style::new().fg(Color::Magenta),
+    small: Style::new(),
+    directory: Style::new().add_modifier(Modifier::BOLD),
+    symlink: Style::new().fg(Color::Blue),
+    special: Style::new().fg(Color::Cyan),
+    skipped: Style::new().fg(Color::DarkGray),
+    unreadable: Style::new().fg(Color::Magenta).add_modifier(Modifier::BOLD),
+    ages: [
+        Style::new().fg(Color::Red),
+        Style::new().fg(Color::Magenta),
+        Style::new().fg(Color::Blue),
+        Style::new(),
+        Style::new().fg(Color::DarkGray),
+    ],
+    rows: Style::new(),
+    group: Style::new().fg(Color::Blue),
+    selection: Style::new().add_modifier(Modifier::REVERSED),
+    marked: Style::new().fg(Color::Magenta).add_modifier(Modifier::BOLD),
+    border: Style::new(),
+    breadcrumb: Style::new().fg(Color::Blue),
+    footer: Style::new().fg(Color::DarkGray),
+    popup: Style::new().fg(Color::Blue),
+    danger: Style::new().fg(Color::Red),
+    prompt: Style::new().fg(Color::Blue),
+};
+
+pub const MONO: Theme = Theme {
+    name: "mono",
+    large: Style::new().add_modifier(Modifier::BOLD),
+    medium: Style::new(),
+    small: Style::new(),
+    directory: Style::new().add_modifier(Modifier::BOLD),
+    symlink: Style::new(),
+    special: Style::new(),
+    skipped: Style::new(),
+    unreadable: Style::new().add_modifier(Modifier::BOLD),
+    ages: [
+        Style::new().add_modifier(Modifier::BOLD),
+        Style::new(),
+        Style::new(),
+        Style::new(),
+        Style::new(),
+    ],
+    rows: Style::new(),
+    group: Style::new().add_modifier(Modifier::BOLD),
+    selection: Style::new().add_modifier(Modifier::REVERSED),
+    marked: Style::new().add_modifier(Modifier::BOLD).add_modifier(Modifier::UNDERLINED),
+    border: Style::new(),
+    breadcrumb: Style::new(),
+    footer: Style::new(),
+    popup: Style::new(),
+    danger: Style::new().add_modifier(Modifier::BOLD),
+    prompt: Style::new().add_modifier(Modifier::BOLD),
+};
+
+const THEMES: [&Theme; 3] = [&DARK, &LIGHT, &MONO];
+
+pub fn parse(name: &str) -> Result<&'static Theme, String> {
+    THEMES.into_iter()
+        .find(|theme| theme.name == name)
+        .ok_or_else(|| format!("expected dark, light or mono, got '{}'", name))
+}
+
+// The theme to use without --theme.
+pub fn detect() -> &'static Theme {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let dumb = std::env::var_os("TERM").is_some_and(|term| term == "dumb");
+    if no_color || dumb { &MONO } else { &DARK }
+}
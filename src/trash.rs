@@ -0,0 +1,196 @@
+// Moving entries deleted from the TUI to the trash, following the freedesktop.org trash
+// specification, so they can be restored from a file manager.
+//
+// An entry is renamed into the `files` directory of a trash and described by a
+// `.trashinfo` file of the same name in its `info` directory. Renaming only works within
+// a filesystem: entries on the filesystem of the home directory go to the home trash
+// ($XDG_DATA_HOME/Trash), others to the trash at the top of their own filesystem
+// ($topdir/.Trash/$uid if an administrator set up $topdir/.Trash, else $topdir/.Trash-$uid).
+// Copying whole directory trees across filesystems is not attempted; where no trash can
+// be used the error says so, and --delete-permanently deletes instead.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+#[cfg(unix)]
+use chrono::Local;
+
+// Moves `path` to a trash on its filesystem, returning the trash directory used.
+#[cfg(unix)]
+pub fn move_to_trash(path: &Path) -> io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let device = fs::symlink_metadata(&path)?.dev();
+    let home_trash = home_trash()?;
+    if existing_ancestor(&home_trash).map(|ancestor| ancestor.dev()) == Some(device) {
+        move_into(&home_trash, &path)?;
+        return Ok(home_trash);
+    }
+
+    let top = top_directory(&path, device);
+    let trash = top_trash(&top).map_err(|e| io::Error::new(
+        e.kind(),
+        format!("no trash can be used on the filesystem mounted at {} ({}); --delete-permanently deletes instead", top.display(), e),
+    ))?;
+    move_into(&trash, &path)?;
+    Ok(trash)
+}
+
+#[cfg(not(unix))]
+pub fn move_to_trash(_path: &Path) -> io::Result<PathBuf> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the trash is only supported on Unix; --delete-permanently deletes instead",
+    ))
+}
+
+#[cfg(unix)]
+fn home_trash() -> io::Result<PathBuf> {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "neither XDG_DATA_HOME nor HOME is set"))?;
+    Ok(data.join("Trash"))
+}
+
+// Metadata of `path` or, while it does not exist yet, of the closest directory above it.
+#[cfg(unix)]
+fn existing_ancestor(path: &Path) -> Option<fs::Metadata> {
+    path.ancestors().find_map(|ancestor| fs::metadata(ancestor).ok())
+}
+
+// The mount point of the filesystem `path` is on: the highest directory above it still
+// on `device`.
+#[cfg(unix)]
+fn top_directory(path: &Path, device: u64) -> PathBuf {
+    let mut top = path.parent().unwrap_or(path);
+    while let Some(parent) = top.parent() {
+        if !fs::metadata(parent).is_ok_and(|metadata| metadata.dev() == device) {
+            break;
+        }
+        top = parent;
+    }
+    top.to_path_buf()
+}
+
+// The trash of the filesystem mounted at `top`, created if needed. A shared $top/.Trash
+// only counts if it is a real directory with the sticky bit set, so that users cannot
+// tamper with each other's trash.
+#[cfg(unix)]
+fn top_trash(top: &Path) -> io::Result<PathBuf> {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    let shared = top.join(".Trash");
+    if let Ok(metadata) = fs::symlink_metadata(&shared)
+        && metadata.is_dir()
+        && metadata.mode() & 0o1000 != 0
+    {
+        let trash = shared.join(uid.to_string());
+        if create_private_dir(&trash).is_ok() {
+            return Ok(trash);
+        }
+    }
+    let trash = top.join(format!(".Trash-{}", uid));
+    create_private_dir(&trash)?;
+    Ok(trash)
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(path)
+}
+
+// Renames `path` into `trash`, after claiming a name that is free in both of its
+// directories by creating the info file.
+#[cfg(unix)]
+fn move_into(trash: &Path, path: &Path) -> io::Result<()> {
+    let files = trash.join("files");
+    let info = trash.join("info");
+    create_private_dir(&files)?;
+    create_private_dir(&info)?;
+
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "nothing to move to the trash"))?;
+    let mut counter = 1;
+    loop {
+        let mut trashed = name.to_os_string();
+        if counter > 1 {
+            trashed.push(format!(".{}", counter));
+        }
+        counter += 1;
+        let target = files.join(&trashed);
+        if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        }
+        trashed.push(".trashinfo");
+        let info_path = info.join(&trashed);
+        let mut info_file = match fs::OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        let written = write!(
+            info_file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(path),
+            Local::now().format("%Y-%m-%dT%H:%M:%S"),
+        );
+        let moved = written.and_then(|()| fs::rename(path, &target));
+        if moved.is_err() {
+            let _ = fs::remove_file(&info_path);
+        }
+        return moved;
+    }
+}
+
+// The path as the Path key of a .trashinfo file wants it: bytes other than unreserved
+// characters and '/' are written as %XX.
+#[cfg(unix)]
+fn percent_encode(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_taken_in_the_trash_get_a_counter() {
+        let dir = std::env::temp_dir().join(format!("rdu-trash-{}", std::process::id()));
+        let trash = dir.join("Trash");
+        for parent in ["a", "b", "c"] {
+            fs::create_dir_all(dir.join(parent)).unwrap();
+            fs::write(dir.join(parent).join("notes.txt"), parent).unwrap();
+            move_into(&trash, &dir.join(parent).join("notes.txt")).unwrap();
+        }
+        for (name, contents) in [("notes.txt", "a"), ("notes.txt.2", "b"), ("notes.txt.3", "c")] {
+            assert_eq!(fs::read_to_string(trash.join("files").join(name)).unwrap(), contents);
+            let info = fs::read_to_string(trash.join("info").join(format!("{}.trashinfo", name))).unwrap();
+            let original = percent_encode(&dir.join(contents).join("notes.txt"));
+            assert!(info.starts_with(&format!("[Trash Info]\nPath={}\nDeletionDate=", original)), "{}", info);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn percent_encode_keeps_unreserved_characters_and_slashes() {
+        assert_eq!(percent_encode(Path::new("/home/me/a-b_c.d~")), "/home/me/a-b_c.d~");
+        assert_eq!(percent_encode(Path::new("/tmp/two words/100%/ü")), "/tmp/two%20words/100%25/%C3%BC");
+    }
+}
@@ -0,0 +1,139 @@
+// Layout of the treemap ('t'): the entries of the directory listed as rectangles whose
+// areas are in proportion to their sizes, like WinDirStat and QDirStat draw them.
+//
+// The squarified algorithm (Bruls, Huizing and van Wijk) fills the area one strip at a
+// time along its shorter side, adding entries to a strip for as long as that brings its
+// rectangles closer to squares, which keeps them easy to compare. The layout is worked
+// out in fractions of a cell and rounded to whole cells from the edges rather than the
+// widths, so the rectangles tile the area without gaps or overlaps; entries too small to
+// get a cell of their own come out empty.
+//
+// Terminal cells are about twice as tall as they are wide, so the layout counts each row
+// as two units of height, for the rectangles to look square rather than just count as
+// square.
+
+use ratatui::layout::Rect;
+
+// Height of a terminal cell in units of its width
+const CELL_ASPECT: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+// A rectangle of `area` for each of `sizes`, which go from largest to smallest.
+pub fn layout(sizes: &[u64], area: Rect) -> Vec<Rect> {
+    let total: u64 = sizes.iter().sum();
+    if total == 0 || area.width == 0 || area.height == 0 {
+        return vec![Rect::default(); sizes.len()];
+    }
+    let height = area.height as f64 * CELL_ASPECT;
+    // Sizes in units of the area
+    let scale = area.width as f64 * height / total as f64;
+    let areas: Vec<f64> = sizes.iter().map(|&size| size as f64 * scale).collect();
+
+    let mut rects = Vec::with_capacity(sizes.len());
+    let mut free = Frac { x: area.x as f64, y: area.y as f64 * CELL_ASPECT, width: area.width as f64, height };
+    let mut start = 0;
+    while start < areas.len() {
+        let side = free.width.min(free.height);
+        // Grow the strip while that makes its worst rectangle less elongated
+        let mut end = start + 1;
+        while end < areas.len() && worst(&areas[start..=end], side) <= worst(&areas[start..end], side) {
+            end += 1;
+        }
+        free = place_strip(&areas[start..end], free, &mut rects);
+        start = end;
+    }
+    rects.into_iter().map(|rect| rect.round()).collect()
+}
+
+// The cell next to `cells[from]` in `direction`: of those beyond its edge, the nearest,
+// and of those the one most in line with it.
+pub fn neighbour(cells: &[Rect], from: usize, direction: Direction) -> Option<usize> {
+    let current = *cells.get(from)?;
+    // Centers are doubled so they stay whole numbers
+    let center_x = |rect: &Rect| 2 * rect.x as i32 + rect.width as i32;
+    let center_y = |rect: &Rect| 2 * rect.y as i32 + rect.height as i32;
+    cells.iter()
+        .enumerate()
+        .filter_map(|(index, cell)| {
+            let (gap, offset) = match direction {
+                Direction::Left => (current.x as i32 - cell.right() as i32, center_y(cell) - center_y(&current)),
+                Direction::Right => (cell.x as i32 - current.right() as i32, center_y(cell) - center_y(&current)),
+                Direction::Up => (current.y as i32 - cell.bottom() as i32, center_x(cell) - center_x(&current)),
+                Direction::Down => (cell.y as i32 - current.bottom() as i32, center_x(cell) - center_x(&current)),
+            };
+            (index != from && gap >= 0).then_some((index, (gap, offset.abs())))
+        })
+        .min_by_key(|&(_, distance)| distance)
+        .map(|(index, _)| index)
+}
+
+// A rectangle in fractions of a cell's width, rows being CELL_ASPECT high.
+#[derive(Clone, Copy)]
+struct Frac {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Frac {
+    fn round(self) -> Rect {
+        let (left, top) = (self.x.round(), (self.y / CELL_ASPECT).round());
+        let (right, bottom) = ((self.x + self.width).round(), ((self.y + self.height) / CELL_ASPECT).round());
+        Rect {
+            x: left as u16,
+            y: top as u16,
+            width: (right - left).max(0.0) as u16,
+            height: (bottom - top).max(0.0) as u16,
+        }
+    }
+}
+
+// How far from a square the most elongated rectangle of a strip of `areas` along a side
+// `side` long is, as the ratio of its longer side to its shorter one.
+fn worst(areas: &[f64], side: f64) -> f64 {
+    let sum: f64 = areas.iter().sum();
+    if sum <= 0.0 || side <= 0.0 {
+        return f64::INFINITY;
+    }
+    let thickness = sum / side;
+    areas.iter()
+        .map(|&area| {
+            let length = area / thickness;
+            if length <= 0.0 { f64::INFINITY } else { (length / thickness).max(thickness / length) }
+        })
+        .fold(0.0, f64::max)
+}
+
+// Lays a strip of `areas` along the shorter side of `free`, returning what is left of it.
+fn place_strip(areas: &[f64], free: Frac, rects: &mut Vec<Frac>) -> Frac {
+    let sum: f64 = areas.iter().sum();
+    if free.width >= free.height {
+        // A column on the left
+        let width = if free.height > 0.0 { (sum / free.height).min(free.width) } else { 0.0 };
+        let mut y = free.y;
+        for &area in areas {
+            let height = if width > 0.0 { area / width } else { 0.0 };
+            rects.push(Frac { x: free.x, y, width, height });
+            y += height;
+        }
+        Frac { x: free.x + width, width: free.width - width, ..free }
+    } else {
+        // A row at the top
+        let height = if free.width > 0.0 { (sum / free.width).min(free.height) } else { 0.0 };
+        let mut x = free.x;
+        for &area in areas {
+            let width = if height > 0.0 { area / height } else { 0.0 };
+            rects.push(Frac { x, y: free.y, width, height });
+            x += width;
+        }
+        Frac { y: free.y + height, height: free.height - height, ..free }
+    }
+}